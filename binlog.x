@@ -0,0 +1,9 @@
+/* Interned format strings for the binary logger, see src/binlog.rs.
+   Kept in the ELF for the host decoder, but never loaded. */
+SECTIONS
+{
+  .binlog 0 (INFO) :
+  {
+    KEEP(*(.binlog .binlog.*));
+  }
+}
@@ -8,12 +8,31 @@ fn main() {
     let dest_path = Path::new(&out_dir);
     let mut f = File::create(&dest_path.join("memory.x")).expect("Could not create file");
 
-    f.write_all(include_bytes!("memory.x"))
+    // The RAM length in memory.x is the smallest board's; use the DRAM
+    // size of the selected one (see `src/board.rs`).
+    let dram = if env::var_os("CARGO_FEATURE_NEZHA").is_some() {
+        "1024M"
+    } else {
+        "512M"
+    };
+    let memory_x = include_str!("memory.x").replace("LENGTH = 512M", &format!("LENGTH = {}", dram));
+    f.write_all(memory_x.as_bytes())
+        .expect("Could not write file");
+
+    let mut f = File::create(&dest_path.join("binlog.x")).expect("Could not create file");
+
+    f.write_all(include_bytes!("binlog.x"))
         .expect("Could not write file");
 
     println!("cargo:rustc-link-search={}", dest_path.display());
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=binlog.x");
     println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rustc-link-arg=-Tmemory.x");
-    println!("cargo:rustc-link-arg=-Tlink.x");
+
+    // Host builds, i.e. `cargo test`, link with the host's defaults.
+    if env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("riscv64") {
+        println!("cargo:rustc-link-arg=-Tmemory.x");
+        println!("cargo:rustc-link-arg=-Tlink.x");
+        println!("cargo:rustc-link-arg=-Tbinlog.x");
+    }
 }
@@ -0,0 +1,254 @@
+//! Host side of the link
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    encode_frame, encode_message, Channel, Decoder, LogRecord, Request, RequestBody, Response,
+    ResponseBody, RpcError, MAX_ENCODED, MAX_MEM_CHUNK,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The board sent something that doesn't fit the protocol
+    Protocol(crate::Error),
+    /// The board couldn't handle the request
+    Remote(RpcError),
+    /// The board answered with the wrong kind of response
+    UnexpectedResponse,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Protocol(e) => write!(f, "protocol error: {}", e),
+            Error::Remote(e) => write!(f, "board error: {}", e),
+            Error::UnexpectedResponse => f.write_str("unexpected response"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<crate::Error> for Error {
+    fn from(e: crate::Error) -> Self {
+        Error::Protocol(e)
+    }
+}
+
+/// An owned [`LogRecord`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log {
+    pub level: u8,
+    pub timestamp_us: u64,
+    pub target: String,
+    pub message: String,
+}
+
+impl fmt::Display for Log {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            1 => "ERROR",
+            2 => "WARN",
+            3 => "INFO",
+            4 => "DEBUG",
+            _ => "TRACE",
+        };
+        write!(
+            f,
+            "[{:>5}.{:06}] {:<5} {}: {}",
+            self.timestamp_us / 1_000_000,
+            self.timestamp_us % 1_000_000,
+            level,
+            self.target,
+            self.message
+        )
+    }
+}
+
+/// Something received from the board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Log(Log),
+    Data(Vec<u8>),
+}
+
+/// Client for the board's side of the link
+///
+/// Requests block until the matching response arrives; log records and
+/// data received meanwhile are queued for [`Client::next_event`]. Use a
+/// read timeout on `io` (e.g. `serialport`'s) to avoid waiting forever on
+/// an unresponsive board.
+pub struct Client<T> {
+    io: T,
+    decoder: Box<Decoder>,
+    next_id: u16,
+    events: VecDeque<Event>,
+    /// Frames that failed to decode, e.g. text sharing the line
+    pub errors: usize,
+}
+
+impl<T: Read + Write> Client<T> {
+    pub fn new(io: T) -> Self {
+        Self {
+            io,
+            decoder: Box::default(),
+            next_id: 0,
+            events: VecDeque::new(),
+            errors: 0,
+        }
+    }
+
+    /// Release the underlying stream
+    pub fn free(self) -> T {
+        self.io
+    }
+
+    /// Round trip a ping, returning the board's uptime in microseconds
+    pub fn ping(&mut self) -> Result<u64, Error> {
+        self.request(RequestBody::Ping, |body| match body {
+            ResponseBody::Pong { uptime_us } => Some(uptime_us),
+            _ => None,
+        })
+    }
+
+    /// Read `len` bytes of the board's memory at `addr`
+    pub fn read_mem(&mut self, addr: u64, len: usize) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(len);
+        while out.len() < len {
+            let chunk = (len - out.len()).min(MAX_MEM_CHUNK);
+            let body = RequestBody::ReadMem {
+                addr: addr + out.len() as u64,
+                len: chunk as u16,
+            };
+            let data = self.request(body, |body| match body {
+                ResponseBody::Memory(data) if data.len() == chunk => Some(data.to_vec()),
+                _ => None,
+            })?;
+            out.extend_from_slice(&data);
+        }
+        Ok(out)
+    }
+
+    /// Write `data` to the board's memory at `addr`
+    pub fn write_mem(&mut self, addr: u64, data: &[u8]) -> Result<(), Error> {
+        for (i, chunk) in data.chunks(MAX_MEM_CHUNK).enumerate() {
+            let body = RequestBody::WriteMem {
+                addr: addr + (i * MAX_MEM_CHUNK) as u64,
+                data: chunk,
+            };
+            self.request(body, |body| match body {
+                ResponseBody::Done => Some(()),
+                _ => None,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Call an application-defined method
+    pub fn call(&mut self, method: u16, args: &[u8]) -> Result<Vec<u8>, Error> {
+        self.request(RequestBody::Call { method, args }, |body| match body {
+            ResponseBody::Return(data) => Some(data.to_vec()),
+            _ => None,
+        })
+    }
+
+    /// Send raw bytes on the data channel
+    pub fn send_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut out = [0u8; MAX_ENCODED];
+        let n = encode_frame(Channel::Data, data, &mut out)?;
+        self.io.write_all(&out[..n])?;
+        self.io.flush()?;
+        Ok(())
+    }
+
+    /// Wait for the next log record or data frame
+    pub fn next_event(&mut self) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            self.receive(|_| None::<()>)?;
+        }
+    }
+
+    fn request<R>(
+        &mut self,
+        body: RequestBody<'_>,
+        mut accept: impl FnMut(ResponseBody<'_>) -> Option<R>,
+    ) -> Result<R, Error> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let mut out = [0u8; MAX_ENCODED];
+        let n = encode_message(Channel::Rpc, &Request { id, body }, &mut out)?;
+        self.io.write_all(&out[..n])?;
+        self.io.flush()?;
+
+        loop {
+            let res = self.receive(|response| {
+                if response.id != id {
+                    // A late answer to a request that was given up on
+                    return None;
+                }
+                Some(match response.body {
+                    ResponseBody::Error(e) => Err(Error::Remote(e)),
+                    body => accept(body).ok_or(Error::UnexpectedResponse),
+                })
+            })?;
+            if let Some(res) = res {
+                return res;
+            }
+        }
+    }
+
+    /// Read until one frame arrives, queueing events and passing responses
+    /// to `on_response`
+    fn receive<R>(
+        &mut self,
+        mut on_response: impl FnMut(Response<'_>) -> Option<R>,
+    ) -> Result<Option<R>, Error> {
+        let mut byte = [0u8];
+        loop {
+            if self.io.read(&mut byte)? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            let frame = match self.decoder.push(byte[0]) {
+                None => continue,
+                Some(Ok(frame)) => frame,
+                Some(Err(_)) => {
+                    self.errors += 1;
+                    continue;
+                }
+            };
+            match frame.channel {
+                Channel::Log => {
+                    let record: LogRecord = frame.message()?;
+                    self.events.push_back(Event::Log(Log {
+                        level: record.level,
+                        timestamp_us: record.timestamp_us,
+                        target: record.target.into(),
+                        message: record.message.into(),
+                    }));
+                    return Ok(None);
+                }
+                Channel::Data => {
+                    self.events.push_back(Event::Data(frame.payload.to_vec()));
+                    return Ok(None);
+                }
+                Channel::Rpc => return Ok(on_response(frame.message()?)),
+            }
+        }
+    }
+}
@@ -0,0 +1,505 @@
+//! Framed link protocol between d1-playground and a host
+//!
+//! Every frame is COBS encoded with a zero byte on either side, so frames
+//! can share a UART with plain text and a receiver can resynchronise after
+//! line noise. Before COBS encoding a frame is:
+//!
+//! | field   | encoding                                        |
+//! |---------|-------------------------------------------------|
+//! | channel | `u8`, see [`Channel`]                           |
+//! | payload | up to [`MAX_PAYLOAD`] bytes                     |
+//! | crc     | CRC-16/XMODEM of channel and payload, big-endian |
+//!
+//! On the [`Log`](Channel::Log) and [`Rpc`](Channel::Rpc) channels the
+//! payload is a `postcard`-serialized [`LogRecord`], [`Request`] or
+//! [`Response`]. [`Data`](Channel::Data) payloads are raw bytes for the
+//! application to interpret.
+//!
+//! With the `std` feature, [`host::Client`] speaks the protocol over any
+//! `Read + Write`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
+pub mod host;
+
+/// Largest payload in a frame
+pub const MAX_PAYLOAD: usize = 1024;
+
+/// Largest frame, before COBS encoding
+pub const MAX_FRAME: usize = MAX_PAYLOAD + 3;
+
+/// Largest frame on the wire: COBS adds one byte per 254, plus delimiters
+pub const MAX_ENCODED: usize = MAX_FRAME + MAX_FRAME / 254 + 3;
+
+/// Most bytes a single `ReadMem`/`WriteMem` request can move, leaving room
+/// for the rest of the message in [`MAX_PAYLOAD`]
+pub const MAX_MEM_CHUNK: usize = 512;
+
+/// Logical channel a frame belongs to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Channel {
+    /// [`LogRecord`]s from the board
+    Log = 1,
+    /// [`Request`]s from the host and [`Response`]s from the board
+    Rpc = 2,
+    /// Raw application data, in either direction
+    Data = 3,
+}
+
+impl TryFrom<u8> for Channel {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            1 => Ok(Channel::Log),
+            2 => Ok(Channel::Rpc),
+            3 => Ok(Channel::Data),
+            _ => Err(Error::Channel),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The payload is larger than [`MAX_PAYLOAD`], or the output buffer is
+    /// too small
+    TooLong,
+    /// A frame was longer than the decoder's buffer
+    Overflow,
+    /// Invalid COBS encoding
+    Cobs,
+    /// The frame is too short or its CRC doesn't match
+    Crc,
+    /// Unknown channel number
+    Channel,
+    /// The payload couldn't be (de)serialized
+    Encoding,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Error::TooLong => "frame too long",
+            Error::Overflow => "receive buffer overflow",
+            Error::Cobs => "invalid COBS encoding",
+            Error::Crc => "CRC mismatch",
+            Error::Channel => "unknown channel",
+            Error::Encoding => "invalid message encoding",
+        })
+    }
+}
+
+/// A log record sent by the board
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord<'a> {
+    /// 1 (error) to 5 (trace), as `log::Level`
+    pub level: u8,
+    pub timestamp_us: u64,
+    pub target: &'a str,
+    pub message: &'a str,
+}
+
+/// A request from the host; the board answers with a [`Response`] carrying
+/// the same `id`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request<'a> {
+    pub id: u16,
+    #[serde(borrow)]
+    pub body: RequestBody<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RequestBody<'a> {
+    /// Check the link is up, answered by [`ResponseBody::Pong`]
+    Ping,
+    /// Read up to [`MAX_MEM_CHUNK`] bytes, answered by
+    /// [`ResponseBody::Memory`]
+    ReadMem { addr: u64, len: u16 },
+    /// Write up to [`MAX_MEM_CHUNK`] bytes, answered by
+    /// [`ResponseBody::Done`]
+    WriteMem { addr: u64, data: &'a [u8] },
+    /// Call an application-defined method, answered by
+    /// [`ResponseBody::Return`]
+    Call { method: u16, args: &'a [u8] },
+}
+
+/// The board's answer to a [`Request`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response<'a> {
+    pub id: u16,
+    #[serde(borrow)]
+    pub body: ResponseBody<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResponseBody<'a> {
+    Pong { uptime_us: u64 },
+    Memory(&'a [u8]),
+    Done,
+    Return(&'a [u8]),
+    Error(RpcError),
+}
+
+/// Why the board couldn't handle a [`Request`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RpcError {
+    /// The request couldn't be decoded
+    BadRequest,
+    /// The access is longer than [`MAX_MEM_CHUNK`]
+    TooLong,
+    /// No method with that number
+    UnknownMethod,
+    /// The method ran but failed
+    Failed,
+}
+
+impl core::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            RpcError::BadRequest => "bad request",
+            RpcError::TooLong => "access too long",
+            RpcError::UnknownMethod => "unknown method",
+            RpcError::Failed => "method failed",
+        })
+    }
+}
+
+/// CRC-16/XMODEM
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Encode a frame carrying `payload` into `out`, returning its length
+///
+/// `out` should be [`MAX_ENCODED`] bytes to fit any frame.
+pub fn encode_frame(channel: Channel, payload: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(Error::TooLong);
+    }
+    let mut frame = [0u8; MAX_FRAME];
+    let len = payload.len() + 3;
+    frame[0] = channel as u8;
+    frame[1..len - 2].copy_from_slice(payload);
+    let crc = crc16(&frame[..len - 2]);
+    frame[len - 2..len].copy_from_slice(&crc.to_be_bytes());
+
+    if out.len() < len + len / 254 + 3 {
+        return Err(Error::TooLong);
+    }
+    out[0] = 0;
+    let n = cobs::encode(&frame[..len], &mut out[1..]);
+    out[n + 1] = 0;
+    Ok(n + 2)
+}
+
+/// Serialize `msg` and encode it as a frame into `out`, returning its length
+pub fn encode_message<T: Serialize>(
+    channel: Channel,
+    msg: &T,
+    out: &mut [u8],
+) -> Result<usize, Error> {
+    let mut payload = [0u8; MAX_PAYLOAD];
+    let payload = postcard::to_slice(msg, &mut payload).map_err(|_| Error::TooLong)?;
+    encode_frame(channel, payload, out)
+}
+
+/// A received, checked frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub channel: Channel,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Deserialize the payload
+    pub fn message<T: Deserialize<'a>>(&self) -> Result<T, Error> {
+        postcard::from_bytes(self.payload).map_err(|_| Error::Encoding)
+    }
+}
+
+/// Splits a byte stream into frames
+///
+/// Bytes outside frames, such as `println!` output sharing the line, show
+/// up as frames with invalid COBS or CRC and are reported as errors.
+pub struct Decoder {
+    buf: [u8; MAX_FRAME + MAX_FRAME / 254 + 1],
+    len: usize,
+    overflow: bool,
+    done: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME + MAX_FRAME / 254 + 1],
+            len: 0,
+            overflow: false,
+            done: false,
+        }
+    }
+
+    /// Feed one byte, returning a frame or error when a delimiter completes one
+    pub fn push(&mut self, byte: u8) -> Option<Result<Frame<'_>, Error>> {
+        if self.done {
+            self.len = 0;
+            self.overflow = false;
+            self.done = false;
+        }
+        if byte != 0 {
+            match self.buf.get_mut(self.len) {
+                Some(b) => {
+                    *b = byte;
+                    self.len += 1;
+                }
+                None => self.overflow = true,
+            }
+            return None;
+        }
+
+        self.done = true;
+        if self.overflow {
+            return Some(Err(Error::Overflow));
+        }
+        if self.len == 0 {
+            return None;
+        }
+        let frame = match cobs::decode_in_place(&mut self.buf[..self.len]) {
+            Ok(n) => &self.buf[..n],
+            Err(()) => return Some(Err(Error::Cobs)),
+        };
+        Some(parse_frame(frame))
+    }
+}
+
+fn parse_frame(frame: &[u8]) -> Result<Frame<'_>, Error> {
+    if frame.len() < 3 {
+        return Err(Error::Crc);
+    }
+    let (body, crc) = frame.split_at(frame.len() - 2);
+    if crc16(body) != u16::from_be_bytes([crc[0], crc[1]]) {
+        return Err(Error::Crc);
+    }
+    Ok(Frame {
+        channel: Channel::try_from(body[0])?,
+        payload: &body[1..],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `wire` through a decoder, calling `f` with every frame or error
+    fn decode(wire: &[u8], mut f: impl FnMut(Result<Frame<'_>, Error>)) {
+        let mut decoder = Decoder::new();
+        for &b in wire {
+            if let Some(res) = decoder.push(b) {
+                f(res);
+            }
+        }
+    }
+
+    /// Send `msg` over a channel, returning the channel and payload the
+    /// decoder saw, with the payload copied into `payload`
+    fn transfer(channel: Channel, msg: &impl Serialize, payload: &mut [u8]) -> (Channel, usize) {
+        let mut wire = [0u8; MAX_ENCODED];
+        let n = encode_message(channel, msg, &mut wire).unwrap();
+        let mut got = None;
+        decode(&wire[..n], |res| {
+            let frame = res.unwrap();
+            payload[..frame.payload.len()].copy_from_slice(frame.payload);
+            assert!(got.is_none());
+            got = Some((frame.channel, frame.payload.len()));
+        });
+        got.unwrap()
+    }
+
+    macro_rules! assert_round_trip {
+        ($channel:expr, $ty:ty, $msg:expr) => {{
+            let msg = $msg;
+            let mut payload = [0u8; MAX_PAYLOAD];
+            let (channel, n) = transfer($channel, &msg, &mut payload);
+            assert_eq!(channel, $channel);
+            let frame = Frame {
+                channel,
+                payload: &payload[..n],
+            };
+            assert_eq!(frame.message::<$ty>(), Ok(msg));
+        }};
+    }
+
+    #[test]
+    fn log_record_round_trip() {
+        assert_round_trip!(
+            Channel::Log,
+            LogRecord,
+            LogRecord {
+                level: 3,
+                timestamp_us: 1_500_000,
+                target: "d1_playground::ccu",
+                message: "PLL_CPU locked",
+            }
+        );
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let data = [0xAA; MAX_MEM_CHUNK];
+        let bodies = [
+            RequestBody::Ping,
+            RequestBody::ReadMem {
+                addr: 0x4000_0000,
+                len: 64,
+            },
+            RequestBody::WriteMem {
+                addr: 0x4000_0000,
+                data: &data,
+            },
+            RequestBody::Call {
+                method: 7,
+                args: &[1, 2, 3],
+            },
+        ];
+        for (id, body) in bodies.into_iter().enumerate() {
+            assert_round_trip!(
+                Channel::Rpc,
+                Request,
+                Request {
+                    id: id as u16,
+                    body,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let data = [0x55; MAX_MEM_CHUNK];
+        let bodies = [
+            ResponseBody::Pong { uptime_us: 42 },
+            ResponseBody::Memory(&data),
+            ResponseBody::Done,
+            ResponseBody::Return(&[]),
+            ResponseBody::Error(RpcError::UnknownMethod),
+        ];
+        for (id, body) in bodies.into_iter().enumerate() {
+            assert_round_trip!(
+                Channel::Rpc,
+                Response,
+                Response {
+                    id: id as u16,
+                    body,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted_crc() {
+        let mut wire = [0u8; MAX_ENCODED];
+        let n = encode_frame(Channel::Data, b"hello", &mut wire).unwrap();
+        // wire[0] is the delimiter and wire[1] the COBS code, so wire[3] is
+        // the first payload byte.
+        wire[3] ^= 0x01;
+        let mut errors = 0;
+        decode(&wire[..n], |res| {
+            assert_eq!(res, Err(Error::Crc));
+            errors += 1;
+        });
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let mut wire = [0u8; MAX_ENCODED];
+        let n = encode_frame(Channel::Data, b"hello", &mut wire).unwrap();
+        for cut in 2..n - 1 {
+            let mut truncated = [0u8; MAX_ENCODED];
+            truncated[..cut].copy_from_slice(&wire[..cut]);
+            let mut errors = 0;
+            decode(&truncated[..cut + 1], |res| {
+                assert!(res.is_err(), "cut at {}: {:?}", cut, res);
+                errors += 1;
+            });
+            assert_eq!(errors, 1, "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn unknown_channel() {
+        let frame = [9, b'x', 0, 0];
+        let crc = crc16(&frame[..2]).to_be_bytes();
+        let frame = [frame[0], frame[1], crc[0], crc[1]];
+        let mut wire = [0u8; 8];
+        let n = cobs::encode(&frame, &mut wire[1..]);
+        decode(&wire[..n + 2], |res| assert_eq!(res, Err(Error::Channel)));
+    }
+
+    #[test]
+    fn channel_demux() {
+        let mut wire = [0u8; 4 * MAX_ENCODED];
+        let mut n = 0;
+        let record = LogRecord {
+            level: 2,
+            timestamp_us: 10,
+            target: "app",
+            message: "low battery",
+        };
+        n += encode_message(Channel::Log, &record, &mut wire[n..]).unwrap();
+        // Plain text between frames shows up as one bad frame.
+        wire[n..n + 6].copy_from_slice(b"hello\n");
+        n += 6;
+        let response = Response {
+            id: 1,
+            body: ResponseBody::Done,
+        };
+        n += encode_message(Channel::Rpc, &response, &mut wire[n..]).unwrap();
+        n += encode_frame(Channel::Data, &[0, 1, 2, 0], &mut wire[n..]).unwrap();
+
+        let mut seen = 0;
+        decode(&wire[..n], |res| {
+            match seen {
+                0 => {
+                    let frame = res.unwrap();
+                    assert_eq!(frame.channel, Channel::Log);
+                    assert_eq!(frame.message::<LogRecord>(), Ok(record.clone()));
+                }
+                1 => assert!(res.is_err()),
+                2 => {
+                    let frame = res.unwrap();
+                    assert_eq!(frame.channel, Channel::Rpc);
+                    assert_eq!(frame.message::<Response>(), Ok(response.clone()));
+                }
+                3 => {
+                    let frame = res.unwrap();
+                    assert_eq!(frame.channel, Channel::Data);
+                    assert_eq!(frame.payload, &[0, 1, 2, 0]);
+                }
+                _ => panic!("unexpected {:?}", res),
+            }
+            seen += 1;
+        });
+        assert_eq!(seen, 4);
+    }
+}
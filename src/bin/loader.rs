@@ -0,0 +1,28 @@
+//! Resident loader
+//!
+//! Brings up the console and drops into the monitor with the `load`, `boot`
+//! and `go` commands, so images can be uploaded over the UART with
+//! XMODEM/YMODEM instead of FEL.
+
+#![no_std]
+#![no_main]
+
+use d1_playground::board::Board;
+use d1_playground::console::{self, Console};
+use d1_playground::monitor::Monitor;
+use d1_playground::{ccu, loader, logger, pinmux, println};
+
+#[riscv_rt::entry]
+fn main() -> ! {
+    let p = d1_pac::Peripherals::take().unwrap();
+
+    let board = Board::new(p.GPIO, p.UART0, p.LEDC);
+    console::init(board.debug_uart);
+    logger::init(log::LevelFilter::Info).unwrap();
+    loader::register_commands().unwrap();
+    pinmux::register_commands().unwrap();
+    ccu::register_commands().unwrap();
+
+    println!("d1 loader: `load` then `boot` to run an image");
+    Monitor::new(Console).run()
+}
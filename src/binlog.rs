@@ -0,0 +1,256 @@
+//! Compact binary logging
+//!
+//! [`binlog!`](crate::binlog!) works like the `log` macros, but instead of
+//! formatting on the target it sends the *address* of the format string and
+//! the raw argument values over the console UART, which is decoded on the
+//! host by `tools/binlog-decode`.
+//!
+//! Format strings are interned in the `.binlog` section, which `binlog.x`
+//! places at address 0 and marks `INFO`, so it is kept in the ELF but never
+//! loaded onto the board. Each entry is `module\0format\0`, and its offset
+//! in the section is the id that goes over the wire.
+//!
+//! Each record is sent as one COBS frame with a zero byte on either side, so
+//! it can share the UART with plain text output. Before COBS encoding a
+//! frame is:
+//!
+//! | field     | encoding                                     |
+//! |-----------|----------------------------------------------|
+//! | magic     | `0xB1`                                       |
+//! | level     | `u8`, 1 (error) to 5 (trace); bit 7 set if truncated |
+//! | id        | LEB128                                       |
+//! | timestamp | LEB128, microseconds                         |
+//! | args      | a tag byte and value for each argument, see [`Encode`] |
+
+pub use log::Level;
+
+use crate::console;
+
+/// First byte of every frame
+pub const MAGIC: u8 = 0xB1;
+
+/// Largest frame, before COBS encoding
+pub const MAX_FRAME: usize = 128;
+
+const TRUNCATED: u8 = 0x80;
+
+/// Argument type tags
+pub mod tag {
+    /// Unsigned integer, LEB128
+    pub const UNSIGNED: u8 = b'u';
+    /// Signed integer: its size in bytes, then the value zigzag and LEB128
+    /// encoded
+    pub const SIGNED: u8 = b'i';
+    /// `0` or `1`
+    pub const BOOL: u8 = b'b';
+    /// Unicode scalar value, LEB128
+    pub const CHAR: u8 = b'c';
+    /// `f32`, little-endian
+    pub const F32: u8 = b'f';
+    /// Length (LEB128) followed by UTF-8
+    pub const STR: u8 = b's';
+    /// Length (LEB128) followed by bytes
+    pub const BYTES: u8 = b'x';
+}
+
+/// Builds a frame in a fixed buffer, silently dropping whatever doesn't fit
+pub struct Encoder {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+    truncated: bool,
+}
+
+impl Encoder {
+    #[doc(hidden)]
+    pub fn new(level: Level, id: usize, timestamp: u64) -> Self {
+        let mut this = Self {
+            buf: [0; MAX_FRAME],
+            len: 0,
+            truncated: false,
+        };
+        this.byte(MAGIC);
+        this.byte(level as u8);
+        this.leb128(id as u64);
+        this.leb128(timestamp);
+        this
+    }
+
+    /// Append one raw byte
+    pub fn byte(&mut self, b: u8) {
+        if self.len < MAX_FRAME {
+            self.buf[self.len] = b;
+            self.len += 1;
+        } else {
+            self.truncated = true;
+        }
+    }
+
+    /// Append raw bytes
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.byte(b);
+        }
+    }
+
+    /// Append an unsigned LEB128 value
+    pub fn leb128(&mut self, mut value: u64) {
+        loop {
+            let b = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.byte(b);
+                return;
+            }
+            self.byte(b | 0x80);
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn send(mut self) {
+        if self.truncated {
+            self.buf[1] |= TRUNCATED;
+        }
+        // COBS adds one byte per 254, plus the delimiters.
+        let mut out = [0u8; MAX_FRAME + MAX_FRAME / 254 + 3];
+        let n = cobs::encode(&self.buf[..self.len], &mut out[1..]);
+        out[n + 1] = 0;
+        console::with(|uart| {
+            for &b in &out[..n + 2] {
+                uart.write_byte(b);
+            }
+        });
+    }
+}
+
+/// A value that can be sent as a `binlog!` argument
+pub trait Encode {
+    fn encode(&self, enc: &mut Encoder);
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    #[inline]
+    fn encode(&self, enc: &mut Encoder) {
+        (**self).encode(enc)
+    }
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),+) => {
+        $(
+            impl Encode for $t {
+                #[inline]
+                fn encode(&self, enc: &mut Encoder) {
+                    enc.byte(tag::UNSIGNED);
+                    enc.leb128(*self as u64);
+                }
+            }
+        )+
+    };
+}
+
+macro_rules! impl_signed {
+    ($($t:ty),+) => {
+        $(
+            impl Encode for $t {
+                #[inline]
+                fn encode(&self, enc: &mut Encoder) {
+                    let v = *self as i64;
+                    enc.byte(tag::SIGNED);
+                    enc.byte(core::mem::size_of::<$t>() as u8);
+                    enc.leb128(((v << 1) ^ (v >> 63)) as u64);
+                }
+            }
+        )+
+    };
+}
+
+impl_unsigned!(u8, u16, u32, u64, usize);
+impl_signed!(i8, i16, i32, i64, isize);
+
+impl Encode for bool {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.byte(tag::BOOL);
+        enc.byte(*self as u8);
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.byte(tag::CHAR);
+        enc.leb128(*self as u64);
+    }
+}
+
+impl Encode for f32 {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.byte(tag::F32);
+        enc.bytes(&self.to_le_bytes());
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.byte(tag::STR);
+        enc.leb128(self.len() as u64);
+        enc.bytes(self.as_bytes());
+    }
+}
+
+impl Encode for [u8] {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.byte(tag::BYTES);
+        enc.leb128(self.len() as u64);
+        enc.bytes(self);
+    }
+}
+
+/// Whether a record at `level` should be sent
+#[doc(hidden)]
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    level <= log::STATIC_MAX_LEVEL && level <= log::max_level()
+}
+
+/// Copy an interned string into a fixed-size array for the `.binlog` section
+#[doc(hidden)]
+pub const fn intern<const N: usize>(s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut out = [0u8; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+/// Log a record in binary form
+///
+/// ```ignore
+/// binlog!(Level::Info, "PLL_CPU at {} MHz, lock took {}us", mhz, us);
+/// ```
+///
+/// Placeholders are `{}`, `{:?}`, `{:x}`, `{:#x}`, `{:X}` and `{:b}`; they
+/// are interpreted by the host decoder, so arguments only need to implement
+/// [`Encode`](crate::binlog::Encode).
+#[macro_export]
+macro_rules! binlog {
+    ($level:expr, $fmt:literal $(, $arg:expr)* $(,)?) => {{
+        let level: $crate::binlog::Level = $level;
+        if $crate::binlog::enabled(level) {
+            const ENTRY: &str = concat!(module_path!(), "\0", $fmt, "\0");
+            #[link_section = ".binlog"]
+            #[used]
+            static ENTRY_DATA: [u8; ENTRY.len()] = $crate::binlog::intern(ENTRY);
+
+            #[allow(unused_mut)]
+            let mut enc = $crate::binlog::Encoder::new(
+                level,
+                &ENTRY_DATA as *const _ as usize,
+                $crate::logger::timestamp_us(),
+            );
+            $( $crate::binlog::Encode::encode(&$arg, &mut enc); )*
+            enc.send();
+        }
+    }};
+}
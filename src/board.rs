@@ -0,0 +1,292 @@
+//! Board support
+//!
+//! Exactly one board feature selects the pin choices and DRAM size:
+//!
+//! * `lichee-rv-dock` (default): Sipeed Lichee RV on the Dock, with the
+//!   green status LED on PC1, a WS2812 on PC0 and the RGB LCD connector
+//! * `nezha`: Allwinner Nezha, whose only LED is a WS2812 on PC0
+//! * `mangopi-mq-pro`: MangoPi MQ-Pro, with the blue status LED on PD18
+//!
+//! All three have the debug UART on PB8/PB9 and the microSD slot on
+//! PF0-PF5. `build.rs` sets the RAM length in `memory.x` to [`DRAM_SIZE`].
+//!
+//! ```ignore
+//! let board = Board::new(p.GPIO, p.UART0, p.LEDC);
+//! console::init(board.debug_uart);
+//! let mut led = board.status_led;
+//! led.on();
+//! ```
+//!
+//! Pins not listed here can still be had with [`Pin::try_steal`]. Every
+//! pin the board sets up is claimed in the [`pinmux`](crate::pinmux)
+//! registry under the name of the peripheral using it.
+
+use d1_pac::{GPIO, LEDC, UART0};
+
+use crate::gpio::{ErasedPin, Function, Input, Pin, Pins, Pull};
+use crate::ledc::Ledc;
+#[cfg(feature = "nezha")]
+use crate::ledc::Rgb;
+#[cfg(any(feature = "nezha", feature = "lichee-rv-dock"))]
+use crate::ledc::{ColorOrder, Timings};
+use crate::pinmux;
+use crate::tcon::LcdPins;
+use crate::uart::Uart;
+
+#[cfg(not(any(
+    feature = "nezha",
+    feature = "lichee-rv-dock",
+    feature = "mangopi-mq-pro"
+)))]
+compile_error!(
+    "select a board with one of the features `nezha`, `lichee-rv-dock` or `mangopi-mq-pro`"
+);
+
+#[cfg(any(
+    all(feature = "nezha", feature = "lichee-rv-dock"),
+    all(feature = "nezha", feature = "mangopi-mq-pro"),
+    all(feature = "lichee-rv-dock", feature = "mangopi-mq-pro"),
+))]
+compile_error!("board features are mutually exclusive; build with `--no-default-features`");
+
+/// Name of the selected board, for banners
+#[cfg(feature = "nezha")]
+pub const NAME: &str = "Nezha";
+#[cfg(feature = "lichee-rv-dock")]
+pub const NAME: &str = "Lichee RV Dock";
+#[cfg(feature = "mangopi-mq-pro")]
+pub const NAME: &str = "MangoPi MQ-Pro";
+
+/// Bytes of DRAM fitted, starting at 0x4000_0000
+#[cfg(feature = "nezha")]
+pub const DRAM_SIZE: usize = 1024 * 1024 * 1024;
+#[cfg(any(feature = "lichee-rv-dock", feature = "mangopi-mq-pro"))]
+pub const DRAM_SIZE: usize = 512 * 1024 * 1024;
+
+/// Baud rate the debug UART is set up with
+pub const DEBUG_BAUDRATE: u32 = 115_200;
+
+#[cfg(feature = "lichee-rv-dock")]
+type StatusLedPin = Pin<'C', 1, crate::gpio::Output>;
+#[cfg(feature = "mangopi-mq-pro")]
+type StatusLedPin = Pin<'D', 18, crate::gpio::Output>;
+
+/// The board's status LED: a plain LED where there is one, otherwise the
+/// WS2812 lit white
+pub struct StatusLed {
+    #[cfg(not(feature = "nezha"))]
+    pin: StatusLedPin,
+    #[cfg(feature = "nezha")]
+    ledc: Ledc,
+    #[cfg(feature = "nezha")]
+    lit: bool,
+}
+
+impl StatusLed {
+    pub fn on(&mut self) {
+        self.set(true);
+    }
+
+    pub fn off(&mut self) {
+        self.set(false);
+    }
+
+    pub fn toggle(&mut self) {
+        self.set(!self.is_on());
+    }
+
+    #[cfg(not(feature = "nezha"))]
+    pub fn set(&mut self, on: bool) {
+        self.pin.set_state(on);
+    }
+
+    #[cfg(not(feature = "nezha"))]
+    pub fn is_on(&self) -> bool {
+        self.pin.is_set_high()
+    }
+
+    #[cfg(feature = "nezha")]
+    pub fn set(&mut self, on: bool) {
+        let level = if on { 32 } else { 0 };
+        let _ = self.ledc.write_blocking(&[Rgb::new(level, level, level)]);
+        self.lit = on;
+    }
+
+    #[cfg(feature = "nezha")]
+    pub fn is_on(&self) -> bool {
+        self.lit
+    }
+}
+
+/// microSD slot, wired to SMHC0
+pub struct SdPins {
+    pub clk: Pin<'F', 2, Function<2>>,
+    pub cmd: Pin<'F', 3, Function<2>>,
+    pub d0: Pin<'F', 1, Function<2>>,
+    pub d1: Pin<'F', 0, Function<2>>,
+    pub d2: Pin<'F', 5, Function<2>>,
+    pub d3: Pin<'F', 4, Function<2>>,
+    /// Low while a card is inserted, on boards that wire it up
+    pub card_detect: Option<ErasedPin<Input>>,
+}
+
+/// Resources of the selected board, muxed and ready to use
+pub struct Board {
+    /// UART0 at [`DEBUG_BAUDRATE`], 8n1
+    pub debug_uart: Uart<UART0>,
+    pub status_led: StatusLed,
+    /// The WS2812 on PC0, where it isn't the status LED
+    pub rgb_led: Option<Ledc>,
+    pub sd: SdPins,
+    /// The RGB LCD connector, on boards that have one, for
+    /// [`Tcon`](crate::tcon::Tcon)
+    pub lcd: Option<LcdPins>,
+}
+
+impl Board {
+    /// Mux and set up everything; `ledc` goes unused on boards without a
+    /// WS2812
+    pub fn new(gpio: GPIO, uart0: UART0, ledc: LEDC) -> Self {
+        let pins = Pins::new(gpio);
+
+        // UART0 on PB8/PB9, with pullups so a floating RX doesn't read
+        // as a stream of breaks.
+        let mut tx = pins.pb8.into_function::<6>();
+        let mut rx = pins.pb9.into_function::<6>();
+        tx.set_pull(Pull::Up);
+        rx.set_pull(Pull::Up);
+        claim(tx.claim("uart0"));
+        claim(rx.claim("uart0"));
+        let debug_uart = Uart::new(uart0, DEBUG_BAUDRATE);
+
+        let sd = sd_pins(
+            pins.pf0, pins.pf1, pins.pf2, pins.pf3, pins.pf4, pins.pf5, pins.pf6,
+        );
+
+        #[cfg(any(feature = "nezha", feature = "lichee-rv-dock"))]
+        let ws2812 = {
+            let ledc_do = pins.pc0.into_function::<4>();
+            claim(ledc_do.claim("ledc"));
+            Ledc::new(ledc, Timings::WS2812, ColorOrder::Grb)
+        };
+        #[cfg(feature = "mangopi-mq-pro")]
+        let _ = ledc;
+
+        #[cfg(not(feature = "nezha"))]
+        let led_pin = {
+            #[cfg(feature = "lichee-rv-dock")]
+            let pin = pins.pc1.into_output();
+            #[cfg(feature = "mangopi-mq-pro")]
+            let pin = pins.pd18.into_output();
+            claim(pin.claim("status-led"));
+            pin
+        };
+
+        #[cfg(feature = "nezha")]
+        let (status_led, rgb_led) = (
+            StatusLed {
+                ledc: ws2812,
+                lit: false,
+            },
+            None,
+        );
+        #[cfg(feature = "lichee-rv-dock")]
+        let (status_led, rgb_led) = (StatusLed { pin: led_pin }, Some(ws2812));
+        #[cfg(feature = "mangopi-mq-pro")]
+        let (status_led, rgb_led) = (StatusLed { pin: led_pin }, None);
+
+        #[cfg(feature = "lichee-rv-dock")]
+        let lcd = Some(LcdPins {
+            clk: pins.pd18.into_function(),
+            de: pins.pd19.into_function(),
+            hsync: pins.pd20.into_function(),
+            vsync: pins.pd21.into_function(),
+            data: [
+                pins.pd0.into_function().erase(),
+                pins.pd1.into_function().erase(),
+                pins.pd2.into_function().erase(),
+                pins.pd3.into_function().erase(),
+                pins.pd4.into_function().erase(),
+                pins.pd5.into_function().erase(),
+                pins.pd6.into_function().erase(),
+                pins.pd7.into_function().erase(),
+                pins.pd8.into_function().erase(),
+                pins.pd9.into_function().erase(),
+                pins.pd10.into_function().erase(),
+                pins.pd11.into_function().erase(),
+                pins.pd12.into_function().erase(),
+                pins.pd13.into_function().erase(),
+                pins.pd14.into_function().erase(),
+                pins.pd15.into_function().erase(),
+                pins.pd16.into_function().erase(),
+                pins.pd17.into_function().erase(),
+            ],
+        });
+        #[cfg(not(feature = "lichee-rv-dock"))]
+        let lcd: Option<LcdPins> = None;
+        if let Some(lcd) = &lcd {
+            claim(lcd.claim("tcon-lcd0"));
+        }
+
+        Self {
+            debug_uart,
+            status_led,
+            rgb_led,
+            sd,
+            lcd,
+        }
+    }
+}
+
+/// Mux the microSD pins, with the pullups the bus needs
+fn sd_pins(
+    pf0: Pin<'F', 0>,
+    pf1: Pin<'F', 1>,
+    pf2: Pin<'F', 2>,
+    pf3: Pin<'F', 3>,
+    pf4: Pin<'F', 4>,
+    pf5: Pin<'F', 5>,
+    pf6: Pin<'F', 6>,
+) -> SdPins {
+    let mut sd = SdPins {
+        clk: pf2.into_function(),
+        cmd: pf3.into_function(),
+        d0: pf1.into_function(),
+        d1: pf0.into_function(),
+        d2: pf5.into_function(),
+        d3: pf4.into_function(),
+        card_detect: None,
+    };
+    sd.cmd.set_pull(Pull::Up);
+    sd.d0.set_pull(Pull::Up);
+    sd.d1.set_pull(Pull::Up);
+    sd.d2.set_pull(Pull::Up);
+    sd.d3.set_pull(Pull::Up);
+    claim(sd.clk.claim("smhc0"));
+    claim(sd.cmd.claim("smhc0"));
+    claim(sd.d0.claim("smhc0"));
+    claim(sd.d1.claim("smhc0"));
+    claim(sd.d2.claim("smhc0"));
+    claim(sd.d3.claim("smhc0"));
+
+    // Only the Nezha wires up card detect; elsewhere PF6 is left alone.
+    #[cfg(feature = "nezha")]
+    {
+        let mut cd = pf6.into_input();
+        cd.set_pull(Pull::Up);
+        claim(cd.claim("smhc0"));
+        sd.card_detect = Some(cd.erase());
+    }
+    #[cfg(not(feature = "nezha"))]
+    let _ = pf6;
+
+    sd
+}
+
+/// Check a board pin claim; nothing else can have claimed the pin this
+/// early, so a conflict means the board tables are wrong
+fn claim(result: Result<(), pinmux::Error>) {
+    if let Err(error) = result {
+        panic!("{}", error);
+    }
+}
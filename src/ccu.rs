@@ -0,0 +1,1327 @@
+//! Clock tree queries and configuration
+//!
+//! [`Registers::read`] snapshots the CCU registers that set up the PLLs,
+//! the CPU and bus clocks and the module clocks drivers care about.
+//! Everything else here is arithmetic on a snapshot, with no hardware
+//! access, so it can be checked on the host against register dumps.
+//!
+//! ```ignore
+//! let apb1 = ccu::frequency(Clock::Apb1);
+//! let divisor = apb1 / (16 * baudrate);
+//! ```
+//!
+//! Frequencies are in Hz. A PLL or module clock that is disabled or gated
+//! off reads as 0, as does anything derived from it.
+//!
+//! [`set_cpu_hz`], [`set_pll_cpu`], [`set_cpu_clock`] and [`set_bus`] change
+//! the CPU and bus clocks. Drivers dividing down a bus clock [`subscribe`]
+//! to it, and are called with its new frequency after any change made
+//! here, until they [`unsubscribe`] on being freed.
+//!
+//! [`set_pixel_clock`] and [`enable_de_clock`] bring up the display
+//! clocks from a cold boot, with PLL_VIDEO0 or PLL_VIDEO1 and the TCON
+//! dividers set for a panel's dot clock.
+//!
+//! Peripherals' bus clock gates and resets, in the BGR registers, are set
+//! through the [`Enable`] and [`Reset`] traits, which driver constructors
+//! call for the peripheral they're given:
+//!
+//! ```ignore
+//! UART0::enable();
+//! UART0::deassert_reset();
+//! ```
+//!
+//! The IOMMU has a gate but no reset, so it only implements [`Enable`].
+
+use core::cell::RefCell;
+use core::fmt;
+use core::ops::RangeInclusive;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use d1_pac::{
+    CCU, DMAC, LEDC, PWM, SMHC0, SMHC1, SMHC2, SPI0, SPI_DBI, TWI0, TWI1, TWI2, TWI3, UART0, UART1,
+    UART2, UART3, UART4, UART5,
+};
+use riscv::interrupt::Mutex;
+
+use crate::monitor::{self, Command};
+
+/// The 24 MHz crystal oscillator
+pub const HOSC_HZ: u32 = 24_000_000;
+/// The 32.768 kHz oscillator
+pub const LOSC_HZ: u32 = 32_768;
+/// The internal 16 MHz RC oscillator
+pub const RC16M_HZ: u32 = 16_000_000;
+
+const PLL_CPU_CTRL: usize = 0x000;
+const PLL_PERI_CTRL: usize = 0x020;
+const PLL_VIDEO0_CTRL: usize = 0x040;
+const PLL_VIDEO1_CTRL: usize = 0x048;
+const PLL_AUDIO1_CTRL: usize = 0x080;
+const PSI_CLK: usize = 0x510;
+const APB0_CLK: usize = 0x520;
+const APB1_CLK: usize = 0x524;
+const DE_CLK: usize = 0x600;
+const SMHC0_CLK: usize = 0x830;
+const SPI0_CLK: usize = 0x940;
+const TCON_LCD0_CLK: usize = 0xB60;
+const LEDC_CLK: usize = 0xBF0;
+const RISCV_CLK: usize = 0xD00;
+
+// Bus gate and reset registers, with each block's gate at bit n and its
+// reset at bit n + 16.
+const DE_BGR: usize = 0x60C;
+const DI_BGR: usize = 0x62C;
+const G2D_BGR: usize = 0x63C;
+const CE_BGR: usize = 0x68C;
+const VE_BGR: usize = 0x69C;
+const DMA_BGR: usize = 0x70C;
+const MSGBOX_BGR: usize = 0x71C;
+const SPINLOCK_BGR: usize = 0x72C;
+const HSTIMER_BGR: usize = 0x73C;
+const DBGSYS_BGR: usize = 0x78C;
+const PWM_BGR: usize = 0x7AC;
+const IOMMU_BGR: usize = 0x7BC;
+const MBUS_MAT_CLK_GATING: usize = 0x804;
+const SMHC_BGR: usize = 0x84C;
+const UART_BGR: usize = 0x90C;
+const TWI_BGR: usize = 0x91C;
+const CAN_BGR: usize = 0x92C;
+const SPI_BGR: usize = 0x96C;
+const EMAC_BGR: usize = 0x97C;
+const GPADC_BGR: usize = 0x9EC;
+const THS_BGR: usize = 0x9FC;
+const I2S_BGR: usize = 0xA20;
+const OWA_BGR: usize = 0xA2C;
+const DMIC_BGR: usize = 0xA4C;
+const AUDIO_CODEC_BGR: usize = 0xA5C;
+const USB_BGR: usize = 0xA8C;
+const DPSS_TOP_BGR: usize = 0xABC;
+const DSI_BGR: usize = 0xB4C;
+const TCON_LCD_BGR: usize = 0xB7C;
+const TCON_TV_BGR: usize = 0xB9C;
+const TVE_BGR: usize = 0xBBC;
+const IR_TX_BGR: usize = 0xBCC;
+const TVD_BGR: usize = 0xBDC;
+const LEDC_BGR: usize = 0xBFC;
+const CSI_BGR: usize = 0xC1C;
+
+// Bus master gates in MBUS_MAT_CLK_GATING
+const MBUS_DMA: u32 = 1 << 0;
+const MBUS_VE: u32 = 1 << 1;
+const MBUS_CE: u32 = 1 << 2;
+const MBUS_TVIN: u32 = 1 << 7;
+const MBUS_CSI: u32 = 1 << 8;
+const MBUS_G2D: u32 = 1 << 10;
+
+/// PLL enable, and module clock gating, bit
+const ENABLE: u32 = 1 << 31;
+const PLL_LDO_EN: u32 = 1 << 30;
+const PLL_LOCK_EN: u32 = 1 << 29;
+const PLL_LOCK: u32 = 1 << 28;
+const PLL_OUTPUT_GATE: u32 = 1 << 27;
+const PLL_CPU_FACTORS: u32 = (0x3 << 16) | (0xFF << 8) | 0x3;
+const PLL_VIDEO_FACTORS: u32 = (0xFF << 8) | 0x3;
+
+const RISCV_CLK_SEL: u32 = 0x7 << 24;
+const RISCV_DIV: u32 = 0x1F;
+const RISCV_AXI_DIV_SHIFT: u32 = 8;
+const RISCV_AXI_DIV: u32 = 0x3 << RISCV_AXI_DIV_SHIFT;
+
+/// Fastest the C906 is specified to run
+pub const CPU_MAX_HZ: u32 = 1_008_000_000;
+/// Fastest the CPU's AXI bus is run; boot0 divides 1008 MHz by 2
+const AXI_MAX_HZ: u32 = 504_000_000;
+/// Fastest the PSI (AHB) bus may run
+const PSI_MAX_HZ: u32 = 200_000_000;
+/// Fastest APB0 may run
+const APB0_MAX_HZ: u32 = 100_000_000;
+/// Fastest APB1 may run
+const APB1_MAX_HZ: u32 = 100_000_000;
+/// Range the PLL_CPU VCO, before the P divider, is run in
+const PLL_CPU_VCO_HZ: RangeInclusive<u32> = 288_000_000..=2_400_000_000;
+
+/// Range of PLL_VIDEO N, running the 4X outputs at 288 MHz to 2.4 GHz
+const PLL_VIDEO_N: RangeInclusive<u32> = 12..=100;
+/// Fastest the TCON_LCD0 module clock may run
+const TCON_CLK_MAX_HZ: u32 = 600_000_000;
+/// Range of TCON_LCD0's own dot clock divider; below 6 it can't produce
+/// parallel RGB timings
+const DCLK_DIV: RangeInclusive<u32> = 6..=127;
+/// Fastest the display engine clock may run
+const DE_CLK_MAX_HZ: u32 = 300_000_000;
+
+/// Polls of the lock bit before giving up on a PLL
+const LOCK_SPINS: u32 = 100_000;
+/// Register reads to wait after switching the CPU clock mux
+const SETTLE_SPINS: u32 = 32;
+
+const MAX_LISTENERS: usize = 8;
+
+/// A clock in the tree
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Clock {
+    Hosc,
+    Losc,
+    Rc16M,
+    PllCpu,
+    /// PLL_PERI(2X), nominally 1.2 GHz
+    PllPeriX2,
+    /// PLL_PERI(1X), nominally 600 MHz
+    PllPeri,
+    /// PLL_PERI(800M)
+    PllPeri800M,
+    PllVideo0X4,
+    PllVideo0X2,
+    PllVideo0,
+    PllVideo1X4,
+    PllVideo1X2,
+    PllVideo1,
+    PllAudio1,
+    PllAudio1Div2,
+    PllAudio1Div5,
+    /// The C906 core
+    Cpu,
+    Axi,
+    /// PSI, also known as AHB
+    Psi,
+    Apb0,
+    /// APB1, clocking the UARTs and TWIs
+    Apb1,
+    /// Display engine
+    De,
+    TconLcd0,
+    Ledc,
+    Smhc0,
+    Spi0,
+}
+
+impl Clock {
+    /// Every clock, in the order the `clocks` command lists them
+    pub const ALL: [Clock; 26] = [
+        Clock::Hosc,
+        Clock::Losc,
+        Clock::Rc16M,
+        Clock::PllCpu,
+        Clock::PllPeriX2,
+        Clock::PllPeri,
+        Clock::PllPeri800M,
+        Clock::PllVideo0X4,
+        Clock::PllVideo0X2,
+        Clock::PllVideo0,
+        Clock::PllVideo1X4,
+        Clock::PllVideo1X2,
+        Clock::PllVideo1,
+        Clock::PllAudio1,
+        Clock::PllAudio1Div2,
+        Clock::PllAudio1Div5,
+        Clock::Cpu,
+        Clock::Axi,
+        Clock::Psi,
+        Clock::Apb0,
+        Clock::Apb1,
+        Clock::De,
+        Clock::TconLcd0,
+        Clock::Ledc,
+        Clock::Smhc0,
+        Clock::Spi0,
+    ];
+}
+
+/// PLL_CPU factors: 24 MHz * `n` / `m` / `p`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PllCpuFactors {
+    /// 12 to 256
+    pub n: u32,
+    /// 1 to 4
+    pub m: u32,
+    /// 1, 2 or 4
+    pub p: u32,
+}
+
+impl PllCpuFactors {
+    /// Output frequency
+    pub const fn hz(&self) -> u32 {
+        self.vco_hz() / self.p
+    }
+
+    const fn vco_hz(&self) -> u32 {
+        (HOSC_HZ as u64 * self.n as u64 / self.m as u64) as u32
+    }
+
+    fn is_valid(&self) -> bool {
+        (12..=256).contains(&self.n)
+            && (1..=4).contains(&self.m)
+            && matches!(self.p, 1 | 2 | 4)
+            && PLL_CPU_VCO_HZ.contains(&self.vco_hz())
+    }
+}
+
+/// Source of the CPU clock, numbered as in RISCV_CLK
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CpuSource {
+    Hosc = 0,
+    Losc = 1,
+    Rc16M = 2,
+    PllPeri800M = 3,
+    PllPeri = 4,
+    PllCpu = 5,
+    PllAudio1Div2 = 6,
+}
+
+impl CpuSource {
+    fn clock(self) -> Clock {
+        match self {
+            CpuSource::Hosc => Clock::Hosc,
+            CpuSource::Losc => Clock::Losc,
+            CpuSource::Rc16M => Clock::Rc16M,
+            CpuSource::PllPeri800M => Clock::PllPeri800M,
+            CpuSource::PllPeri => Clock::PllPeri,
+            CpuSource::PllCpu => Clock::PllCpu,
+            CpuSource::PllAudio1Div2 => Clock::PllAudio1Div2,
+        }
+    }
+}
+
+/// A bus clock set by [`set_bus`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bus {
+    Psi,
+    Apb0,
+    Apb1,
+}
+
+/// Source of a bus clock
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusSource {
+    Hosc,
+    Losc,
+    /// For PSI only
+    Rc16M,
+    /// For APB0 and APB1 only
+    Psi,
+    PllPeri,
+}
+
+/// A video PLL, for [`set_pixel_clock`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VideoPll {
+    Video0,
+    Video1,
+}
+
+/// Dividers from a video PLL's 4X output down to a dot clock
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PixelFactors {
+    /// PLL_VIDEO N, 12 to 100; the 4X output is 24 MHz * `n`
+    pub n: u32,
+    /// TCON_LCD0 module clock divider, 1 to 16
+    pub m: u32,
+    /// TCON_LCD0's dot clock divider, 6 to 127
+    pub dclk_div: u32,
+}
+
+impl PixelFactors {
+    /// Frequency of the PLL's 4X output
+    pub const fn pll_hz(&self) -> u32 {
+        HOSC_HZ * self.n
+    }
+
+    /// Frequency of the TCON_LCD0 module clock
+    pub const fn tcon_hz(&self) -> u32 {
+        self.pll_hz() / self.m
+    }
+
+    /// The dot clock
+    pub const fn hz(&self) -> u32 {
+        self.tcon_hz() / self.dclk_div
+    }
+}
+
+/// Error changing a clock
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The frequency asked for, or that would result, is out of range
+    FrequencyOutOfRange,
+    /// The factors or divider can't be set in the register
+    InvalidFactors,
+    /// The bus can't be clocked from that source
+    InvalidSource,
+    /// A PLL didn't lock; the CPU, if it ran from PLL_CPU, is left on the
+    /// 24 MHz oscillator
+    LockTimeout,
+}
+
+/// Called with a clock's new frequency
+pub type Listener = fn(hz: u32);
+
+/// Returned by [`subscribe`] when the listener table is full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ListenersFull;
+
+/// A clock and the listener subscribed to it
+type Subscription = (Clock, Listener);
+
+static LISTENERS: Mutex<RefCell<[Option<Subscription>; MAX_LISTENERS]>> =
+    Mutex::new(RefCell::new([None; MAX_LISTENERS]));
+
+/// Bus gate and reset of the display engine, which has no PAC peripheral
+pub struct De;
+
+/// Bus gate and reset of TCON_LCD0, which has no PAC peripheral
+pub struct TconLcd0;
+
+// Blocks with no driver in this crate get a marker type too, so their
+// gates can be opened, and their users counted, without owning the PAC
+// peripheral.
+
+/// Bus gate and reset of the display subsystem top (TCON TOP), which
+/// routes the display engine to the TCONs
+pub struct DpssTop;
+
+/// Bus gate and reset of TCON_TV0
+pub struct TconTv0;
+
+/// Bus gate and reset of the TV encoder's top-level registers
+pub struct TveTop;
+
+/// Bus gate and reset of the TV encoder
+pub struct Tve;
+
+/// Bus gate and reset of the deinterlacer
+pub struct Di;
+
+/// Bus gate and reset of the 2D graphics engine
+pub struct G2d;
+
+/// Bus gate and reset of the crypto engine
+pub struct Ce;
+
+/// Bus gate and reset of the high-speed timer
+pub struct HsTimer;
+
+/// Bus gate and reset of CAN0
+pub struct Can0;
+
+/// Bus gate and reset of CAN1
+pub struct Can1;
+
+/// Bus gate and reset of the Ethernet MAC
+pub struct Emac;
+
+/// Bus gate and reset of the general-purpose ADC
+pub struct Gpadc;
+
+/// Bus gate and reset of the thermal sensor
+pub struct Ths;
+
+/// Bus gate and reset of I2S/PCM0
+pub struct I2s0;
+
+/// Bus gate and reset of I2S/PCM1
+pub struct I2s1;
+
+/// Bus gate and reset of I2S/PCM2
+pub struct I2s2;
+
+/// Bus gate and reset of the digital microphone interface
+pub struct Dmic;
+
+/// Bus gate and reset of the audio codec
+pub struct AudioCodec;
+
+/// Bus gate and reset of the USB0 OTG controller
+pub struct UsbOtg;
+
+/// Bus gate and reset of the USB0 EHCI controller
+pub struct UsbEhci0;
+
+/// Bus gate and reset of the USB1 EHCI controller
+pub struct UsbEhci1;
+
+/// Bus gate and reset of the USB0 OHCI controller
+pub struct UsbOhci0;
+
+/// Bus gate and reset of the USB1 OHCI controller
+pub struct UsbOhci1;
+
+/// Bus gate and reset of the video engine
+pub struct Ve;
+
+/// Bus gate and reset of the MIPI DSI controller
+pub struct Dsi;
+
+/// Bus gate and reset of the camera sensor interface
+pub struct Csi;
+
+/// Bus gate and reset of the TV decoder's top-level registers
+pub struct TvdTop;
+
+/// Bus gate and reset of the TV decoder
+pub struct Tvd;
+
+/// Bus gate and reset of the IR transmitter
+pub struct IrTx;
+
+/// Bus gate and reset of the S/PDIF (One Wire Audio) interface
+pub struct Owa;
+
+/// Bus gate and reset of message box 0
+pub struct Msgbox0;
+
+/// Bus gate and reset of message box 1
+pub struct Msgbox1;
+
+/// Bus gate and reset of message box 2
+pub struct Msgbox2;
+
+/// Bus gate and reset of the hardware spinlocks
+pub struct Spinlock;
+
+/// Bus gate and reset of the debug subsystem
+pub struct Dbgsys;
+
+/// Bus gate of the IOMMU, which has no reset
+pub struct Iommu;
+
+mod sealed {
+    use core::sync::atomic::AtomicU8;
+
+    pub trait Gated {
+        /// Offset of the BGR register
+        const BGR: usize;
+        /// Gate bit; the reset bit is 16 above it
+        const BIT: u32;
+        /// Gate bits in MBUS_MAT_CLK_GATING, for blocks that are bus masters
+        const MBUS: u32;
+
+        /// How many users the gate has
+        fn users() -> &'static AtomicU8;
+    }
+}
+
+/// A peripheral with a bus clock gate in one of the CCU's BGR registers
+///
+/// The gate is counted: it opens on the first [`enable`](Enable::enable)
+/// and closes when every enable has been matched by a
+/// [`disable`](Enable::disable), so users sharing a block, like the DMA
+/// controller's channels, don't switch it off under each other.
+pub trait Enable: sealed::Gated {
+    fn enable() {
+        riscv::interrupt::free(|_| {
+            let users = Self::users().load(Ordering::Relaxed);
+            if users == 0 {
+                unsafe { modify_reg(Self::BGR, |r| r | (1 << Self::BIT)) };
+                if Self::MBUS != 0 {
+                    unsafe { modify_reg(MBUS_MAT_CLK_GATING, |r| r | Self::MBUS) };
+                }
+            }
+            Self::users().store(users.saturating_add(1), Ordering::Relaxed);
+        })
+    }
+
+    /// Drop a user of the gate, closing it if that was the last one
+    fn disable() {
+        riscv::interrupt::free(|_| {
+            let users = Self::users().load(Ordering::Relaxed);
+            if users <= 1 {
+                if Self::MBUS != 0 {
+                    unsafe { modify_reg(MBUS_MAT_CLK_GATING, |r| r & !Self::MBUS) };
+                }
+                unsafe { modify_reg(Self::BGR, |r| r & !(1 << Self::BIT)) };
+            }
+            Self::users().store(users.saturating_sub(1), Ordering::Relaxed);
+        })
+    }
+
+    fn is_enabled() -> bool {
+        read_reg(Self::BGR) & (1 << Self::BIT) != 0
+    }
+}
+
+/// A peripheral with a reset line in one of the CCU's BGR registers
+pub trait Reset: sealed::Gated {
+    fn assert_reset() {
+        riscv::interrupt::free(|_| unsafe {
+            modify_reg(Self::BGR, |r| r & !(1 << (Self::BIT + 16)))
+        })
+    }
+
+    fn deassert_reset() {
+        riscv::interrupt::free(|_| unsafe {
+            modify_reg(Self::BGR, |r| r | (1 << (Self::BIT + 16)))
+        })
+    }
+
+    /// Pulse the reset, returning the block's registers to their defaults
+    fn reset() {
+        Self::assert_reset();
+        Self::deassert_reset();
+    }
+}
+
+/// Implement [`Enable`] for blocks with only a gate
+macro_rules! impl_gate {
+    ($($block:ident: $bgr:ident, $bit:literal $(, mbus $mbus:ident)?;)+) => {
+        $(
+            impl sealed::Gated for $block {
+                const BGR: usize = $bgr;
+                const BIT: u32 = $bit;
+                const MBUS: u32 = 0 $(| $mbus)?;
+
+                #[inline(always)]
+                fn users() -> &'static AtomicU8 {
+                    static USERS: AtomicU8 = AtomicU8::new(0);
+                    &USERS
+                }
+            }
+
+            impl Enable for $block {}
+        )+
+    };
+}
+
+/// Implement [`Enable`] and [`Reset`] for blocks with a gate and a reset
+macro_rules! impl_gated {
+    ($($block:ident: $bgr:ident, $bit:literal $(, mbus $mbus:ident)?;)+) => {
+        impl_gate! {
+            $($block: $bgr, $bit $(, mbus $mbus)?;)+
+        }
+        $(
+            impl Reset for $block {}
+        )+
+    };
+}
+
+impl_gated! {
+    De: DE_BGR, 0;
+    Di: DI_BGR, 0;
+    G2d: G2D_BGR, 0, mbus MBUS_G2D;
+    Ce: CE_BGR, 0, mbus MBUS_CE;
+    Ve: VE_BGR, 0, mbus MBUS_VE;
+    DMAC: DMA_BGR, 0, mbus MBUS_DMA;
+    Msgbox0: MSGBOX_BGR, 0;
+    Msgbox1: MSGBOX_BGR, 1;
+    Msgbox2: MSGBOX_BGR, 2;
+    Spinlock: SPINLOCK_BGR, 0;
+    HsTimer: HSTIMER_BGR, 0;
+    Dbgsys: DBGSYS_BGR, 0;
+    PWM: PWM_BGR, 0;
+    SMHC0: SMHC_BGR, 0;
+    SMHC1: SMHC_BGR, 1;
+    SMHC2: SMHC_BGR, 2;
+    UART0: UART_BGR, 0;
+    UART1: UART_BGR, 1;
+    UART2: UART_BGR, 2;
+    UART3: UART_BGR, 3;
+    UART4: UART_BGR, 4;
+    UART5: UART_BGR, 5;
+    TWI0: TWI_BGR, 0;
+    TWI1: TWI_BGR, 1;
+    TWI2: TWI_BGR, 2;
+    TWI3: TWI_BGR, 3;
+    Can0: CAN_BGR, 0;
+    Can1: CAN_BGR, 1;
+    SPI0: SPI_BGR, 0;
+    SPI_DBI: SPI_BGR, 1;
+    Emac: EMAC_BGR, 0;
+    Gpadc: GPADC_BGR, 0;
+    Ths: THS_BGR, 0;
+    I2s0: I2S_BGR, 0;
+    I2s1: I2S_BGR, 1;
+    I2s2: I2S_BGR, 2;
+    Owa: OWA_BGR, 0;
+    Dmic: DMIC_BGR, 0;
+    AudioCodec: AUDIO_CODEC_BGR, 0;
+    UsbOhci0: USB_BGR, 0;
+    UsbOhci1: USB_BGR, 1;
+    UsbEhci0: USB_BGR, 4;
+    UsbEhci1: USB_BGR, 5;
+    UsbOtg: USB_BGR, 8;
+    DpssTop: DPSS_TOP_BGR, 0;
+    Dsi: DSI_BGR, 0;
+    TconLcd0: TCON_LCD_BGR, 0;
+    TconTv0: TCON_TV_BGR, 0;
+    TveTop: TVE_BGR, 0;
+    Tve: TVE_BGR, 1;
+    IrTx: IR_TX_BGR, 0;
+    TvdTop: TVD_BGR, 0, mbus MBUS_TVIN;
+    Tvd: TVD_BGR, 1;
+    LEDC: LEDC_BGR, 0;
+    Csi: CSI_BGR, 0, mbus MBUS_CSI;
+}
+
+impl_gate! {
+    Iommu: IOMMU_BGR, 0;
+}
+
+/// Raw values of the CCU registers the clock tree depends on
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub pll_cpu: u32,
+    pub pll_peri: u32,
+    pub pll_video0: u32,
+    pub pll_video1: u32,
+    pub pll_audio1: u32,
+    pub riscv_clk: u32,
+    pub psi_clk: u32,
+    pub apb0_clk: u32,
+    pub apb1_clk: u32,
+    pub de_clk: u32,
+    pub tcon_lcd0_clk: u32,
+    pub ledc_clk: u32,
+    pub smhc0_clk: u32,
+    pub spi0_clk: u32,
+}
+
+impl Registers {
+    /// Snapshot the registers from the CCU
+    pub fn read() -> Self {
+        Self {
+            pll_cpu: read_reg(PLL_CPU_CTRL),
+            pll_peri: read_reg(PLL_PERI_CTRL),
+            pll_video0: read_reg(PLL_VIDEO0_CTRL),
+            pll_video1: read_reg(PLL_VIDEO1_CTRL),
+            pll_audio1: read_reg(PLL_AUDIO1_CTRL),
+            riscv_clk: read_reg(RISCV_CLK),
+            psi_clk: read_reg(PSI_CLK),
+            apb0_clk: read_reg(APB0_CLK),
+            apb1_clk: read_reg(APB1_CLK),
+            de_clk: read_reg(DE_CLK),
+            tcon_lcd0_clk: read_reg(TCON_LCD0_CLK),
+            ledc_clk: read_reg(LEDC_CLK),
+            smhc0_clk: read_reg(SMHC0_CLK),
+            spi0_clk: read_reg(SPI0_CLK),
+        }
+    }
+
+    /// Frequency of `clock` with the CCU set up as in this snapshot
+    pub fn frequency(&self, clock: Clock) -> u32 {
+        use Clock::*;
+
+        match clock {
+            Hosc => HOSC_HZ,
+            Losc => LOSC_HZ,
+            Rc16M => RC16M_HZ,
+            // PLL_CPU = 24 MHz * N / M / P
+            PllCpu => {
+                let p = 1 << field(self.pll_cpu, 16, 2).min(2);
+                pll(self.pll_cpu, field(self.pll_cpu, 0, 2) + 1) / p
+            }
+            // PLL_PERI(2X) = 24 MHz * N / M / P0
+            PllPeriX2 => self.pll_peri_x4() / (field(self.pll_peri, 16, 3) + 1),
+            PllPeri => self.frequency(PllPeriX2) / 2,
+            // PLL_PERI(800M) = 24 MHz * N / M / P1
+            PllPeri800M => self.pll_peri_x4() / (field(self.pll_peri, 20, 3) + 1),
+            PllVideo0X4 => pll(self.pll_video0, field(self.pll_video0, 1, 1) + 1),
+            PllVideo0X2 => self.frequency(PllVideo0X4) / 2,
+            PllVideo0 => self.frequency(PllVideo0X4) / 4,
+            PllVideo1X4 => pll(self.pll_video1, field(self.pll_video1, 1, 1) + 1),
+            PllVideo1X2 => self.frequency(PllVideo1X4) / 2,
+            PllVideo1 => self.frequency(PllVideo1X4) / 4,
+            PllAudio1 => pll(self.pll_audio1, field(self.pll_audio1, 1, 1) + 1),
+            PllAudio1Div2 => self.frequency(PllAudio1) / (field(self.pll_audio1, 16, 3) + 1),
+            PllAudio1Div5 => self.frequency(PllAudio1) / (field(self.pll_audio1, 20, 3) + 1),
+            Cpu => {
+                let source = match field(self.riscv_clk, 24, 3) {
+                    0 => Hosc,
+                    1 => Losc,
+                    2 => Rc16M,
+                    3 => PllPeri800M,
+                    4 => PllPeri,
+                    5 => PllCpu,
+                    6 => PllAudio1Div2,
+                    _ => return 0,
+                };
+                self.frequency(source) / (field(self.riscv_clk, 0, 5) + 1)
+            }
+            Axi => self.frequency(Cpu) / (field(self.riscv_clk, 8, 2) + 1),
+            Psi => {
+                let source = match field(self.psi_clk, 24, 2) {
+                    0 => Hosc,
+                    1 => Losc,
+                    2 => Rc16M,
+                    _ => PllPeri,
+                };
+                (self.frequency(source) >> field(self.psi_clk, 8, 2))
+                    / (field(self.psi_clk, 0, 2) + 1)
+            }
+            Apb0 => self.apb(self.apb0_clk),
+            Apb1 => self.apb(self.apb1_clk),
+            De => self.module(
+                self.de_clk,
+                5,
+                &[PllPeriX2, PllVideo0X4, PllVideo1X4, PllAudio1Div2],
+            ),
+            TconLcd0 => self.module(
+                self.tcon_lcd0_clk,
+                4,
+                &[
+                    PllVideo0,
+                    PllVideo0X4,
+                    PllVideo1,
+                    PllVideo1X4,
+                    PllPeriX2,
+                    PllAudio1Div2,
+                ],
+            ),
+            Ledc => self.module(self.ledc_clk, 4, &[Hosc, PllPeri]),
+            Smhc0 => self.module(
+                self.smhc0_clk,
+                4,
+                &[Hosc, PllPeri, PllPeriX2, PllAudio1Div2],
+            ),
+            Spi0 => self.module(
+                self.spi0_clk,
+                4,
+                &[Hosc, PllPeri, PllPeriX2, PllAudio1Div2, PllAudio1Div5],
+            ),
+        }
+    }
+
+    /// PLL_PERI(4X) = 24 MHz * N / M, which the PLL_PERI outputs divide
+    fn pll_peri_x4(&self) -> u32 {
+        pll(self.pll_peri, field(self.pll_peri, 1, 1) + 1)
+    }
+
+    /// APB0 and APB1 = source / 2^N / M
+    fn apb(&self, reg: u32) -> u32 {
+        let source = match field(reg, 24, 2) {
+            0 => Clock::Hosc,
+            1 => Clock::Losc,
+            2 => Clock::Psi,
+            _ => Clock::PllPeri,
+        };
+        (self.frequency(source) >> field(reg, 8, 2)) / (field(reg, 0, 5) + 1)
+    }
+
+    /// A gated module clock = source / 2^N / M, with `m_bits` of M and the
+    /// sources listed in register order
+    fn module(&self, reg: u32, m_bits: u32, sources: &[Clock]) -> u32 {
+        if reg & ENABLE == 0 {
+            return 0;
+        }
+        let Some(&source) = sources.get(field(reg, 24, 3) as usize) else {
+            return 0;
+        };
+        (self.frequency(source) >> field(reg, 8, 2)) / (field(reg, 0, m_bits) + 1)
+    }
+
+    /// These registers with `bus` clocked from `source` divided by `div`,
+    /// as long as every bus stays within its maximum
+    fn with_bus(mut self, bus: Bus, source: BusSource, div: u32) -> Result<Self, Error> {
+        let (reg, m_bits) = match bus {
+            Bus::Psi => (&mut self.psi_clk, 2),
+            Bus::Apb0 => (&mut self.apb0_clk, 5),
+            Bus::Apb1 => (&mut self.apb1_clk, 5),
+        };
+        let select = match (bus, source) {
+            (_, BusSource::Hosc) => 0,
+            (_, BusSource::Losc) => 1,
+            (Bus::Psi, BusSource::Rc16M) => 2,
+            (Bus::Apb0 | Bus::Apb1, BusSource::Psi) => 2,
+            (_, BusSource::PllPeri) => 3,
+            _ => return Err(Error::InvalidSource),
+        };
+        let (n, m) = (0..4)
+            .map(|n| (n, div >> n))
+            .find(|&(n, m)| m << n == div && (1..=1 << m_bits).contains(&m))
+            .ok_or(Error::InvalidFactors)?;
+
+        let mask = (0x3 << 24) | (0x3 << 8) | ((1 << m_bits) - 1);
+        *reg = (*reg & !mask) | (select << 24) | (n << 8) | (m - 1);
+
+        let within = |clock, max| (1..=max).contains(&self.frequency(clock));
+        if within(Clock::Psi, PSI_MAX_HZ)
+            && within(Clock::Apb0, APB0_MAX_HZ)
+            && within(Clock::Apb1, APB1_MAX_HZ)
+        {
+            Ok(self)
+        } else {
+            Err(Error::FrequencyOutOfRange)
+        }
+    }
+}
+
+/// 24 MHz * N / `m` for an enabled PLL, whose N is bits 15:8 plus one
+fn pll(reg: u32, m: u32) -> u32 {
+    if reg & (ENABLE | PLL_OUTPUT_GATE) != ENABLE | PLL_OUTPUT_GATE {
+        return 0;
+    }
+    let n = field(reg, 8, 8) as u64 + 1;
+    (HOSC_HZ as u64 * n / m as u64) as u32
+}
+
+fn field(reg: u32, shift: u32, bits: u32) -> u32 {
+    (reg >> shift) & ((1 << bits) - 1)
+}
+
+/// Current frequency of `clock`, read from the CCU
+pub fn frequency(clock: Clock) -> u32 {
+    Registers::read().frequency(clock)
+}
+
+/// Have `listener` called with `clock`'s new frequency whenever a change
+/// made through this module changes it
+pub fn subscribe(clock: Clock, listener: Listener) -> Result<(), ListenersFull> {
+    riscv::interrupt::free(|cs| {
+        let mut listeners = LISTENERS.borrow(cs).borrow_mut();
+        let slot = listeners
+            .iter_mut()
+            .find(|l| l.is_none())
+            .ok_or(ListenersFull)?;
+        *slot = Some((clock, listener));
+        Ok(())
+    })
+}
+
+/// Stop calling `listener` about `clock`
+pub fn unsubscribe(clock: Clock, listener: Listener) {
+    riscv::interrupt::free(|cs| {
+        for slot in LISTENERS.borrow(cs).borrow_mut().iter_mut() {
+            if slot.is_some_and(|(c, l)| c == clock && core::ptr::fn_addr_eq(l, listener)) {
+                *slot = None;
+            }
+        }
+    })
+}
+
+/// Run the CPU as close to `hz` as PLL_CPU allows without going over,
+/// returning the frequency it got
+///
+/// The AXI divider is set to keep AXI within its limit, as it is by every
+/// function here that changes the CPU clock.
+pub fn set_cpu_hz(hz: u32) -> Result<u32, Error> {
+    if hz > CPU_MAX_HZ {
+        return Err(Error::FrequencyOutOfRange);
+    }
+    let (factors, div) = cpu_factors(hz).ok_or(Error::FrequencyOutOfRange)?;
+    // Off PLL_CPU first, so the new factors never apply at the old divider.
+    set_cpu_clock(CpuSource::Hosc, 1)?;
+    set_pll_cpu(factors)?;
+    set_cpu_clock(CpuSource::PllCpu, div)
+}
+
+/// The PLL_CPU factors and CPU divider giving the frequency closest to
+/// `hz` without going over
+pub fn cpu_factors(hz: u32) -> Option<(PllCpuFactors, u32)> {
+    let mut best: Option<(PllCpuFactors, u32, u32)> = None;
+    for div in 1..=32 {
+        for m in 1..=4 {
+            for p in [1, 2, 4] {
+                for n in 12..=256 {
+                    let factors = PllCpuFactors { n, m, p };
+                    if !factors.is_valid() {
+                        continue;
+                    }
+                    let got = factors.hz() / div;
+                    if got > hz {
+                        break;
+                    }
+                    if got == hz {
+                        return Some((factors, div));
+                    }
+                    if best.is_none_or(|(_, _, b)| got > b) {
+                        best = Some((factors, div, got));
+                    }
+                }
+            }
+        }
+    }
+    best.map(|(factors, div, _)| (factors, div))
+}
+
+/// Program PLL_CPU and wait for it to lock, returning its new frequency
+///
+/// If the CPU runs from PLL_CPU, it's moved to the 24 MHz oscillator while
+/// the PLL relocks and then moved back, keeping its divider.
+pub fn set_pll_cpu(factors: PllCpuFactors) -> Result<u32, Error> {
+    if !factors.is_valid() {
+        return Err(Error::InvalidFactors);
+    }
+    let before = Registers::read();
+    let on_pll = field(before.riscv_clk, 24, 3) == CpuSource::PllCpu as u32;
+    let div = field(before.riscv_clk, 0, 5) + 1;
+    if on_pll && factors.hz() / div > CPU_MAX_HZ {
+        return Err(Error::FrequencyOutOfRange);
+    }
+
+    let result = unsafe {
+        if on_pll {
+            switch_cpu(CpuSource::Hosc, 1, 1);
+        }
+        let locked = program_pll_cpu(factors);
+        if on_pll && locked.is_ok() {
+            switch_cpu(CpuSource::PllCpu, div, axi_div(factors.hz() / div));
+        }
+        locked
+    };
+    notify(&before);
+    result.map(|()| factors.hz())
+}
+
+/// Clock the CPU from `source` divided by `div` (1 to 32), returning the
+/// new CPU frequency
+///
+/// The mux goes via the undivided 24 MHz oscillator, so the core never
+/// runs from one source at the other's divider.
+pub fn set_cpu_clock(source: CpuSource, div: u32) -> Result<u32, Error> {
+    if !(1..=32).contains(&div) {
+        return Err(Error::InvalidFactors);
+    }
+    let before = Registers::read();
+    let hz = before.frequency(source.clock()) / div;
+    if hz == 0 || hz > CPU_MAX_HZ {
+        return Err(Error::FrequencyOutOfRange);
+    }
+    unsafe { switch_cpu(source, div, axi_div(hz)) };
+    Ok(notify(&before).frequency(Clock::Cpu))
+}
+
+/// Clock `bus` from `source` divided by `div`, returning the bus's new
+/// frequency
+///
+/// `div` is a power of two up to 8 times 1 to 4 for PSI, or times 1 to 32
+/// for the APBs. Settings that would run any bus, including APBs clocked
+/// from PSI, over its maximum are refused.
+pub fn set_bus(bus: Bus, source: BusSource, div: u32) -> Result<u32, Error> {
+    let before = Registers::read();
+    let after = before.with_bus(bus, source, div)?;
+    let (offset, value, clock) = match bus {
+        Bus::Psi => (PSI_CLK, after.psi_clk, Clock::Psi),
+        Bus::Apb0 => (APB0_CLK, after.apb0_clk, Clock::Apb0),
+        Bus::Apb1 => (APB1_CLK, after.apb1_clk, Clock::Apb1),
+    };
+    unsafe { write_reg(offset, value) };
+    Ok(notify(&before).frequency(clock))
+}
+
+/// The factors giving the dot clock closest to `hz`
+pub fn pixel_factors(hz: u32) -> Option<PixelFactors> {
+    let mut best: Option<(PixelFactors, u32)> = None;
+    for dclk_div in DCLK_DIV {
+        for m in 1..=16 {
+            let tcon_hz = hz as u64 * dclk_div as u64;
+            if tcon_hz > TCON_CLK_MAX_HZ as u64 {
+                break;
+            }
+            let pll_hz = tcon_hz * m as u64;
+            let n = ((pll_hz + HOSC_HZ as u64 / 2) / HOSC_HZ as u64) as u32;
+            if !PLL_VIDEO_N.contains(&n) {
+                continue;
+            }
+            let factors = PixelFactors { n, m, dclk_div };
+            if factors.tcon_hz() > TCON_CLK_MAX_HZ {
+                continue;
+            }
+            let error = factors.hz().abs_diff(hz);
+            if error == 0 {
+                return Some(factors);
+            }
+            if best.is_none_or(|(_, e)| error < e) {
+                best = Some((factors, error));
+            }
+        }
+    }
+    best.map(|(factors, _)| factors)
+}
+
+/// Clock TCON_LCD0 from `pll` for a dot clock as close to `hz` as it can
+/// get, returning the factors used
+///
+/// This programs the PLL, so nothing else may be using it. TCON_LCD0's
+/// bus gate and reset, and its own dot clock divider, are left to its
+/// driver, the divider set to the returned [`PixelFactors::dclk_div`].
+pub fn set_pixel_clock(pll: VideoPll, hz: u32) -> Result<PixelFactors, Error> {
+    let factors = pixel_factors(hz).ok_or(Error::FrequencyOutOfRange)?;
+    let (offset, source) = match pll {
+        VideoPll::Video0 => (PLL_VIDEO0_CTRL, 1),
+        VideoPll::Video1 => (PLL_VIDEO1_CTRL, 3),
+    };
+
+    let before = Registers::read();
+    unsafe {
+        program_pll(offset, PLL_VIDEO_FACTORS, (factors.n - 1) << 8)?;
+        write_reg(TCON_LCD0_CLK, ENABLE | (source << 24) | (factors.m - 1));
+    }
+    notify(&before);
+    Ok(factors)
+}
+
+/// Clock the display engine from PLL_PERI(2X), as fast as it may run,
+/// returning the DE clock's frequency
+///
+/// The DE's bus gate and reset are left to its driver.
+pub fn enable_de_clock() -> Result<u32, Error> {
+    let before = Registers::read();
+    let peri_hz = before.frequency(Clock::PllPeriX2);
+    let m = peri_hz.div_ceil(DE_CLK_MAX_HZ);
+    if !(1..=32).contains(&m) {
+        return Err(Error::FrequencyOutOfRange);
+    }
+    unsafe { write_reg(DE_CLK, ENABLE | (m - 1)) };
+    Ok(notify(&before).frequency(Clock::De))
+}
+
+/// Clock the LEDC from the 24 MHz oscillator, undivided, returning the
+/// LEDC clock's frequency
+pub fn enable_ledc_clock() -> u32 {
+    let before = Registers::read();
+    unsafe { write_reg(LEDC_CLK, ENABLE) };
+    notify(&before).frequency(Clock::Ledc)
+}
+
+/// Run the listeners of clocks that changed since `before`, returning the
+/// registers as they are now
+fn notify(before: &Registers) -> Registers {
+    let after = Registers::read();
+    let listeners = riscv::interrupt::free(|cs| *LISTENERS.borrow(cs).borrow());
+    for (clock, listener) in listeners.into_iter().flatten() {
+        let hz = after.frequency(clock);
+        if hz != before.frequency(clock) {
+            listener(hz);
+        }
+    }
+    after
+}
+
+/// Set the factors, enable PLL_CPU and ungate it once locked
+unsafe fn program_pll_cpu(factors: PllCpuFactors) -> Result<(), Error> {
+    let p = factors.p.trailing_zeros();
+    let bits = (p << 16) | ((factors.n - 1) << 8) | (factors.m - 1);
+    program_pll(PLL_CPU_CTRL, PLL_CPU_FACTORS, bits)
+}
+
+/// Replace the `mask` factor bits of a PLL with `bits`, enable it and
+/// ungate its output once locked
+unsafe fn program_pll(offset: usize, mask: u32, bits: u32) -> Result<(), Error> {
+    let mut reg = read_reg(offset) & !(PLL_OUTPUT_GATE | mask);
+    write_reg(offset, reg);
+    reg |= ENABLE | PLL_LDO_EN | PLL_LOCK_EN | bits;
+    write_reg(offset, reg);
+
+    if !(0..LOCK_SPINS).any(|_| read_reg(offset) & PLL_LOCK != 0) {
+        return Err(Error::LockTimeout);
+    }
+    write_reg(offset, reg | PLL_OUTPUT_GATE);
+    write_reg(offset, (reg | PLL_OUTPUT_GATE) & !PLL_LOCK_EN);
+    Ok(())
+}
+
+/// Smallest AXI divider, 1 to 4, keeping AXI within [`AXI_MAX_HZ`] with
+/// the CPU at `cpu_hz`
+fn axi_div(cpu_hz: u32) -> u32 {
+    cpu_hz.div_ceil(AXI_MAX_HZ).clamp(1, 4)
+}
+
+/// Move the CPU clock mux to `source` via the oscillator, with AXI at the
+/// CPU clock divided by `axi_div`
+///
+/// The AXI divider changes while the CPU runs from the oscillator, so AXI
+/// never runs fast at the old divider.
+unsafe fn switch_cpu(source: CpuSource, div: u32, axi_div: u32) {
+    let reg = read_reg(RISCV_CLK) & !(RISCV_CLK_SEL | RISCV_DIV | RISCV_AXI_DIV);
+    let reg = reg | ((axi_div - 1) << RISCV_AXI_DIV_SHIFT);
+    write_reg(RISCV_CLK, reg);
+    settle();
+    write_reg(RISCV_CLK, reg | (div - 1));
+    write_reg(RISCV_CLK, reg | ((source as u32) << 24) | (div - 1));
+    settle();
+}
+
+fn settle() {
+    for _ in 0..SETTLE_SPINS {
+        read_reg(RISCV_CLK);
+    }
+}
+
+fn read_reg(offset: usize) -> u32 {
+    unsafe { read_volatile((CCU::PTR as usize + offset) as *const u32) }
+}
+
+unsafe fn write_reg(offset: usize, value: u32) {
+    write_volatile((CCU::PTR as usize + offset) as *mut u32, value)
+}
+
+/// Read-modify-write a register; callers hold a critical section, as
+/// other peripherals' bits share it
+unsafe fn modify_reg(offset: usize, f: impl FnOnce(u32) -> u32) {
+    write_reg(offset, f(read_reg(offset)))
+}
+
+/// Add the `clocks` and `cpu` commands to the monitor
+pub fn register_commands() -> Result<(), monitor::TableFull> {
+    monitor::register(&CLOCKS)?;
+    monitor::register(&CPU)
+}
+
+pub static CLOCKS: Command = Command {
+    name: "clocks",
+    usage: "",
+    help: "show the frequency of each clock in the CCU",
+    run: cmd_clocks,
+};
+
+fn cmd_clocks(out: &mut dyn fmt::Write, _args: &[&str]) -> Result<(), &'static str> {
+    let regs = Registers::read();
+    for clock in Clock::ALL {
+        let hz = regs.frequency(clock);
+        let _ = writeln!(
+            out,
+            "{:>5}.{:06} MHz  {:?}",
+            hz / 1_000_000,
+            hz % 1_000_000,
+            clock
+        );
+    }
+    Ok(())
+}
+
+pub static CPU: Command = Command {
+    name: "cpu",
+    usage: "[MHz]",
+    help: "show the CPU frequency, or change it",
+    run: cmd_cpu,
+};
+
+fn cmd_cpu(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str> {
+    let hz = match args.get(1) {
+        None => frequency(Clock::Cpu),
+        Some(mhz) => {
+            let mhz: u32 = mhz.parse().map_err(|_| "bad frequency")?;
+            set_cpu_hz(mhz.saturating_mul(1_000_000)).map_err(|e| match e {
+                Error::LockTimeout => "PLL_CPU didn't lock",
+                _ => "frequency out of range",
+            })?
+        }
+    };
+    let _ = writeln!(out, "cpu: {} MHz", hz / 1_000_000);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Power-on values: every PLL off, everything on the 24 MHz crystal
+    const RESET: Registers = Registers {
+        pll_cpu: 0x4A00_1000,
+        pll_peri: 0x4821_6300,
+        pll_video0: 0x4800_2301,
+        pll_video1: 0x4800_2301,
+        pll_audio1: 0x4814_2A01,
+        riscv_clk: 0,
+        psi_clk: 0,
+        apb0_clk: 0,
+        apb1_clk: 0,
+        de_clk: 0,
+        tcon_lcd0_clk: 0,
+        ledc_clk: 0,
+        smhc0_clk: 0,
+        spi0_clk: 0,
+    };
+
+    /// As left by boot0: PLL_CPU at 1008 MHz, PLL_PERI at 600 MHz, PSI at
+    /// 200 MHz and APB0 at 100 MHz from PLL_PERI
+    const BOOT0: Registers = Registers {
+        pll_cpu: 0xF800_2900,
+        pll_peri: 0xF821_6300,
+        riscv_clk: 0x0500_0100,
+        psi_clk: 0x0300_0002,
+        apb0_clk: 0x0300_0102,
+        ..RESET
+    };
+
+    #[test]
+    fn reset_values() {
+        assert_eq!(RESET.frequency(Clock::PllCpu), 0);
+        assert_eq!(RESET.frequency(Clock::PllPeri), 0);
+        assert_eq!(RESET.frequency(Clock::Cpu), HOSC_HZ);
+        assert_eq!(RESET.frequency(Clock::Psi), HOSC_HZ);
+        assert_eq!(RESET.frequency(Clock::Apb0), HOSC_HZ);
+        assert_eq!(RESET.frequency(Clock::Apb1), 24_000_000);
+        assert_eq!(RESET.frequency(Clock::Ledc), 0);
+    }
+
+    #[test]
+    fn pll_peri() {
+        assert_eq!(BOOT0.frequency(Clock::PllPeriX2), 1_200_000_000);
+        assert_eq!(BOOT0.frequency(Clock::PllPeri), 600_000_000);
+        assert_eq!(BOOT0.frequency(Clock::PllPeri800M), 800_000_000);
+        assert_eq!(BOOT0.frequency(Clock::Psi), 200_000_000);
+        assert_eq!(BOOT0.frequency(Clock::Apb0), 100_000_000);
+        assert_eq!(BOOT0.frequency(Clock::Apb1), 24_000_000);
+    }
+
+    #[test]
+    fn cpu_on_pll_cpu() {
+        assert_eq!(BOOT0.frequency(Clock::PllCpu), 1_008_000_000);
+        assert_eq!(BOOT0.frequency(Clock::Cpu), 1_008_000_000);
+        assert_eq!(BOOT0.frequency(Clock::Axi), 504_000_000);
+
+        let div2 = Registers {
+            riscv_clk: 0x0500_0101,
+            ..BOOT0
+        };
+        assert_eq!(div2.frequency(Clock::Cpu), 504_000_000);
+        assert_eq!(div2.frequency(Clock::Axi), 252_000_000);
+    }
+
+    #[test]
+    fn bus_limits() {
+        let apb1 = BOOT0.with_bus(Bus::Apb1, BusSource::PllPeri, 8).unwrap();
+        assert_eq!(apb1.frequency(Clock::Apb1), 75_000_000);
+        let apb1 = BOOT0.with_bus(Bus::Apb1, BusSource::Psi, 2).unwrap();
+        assert_eq!(apb1.frequency(Clock::Apb1), 100_000_000);
+
+        assert_eq!(
+            BOOT0.with_bus(Bus::Apb1, BusSource::PllPeri, 1),
+            Err(Error::FrequencyOutOfRange)
+        );
+        assert_eq!(
+            BOOT0.with_bus(Bus::Psi, BusSource::PllPeri, 2),
+            Err(Error::FrequencyOutOfRange)
+        );
+        // APB1 on PSI follows it over the limit.
+        let psi = BOOT0.with_bus(Bus::Psi, BusSource::PllPeri, 6).unwrap();
+        let apb1 = psi.with_bus(Bus::Apb1, BusSource::Psi, 1).unwrap();
+        assert_eq!(apb1.frequency(Clock::Apb1), 100_000_000);
+        assert_eq!(
+            apb1.with_bus(Bus::Psi, BusSource::PllPeri, 3),
+            Err(Error::FrequencyOutOfRange)
+        );
+        assert_eq!(
+            RESET.with_bus(Bus::Apb0, BusSource::PllPeri, 8),
+            Err(Error::FrequencyOutOfRange)
+        );
+    }
+
+    #[test]
+    fn cpu_factors_exact() {
+        let (factors, div) = cpu_factors(1_008_000_000).unwrap();
+        assert_eq!(factors, PllCpuFactors { n: 42, m: 1, p: 1 });
+        assert_eq!(div, 1);
+        let (factors, div) = cpu_factors(24_000_000).unwrap();
+        assert_eq!(factors.hz() / div, 24_000_000);
+    }
+
+    #[test]
+    fn axi_divider() {
+        assert_eq!(axi_div(24_000_000), 1);
+        assert_eq!(axi_div(AXI_MAX_HZ), 1);
+        assert_eq!(axi_div(AXI_MAX_HZ + 1), 2);
+        assert_eq!(axi_div(CPU_MAX_HZ), 2);
+        // The same setting as boot0's
+        let cpu = BOOT0.frequency(Clock::Cpu);
+        assert_eq!(axi_div(cpu), field(BOOT0.riscv_clk, 8, 2) + 1);
+        assert_eq!(BOOT0.frequency(Clock::Axi), AXI_MAX_HZ);
+    }
+
+    #[test]
+    fn pixel_factors_for_lcd() {
+        let factors = pixel_factors(10_000_000).unwrap();
+        assert_eq!((factors.n, factors.m, factors.dclk_div), (15, 6, 6));
+        assert_eq!(factors.hz(), 10_000_000);
+    }
+}
@@ -0,0 +1,145 @@
+//! Console on UART0
+//!
+//! Owns the console [`Uart`] so that `print!`/`println!`, the logger and
+//! interrupt handlers can share it. Output is written with interrupts
+//! disabled, so lines from different contexts don't interleave.
+
+use core::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+};
+
+use d1_pac::UART0;
+use riscv::interrupt::Mutex;
+
+use crate::uart::Uart;
+
+static CONSOLE: Mutex<RefCell<Option<Uart<UART0>>>> = Mutex::new(RefCell::new(None));
+
+/// A byte received by [`peek`] that hasn't been consumed yet
+static PEEKED: Mutex<Cell<Option<u8>>> = Mutex::new(Cell::new(None));
+
+/// Install `uart` as the console
+pub fn init(uart: Uart<UART0>) {
+    riscv::interrupt::free(|cs| *CONSOLE.borrow(cs).borrow_mut() = Some(uart));
+}
+
+/// Remove the console UART, e.g. to hand it to a different protocol
+pub fn take() -> Option<Uart<UART0>> {
+    riscv::interrupt::free(|cs| CONSOLE.borrow(cs).borrow_mut().take())
+}
+
+/// Run `f` with the console UART, if one is installed
+///
+/// Returns `None` if there is no console, or it is already borrowed further
+/// up the stack.
+pub fn with<R>(f: impl FnOnce(&mut Uart<UART0>) -> R) -> Option<R> {
+    riscv::interrupt::free(|cs| {
+        let mut console = CONSOLE.borrow(cs).try_borrow_mut().ok()?;
+        console.as_mut().map(f)
+    })
+}
+
+/// Look at the next received byte, if there is one, without consuming it
+///
+/// The byte is still returned by the next read through [`Console`], unless
+/// [`take_peeked`] consumes it first.
+pub fn peek() -> Option<u8> {
+    riscv::interrupt::free(|cs| {
+        let peeked = PEEKED.borrow(cs);
+        if peeked.get().is_none() {
+            peeked.set(with(|uart| uart.try_read_byte().ok()).flatten());
+        }
+        peeked.get()
+    })
+}
+
+/// Consume the byte returned by [`peek`]
+pub fn take_peeked() -> Option<u8> {
+    riscv::interrupt::free(|cs| PEEKED.borrow(cs).take())
+}
+
+fn has_peeked() -> bool {
+    riscv::interrupt::free(|cs| PEEKED.borrow(cs).get().is_some())
+}
+
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    with(|uart| uart.write_fmt(args).ok());
+}
+
+/// Print `args` and a line break with the console held throughout, so the
+/// line can't be split by output from an interrupt handler
+#[doc(hidden)]
+pub fn _println(args: core::fmt::Arguments) {
+    with(|uart| {
+        uart.write_fmt(args).ok();
+        uart.write_str("\r\n").ok();
+    });
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::console::_print(core::format_args!($($arg)*));
+    }
+}
+
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {
+        $crate::console::_println(core::format_args!($($arg)*));
+    }
+}
+
+/// Handle implementing `embedded-io` over the installed console
+///
+/// Reads spin (with interrupts enabled between polls) until data arrives;
+/// writes are dropped if there is no console.
+pub struct Console;
+
+impl embedded_io::ErrorType for Console {
+    type Error = crate::uart::Error;
+}
+
+impl embedded_io::Read for Console {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if let Some(first) = buf.first_mut() {
+            if let Some(byte) = take_peeked() {
+                *first = byte;
+                return Ok(1);
+            }
+        }
+        loop {
+            // A receive error held back by an earlier read counts as ready,
+            // or it would never be reported.
+            let res = with(|uart| {
+                if embedded_io::ReadReady::read_ready(uart).unwrap_or(true) {
+                    Some(embedded_io::Read::read(uart, buf))
+                } else {
+                    None
+                }
+            });
+            if let Some(Some(res)) = res {
+                return res;
+            }
+        }
+    }
+}
+
+impl embedded_io::ReadReady for Console {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(has_peeked() || with(|uart| uart.is_rx_ready()).unwrap_or(false))
+    }
+}
+
+impl embedded_io::Write for Console {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        with(|uart| embedded_io::Write::write(uart, buf)).unwrap_or(Ok(buf.len()))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        with(|uart| uart.flush_blocking());
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
-#![allow(dead_code)]
+//! Display engine mixer 0, scanning a framebuffer out to the TCON
+//!
+//! [`init`] sets up UI overlay 1 for a [`DisplayConfig`]; the TCON then
+//! needs to be started for the panel, see [`tcon`](crate::tcon).
+
+// Not every register is used yet, and the writes spell out zero fields.
+#![allow(dead_code, clippy::identity_op, clippy::erasing_op, clippy::eq_op)]
+
+use crate::ccu::{self, Clock, De, DpssTop, Enable, Reset};
 
 const DE_BASE: u32 = 0x0500_0000;
 const DE_SCLK_GATE: u32 = DE_BASE + 0x000;
@@ -6,6 +14,12 @@ const DE_HCLK_GATE: u32 = DE_BASE + 0x004;
 const DE_AHB_RESET: u32 = DE_BASE + 0x008;
 const DE_SCLK_DIV: u32 = DE_BASE + 0x00C;
 
+/// Fastest the DE core clocks may run
+const DE_SCLK_MAX_HZ: u32 = 300_000_000;
+
+/// Largest width or height mixer 0 handles
+pub const MAX_SIZE: u32 = 2048;
+
 const DE_MIXER0: u32 = DE_BASE + 0x0010_0000;
 const DE_M0_GLB: u32 = DE_MIXER0 + 0x0_0000;
 const DE_M0_BLD: u32 = DE_MIXER0 + 0x0_1000;
@@ -58,7 +72,7 @@ const DE_M0_UI1_SIZE: u32 = DE_M0_OVL_UI1 + 0x088;
 const DE_M0_BLD_FILL_COLOR_CTL: u32 = DE_M0_BLD + 0x000;
 const DE_M0_BLD_FILL_COLOR_P0: u32 = DE_M0_BLD + 0x004 + 0 * 0x14;
 const DE_M0_BLD_CH_ISIZE_P0: u32 = DE_M0_BLD + 0x008 + 0 * 0x14;
-const DE_M0_BLD_CH_OFFSET_P0: u32 = DE_M0_BLD + 0x008 + 0 * 0x14;
+const DE_M0_BLD_CH_OFFSET_P0: u32 = DE_M0_BLD + 0x00C + 0 * 0x14;
 const DE_M0_BLD_CH_RTCTL: u32 = DE_M0_BLD + 0x080;
 const DE_M0_BLD_PREMUL_CTL: u32 = DE_M0_BLD + 0x084;
 const DE_M0_BLD_BK_COLOR: u32 = DE_M0_BLD + 0x088;
@@ -70,8 +84,139 @@ const DE_M0_BLD_KEY_MAX: u32 = DE_M0_BLD + 0x0C0;
 const DE_M0_BLD_KEY_MIN: u32 = DE_M0_BLD + 0x0E0;
 const DE_M0_BLD_OUT_COLOR: u32 = DE_M0_BLD + 0x0FC;
 
-pub unsafe fn init(fb: &[u8]) {
+/// Framebuffer formats the UI overlay scans out, numbered as in its
+/// ATTCTL register
+///
+/// Names give the channels of a little-endian pixel word from the most
+/// significant end, so e.g. [`Bgr888`](PixelFormat::Bgr888) is stored as
+/// the bytes R, G, B.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    Argb8888 = 0x00,
+    Abgr8888 = 0x01,
+    Rgba8888 = 0x02,
+    Bgra8888 = 0x03,
+    Xrgb8888 = 0x04,
+    Xbgr8888 = 0x05,
+    Rgbx8888 = 0x06,
+    Bgrx8888 = 0x07,
+    Rgb888 = 0x08,
+    Bgr888 = 0x09,
+    Rgb565 = 0x0A,
+    Bgr565 = 0x0B,
+}
+
+impl PixelFormat {
+    pub const fn bytes_per_pixel(self) -> u32 {
+        match self {
+            PixelFormat::Rgb888 | PixelFormat::Bgr888 => 3,
+            PixelFormat::Rgb565 | PixelFormat::Bgr565 => 2,
+            _ => 4,
+        }
+    }
+
+    /// Whether the format's alpha channel is used, rather than the
+    /// overlay's global alpha
+    const fn has_alpha(self) -> bool {
+        matches!(
+            self,
+            PixelFormat::Argb8888
+                | PixelFormat::Abgr8888
+                | PixelFormat::Rgba8888
+                | PixelFormat::Bgra8888
+        )
+    }
+}
+
+/// Size and layout of the framebuffer, which is also the output size
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DisplayConfig {
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+    /// Bytes from the start of one line to the next
+    pub pitch: u32,
+}
+
+impl DisplayConfig {
+    /// A framebuffer with its lines packed together
+    ///
+    /// Sizes too large to scan out saturate the pitch; [`validate`]
+    /// rejects them.
+    ///
+    /// [`validate`]: DisplayConfig::validate
+    pub const fn new(width: u32, height: u32, format: PixelFormat) -> Self {
+        Self {
+            width,
+            height,
+            format,
+            pitch: width.saturating_mul(format.bytes_per_pixel()),
+        }
+    }
+
+    /// Bytes of framebuffer needed
+    pub const fn fb_len(&self) -> usize {
+        self.pitch as usize * self.height as usize
+    }
+
+    /// Check the config can be scanned out from `fb`
+    pub fn validate(&self, fb: &[u8]) -> Result<(), Error> {
+        if !(1..=MAX_SIZE).contains(&self.width) || !(1..=MAX_SIZE).contains(&self.height) {
+            return Err(Error::InvalidSize);
+        }
+        if self.pitch < self.width * self.format.bytes_per_pixel() {
+            return Err(Error::PitchTooSmall);
+        }
+        if fb.len() < self.fb_len() {
+            return Err(Error::BufferTooSmall);
+        }
+        Ok(())
+    }
+
+    /// Value for the mixer's size registers: height - 1 in the top half,
+    /// width - 1 in the bottom
+    fn size_reg(&self) -> u32 {
+        ((self.height - 1) << 16) | (self.width - 1)
+    }
+}
+
+/// Why [`init`] failed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The width or height is 0 or above [`MAX_SIZE`]
+    InvalidSize,
+    /// The pitch is shorter than a line of pixels
+    PitchTooSmall,
+    /// The framebuffer is smaller than pitch * height
+    BufferTooSmall,
+    /// The DE clock couldn't be set up
+    Clock(ccu::Error),
+}
+
+impl From<ccu::Error> for Error {
+    fn from(error: ccu::Error) -> Self {
+        Error::Clock(error)
+    }
+}
+
+/// Set up mixer 0 to scan `fb` out through UI overlay 1, sized and laid
+/// out as in `config`
+///
+/// # Safety
+///
+/// The DE keeps reading `fb` after this returns, so it must outlive the
+/// display being on.
+pub unsafe fn init(config: &DisplayConfig, fb: &[u8]) -> Result<(), Error> {
     use core::ptr::write_volatile;
+    config.validate(fb)?;
+    let size = config.size_reg();
+    // Clock the DE from PLL_PERI, which the boot ROM always brings up.
+    ccu::enable_de_clock()?;
+    DpssTop::enable();
+    DpssTop::deassert_reset();
+    De::enable();
+    De::deassert_reset();
+
     // Enable ROT_SCLK_GATE, RT_WB_SCLK_GATE, CORE1_SCLK_GATE, CORE0_SCLK_GATE
     // (at least, as they exist in the H8).
     write_volatile(DE_SCLK_GATE as *mut u32, 0xF);
@@ -83,13 +228,15 @@ pub unsafe fn init(fb: &[u8]) {
     // Bring ROT, RT_WB, CORE1, CORE0 out of reset.
     write_volatile(DE_AHB_RESET as *mut u32, 0xF);
 
-    // Hopefully default div is OK.
+    // Divide the CCU's DE clock down to what the mixers can run at.
+    let de_hz = ccu::frequency(Clock::De);
+    let div = de_hz.div_ceil(DE_SCLK_MAX_HZ).clamp(1, 16) - 1;
     write_volatile(
         DE_SCLK_DIV as *mut u32,
-        (0 << 12)     // ROT_SCLK_DIV
-        | (0 <<  8)     // RT_WB_SCLK_DIV
-        | (0 <<  4)     // CORE1_SCLK_DIV
-        | (0 <<  0), // CORE0_SCLK_DIV
+        (div << 12)     // ROT_SCLK_DIV
+        | (div <<  8)     // RT_WB_SCLK_DIV
+        | (div <<  4)     // CORE1_SCLK_DIV
+        | (div <<  0), // CORE0_SCLK_DIV
     );
 
     // Hopefully DE2TCON_MUX either doesn't exist or default is fine.
@@ -98,38 +245,87 @@ pub unsafe fn init(fb: &[u8]) {
 
     // Enable RT
     write_volatile(DE_M0_GLB_CTL as *mut u32, 1);
-    // Set height=272 and width=480
-    write_volatile(DE_M0_GLB_SIZE as *mut u32, (271 << 16) | (479 << 0));
+    // Set the output size
+    write_volatile(DE_M0_GLB_SIZE as *mut u32, size);
 
-    // Set OVL_UI1_L0 to alpha=FF, top-addr-only, no-premult, BGR888, no fill, global alpha, enable
-    // NB not sure why BGR is required, since data is in RGB...??
+    // Set OVL_UI1_L0 to alpha=FF, top-addr-only, no-premult, the config's
+    // format, no fill, pixel or global alpha, enable
+    let alpha_mode = if config.format.has_alpha() { 0 } else { 1 };
     write_volatile(
         DE_M0_UI1_ATTCTL_L0 as *mut u32,
-        (0xFF << 24) | (0 << 23) | (0 << 16) | (0x09 << 8) | (0 << 4) | (1 << 1) | (1 << 0),
+        (0xFF << 24)
+            | (0 << 23)
+            | (0 << 16)
+            | ((config.format as u32) << 8)
+            | (0 << 4)
+            | (alpha_mode << 1)
+            | (1 << 0),
     );
-    // Set OVL_UI1_L0 to height=272 width=480
-    write_volatile(DE_M0_UI1_MBSIZE_L0 as *mut u32, (271 << 16) | (479 << 0));
+    // Set OVL_UI1_L0 to the framebuffer size
+    write_volatile(DE_M0_UI1_MBSIZE_L0 as *mut u32, size);
     // Set OVL_UI1_L0 coordinate to 0, 0
     write_volatile(DE_M0_UI1_COOR_L0 as *mut u32, (0 << 16) | (0 << 0));
-    // Set OVL_UI1_L0 pitch to 480*3 bytes/line.
-    write_volatile(DE_M0_UI1_PITCH_L0 as *mut u32, 480 * 3);
+    // Set OVL_UI1_L0 pitch in bytes/line
+    write_volatile(DE_M0_UI1_PITCH_L0 as *mut u32, config.pitch);
     // Set memory start address
-    write_volatile(DE_M0_UI1_TOP_LADD_L0 as *mut u32, fb.as_ptr() as u32);
-    write_volatile(DE_M0_UI1_TOP_HADD as *mut u32, 0);
-    // Set overlay to 272x480
-    write_volatile(DE_M0_UI1_SIZE as *mut u32, (271 << 16) | (479 << 0));
+    let addr = fb.as_ptr() as usize as u64;
+    write_volatile(DE_M0_UI1_TOP_LADD_L0 as *mut u32, addr as u32);
+    write_volatile(DE_M0_UI1_TOP_HADD as *mut u32, (addr >> 32) as u32 & 0xFF);
+    // Set overlay size
+    write_volatile(DE_M0_UI1_SIZE as *mut u32, size);
 
     // Enable Pipe0, no fill
     write_volatile(DE_M0_BLD_FILL_COLOR_CTL as *mut u32, 1 << 8);
-    // Pipe0 Input size 272x480
-    write_volatile(DE_M0_BLD_CH_ISIZE_P0 as *mut u32, (271 << 16) | (479 << 0));
-    // Pipe 0 offset apparently needs to be 271,479? Not sure why.
-    write_volatile(DE_M0_BLD_CH_OFFSET_P0 as *mut u32, (271 << 16) | (479 << 0));
+    // Pipe0 input size, placed at the top left
+    write_volatile(DE_M0_BLD_CH_ISIZE_P0 as *mut u32, size);
+    write_volatile(DE_M0_BLD_CH_OFFSET_P0 as *mut u32, 0);
     // Pipe 0 select from channel 1, pipe 1 from 0, pipe 2 from 2, pipe 3 from 3
     write_volatile(
         DE_M0_BLD_CH_RTCTL as *mut u32,
         (3 << 12) | (2 << 8) | (0 << 4) | (1 << 0),
     );
-    // Output size 272x480
-    write_volatile(DE_M0_BLD_SIZE as *mut u32, (271 << 16) | (479 << 0));
+    // Output size
+    write_volatile(DE_M0_BLD_SIZE as *mut u32, size);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packed_config() {
+        let config = DisplayConfig::new(480, 272, PixelFormat::Rgb565);
+        assert_eq!(config.pitch, 960);
+        assert_eq!(config.fb_len(), 960 * 272);
+        let fb = [0; 960 * 272];
+        assert_eq!(config.validate(&fb), Ok(()));
+        assert_eq!(config.validate(&fb[1..]), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        let fb = [0; 16];
+        for (width, height) in [(0, 1), (1, 0), (MAX_SIZE + 1, 1), (1, MAX_SIZE + 1)] {
+            let config = DisplayConfig::new(width, height, PixelFormat::Argb8888);
+            assert_eq!(config.validate(&fb), Err(Error::InvalidSize));
+        }
+        // Too wide to multiply out
+        let config = DisplayConfig::new(u32::MAX, 1, PixelFormat::Argb8888);
+        assert_eq!(config.pitch, u32::MAX);
+        assert_eq!(config.validate(&fb), Err(Error::InvalidSize));
+    }
+
+    #[test]
+    fn pitch() {
+        let fb = [0; 4 * 4 * 2];
+        let mut config = DisplayConfig::new(2, 2, PixelFormat::Bgr888);
+        config.pitch = 5;
+        assert_eq!(config.validate(&fb), Err(Error::PitchTooSmall));
+        // Padded lines
+        config.pitch = 16;
+        assert_eq!(config.validate(&fb), Ok(()));
+        config.height = 3;
+        assert_eq!(config.validate(&fb), Err(Error::BufferTooSmall));
+    }
 }
@@ -0,0 +1,579 @@
+//! Minimal RV64IMAC instruction decoder and disassembler
+//!
+//! Compressed instructions are expanded to their 32-bit equivalent first,
+//! so everything else only has to deal with the base encodings.
+
+use core::fmt;
+
+/// ABI names of the integer registers
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+const OP_LOAD: u32 = 0x03;
+const OP_MISC_MEM: u32 = 0x0F;
+const OP_IMM: u32 = 0x13;
+const OP_AUIPC: u32 = 0x17;
+const OP_IMM_32: u32 = 0x1B;
+const OP_STORE: u32 = 0x23;
+const OP_AMO: u32 = 0x2F;
+const OP_OP: u32 = 0x33;
+const OP_LUI: u32 = 0x37;
+const OP_OP_32: u32 = 0x3B;
+const OP_BRANCH: u32 = 0x63;
+const OP_JALR: u32 = 0x67;
+const OP_JAL: u32 = 0x6F;
+const OP_SYSTEM: u32 = 0x73;
+
+/// Length in bytes of the instruction whose low half-word is `low`
+#[inline]
+pub fn insn_len(low: u16) -> usize {
+    if low & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Read the instruction at `pc`, returning it and its length
+///
+/// Compressed instructions are returned as-is (not expanded).
+///
+/// # Safety
+///
+/// `pc` must be 2-byte aligned and readable.
+pub unsafe fn fetch(pc: usize) -> (u32, usize) {
+    // Read in half-words: a 32-bit instruction is only 2-byte aligned.
+    let low = core::ptr::read_volatile(pc as *const u16);
+    match insn_len(low) {
+        4 => {
+            let high = core::ptr::read_volatile((pc + 2) as *const u16);
+            (low as u32 | (high as u32) << 16, 4)
+        }
+        _ => (low as u32, 2),
+    }
+}
+
+/// A memory access performed by a load or store instruction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Load {
+        rd: usize,
+        rs1: usize,
+        offset: i64,
+        width: usize,
+        signed: bool,
+    },
+    Store {
+        rs1: usize,
+        rs2: usize,
+        offset: i64,
+        width: usize,
+    },
+}
+
+/// Decode an (expanded) integer load or store
+pub fn decode_access(insn: u32) -> Option<Access> {
+    let f3 = funct3(insn);
+    match opcode(insn) {
+        OP_LOAD if f3 != 7 => Some(Access::Load {
+            rd: rd(insn),
+            rs1: rs1(insn),
+            offset: imm_i(insn),
+            width: 1 << (f3 & 0b11),
+            signed: f3 & 0b100 == 0,
+        }),
+        OP_STORE if f3 < 4 => Some(Access::Store {
+            rs1: rs1(insn),
+            rs2: rs2(insn),
+            offset: imm_s(insn),
+            width: 1 << f3,
+        }),
+        _ => None,
+    }
+}
+
+/// Where execution can go after the (expanded) instruction at `pc`
+///
+/// `len` is the length of the instruction as fetched, and `regs` the
+/// register file, for `jalr`. Branches have two possible successors, which
+/// may be the same; everything else has one. Traps and `mret` are not
+/// followed.
+pub fn successors(insn: u32, pc: usize, len: usize, regs: &[usize; 32]) -> [Option<usize>; 2] {
+    let next = pc.wrapping_add(len);
+    match opcode(insn) {
+        OP_JAL => [Some(pc.wrapping_add(imm_j(insn) as usize)), None],
+        OP_JALR => [
+            Some(regs[rs1(insn)].wrapping_add(imm_i(insn) as usize) & !1),
+            None,
+        ],
+        OP_BRANCH => [Some(next), Some(pc.wrapping_add(imm_b(insn) as usize))],
+        _ => [Some(next), None],
+    }
+}
+
+/// Expand a 16-bit compressed instruction to its 32-bit equivalent
+///
+/// Returns `None` for reserved encodings and the floating-point loads and
+/// stores, which this core has no use for.
+pub fn expand_compressed(c: u16) -> Option<u32> {
+    let c = c as u32;
+    let bits = |hi: u32, lo: u32| (c >> lo) & ((1 << (hi - lo + 1)) - 1);
+    let bit = |n: u32| (c >> n) & 1;
+    // Registers x8..x15 in the 3-bit fields
+    let rd_p = bits(4, 2) + 8;
+    let rs1_p = bits(9, 7) + 8;
+    let rd = bits(11, 7);
+    let rs2 = bits(6, 2);
+    // The 6-bit signed immediate shared by many quadrant 1 instructions
+    let imm6 = sext((bit(12) << 5 | bits(6, 2)) as u64, 6);
+
+    let insn = match (c & 0b11, bits(15, 13)) {
+        (0b00, 0b000) => {
+            let imm = bits(12, 11) << 4 | bits(10, 7) << 6 | bit(6) << 2 | bit(5) << 3;
+            if imm == 0 {
+                return None;
+            }
+            enc_i(OP_IMM, 0, rd_p, 2, imm as i64)
+        }
+        (0b00, 0b010) => {
+            let imm = bits(12, 10) << 3 | bit(6) << 2 | bit(5) << 6;
+            enc_i(OP_LOAD, 2, rd_p, rs1_p, imm as i64)
+        }
+        (0b00, 0b011) => {
+            let imm = bits(12, 10) << 3 | bits(6, 5) << 6;
+            enc_i(OP_LOAD, 3, rd_p, rs1_p, imm as i64)
+        }
+        (0b00, 0b110) => {
+            let imm = bits(12, 10) << 3 | bit(6) << 2 | bit(5) << 6;
+            enc_s(2, rs1_p, rd_p, imm as i64)
+        }
+        (0b00, 0b111) => {
+            let imm = bits(12, 10) << 3 | bits(6, 5) << 6;
+            enc_s(3, rs1_p, rd_p, imm as i64)
+        }
+        (0b01, 0b000) => enc_i(OP_IMM, 0, rd, rd, imm6),
+        (0b01, 0b001) if rd != 0 => enc_i(OP_IMM_32, 0, rd, rd, imm6),
+        (0b01, 0b010) => enc_i(OP_IMM, 0, rd, 0, imm6),
+        (0b01, 0b011) if rd == 2 => {
+            let imm = bit(12) << 9 | bit(6) << 4 | bit(5) << 6 | bits(4, 3) << 7 | bit(2) << 5;
+            let imm = sext(imm as u64, 10);
+            if imm == 0 {
+                return None;
+            }
+            enc_i(OP_IMM, 0, 2, 2, imm)
+        }
+        (0b01, 0b011) => {
+            if imm6 == 0 {
+                return None;
+            }
+            (imm6 as u32) << 12 | rd << 7 | OP_LUI
+        }
+        (0b01, 0b100) => {
+            let shamt = bit(12) << 5 | bits(6, 2);
+            match bits(11, 10) {
+                0b00 => enc_i(OP_IMM, 5, rs1_p, rs1_p, shamt as i64),
+                0b01 => enc_i(OP_IMM, 5, rs1_p, rs1_p, (0x400 | shamt) as i64),
+                0b10 => enc_i(OP_IMM, 7, rs1_p, rs1_p, imm6),
+                _ => {
+                    let (op, f7, f3) = match (bit(12), bits(6, 5)) {
+                        (0, 0b00) => (OP_OP, 0x20, 0),
+                        (0, 0b01) => (OP_OP, 0, 4),
+                        (0, 0b10) => (OP_OP, 0, 6),
+                        (0, 0b11) => (OP_OP, 0, 7),
+                        (1, 0b00) => (OP_OP_32, 0x20, 0),
+                        (1, 0b01) => (OP_OP_32, 0, 0),
+                        _ => return None,
+                    };
+                    enc_r(op, f3, f7, rs1_p, rs1_p, rd_p)
+                }
+            }
+        }
+        (0b01, 0b101) => {
+            let imm = bit(12) << 11
+                | bit(11) << 4
+                | bits(10, 9) << 8
+                | bit(8) << 10
+                | bit(7) << 6
+                | bit(6) << 7
+                | bits(5, 3) << 1
+                | bit(2) << 5;
+            enc_j(0, sext(imm as u64, 12))
+        }
+        (0b01, f3 @ (0b110 | 0b111)) => {
+            let imm =
+                bit(12) << 8 | bits(11, 10) << 3 | bits(6, 5) << 6 | bits(4, 3) << 1 | bit(2) << 5;
+            enc_b(f3 & 1, rs1_p, 0, sext(imm as u64, 9))
+        }
+        (0b10, 0b000) => enc_i(OP_IMM, 1, rd, rd, (bit(12) << 5 | bits(6, 2)) as i64),
+        (0b10, 0b010) if rd != 0 => {
+            let imm = bit(12) << 5 | bits(6, 4) << 2 | bits(3, 2) << 6;
+            enc_i(OP_LOAD, 2, rd, 2, imm as i64)
+        }
+        (0b10, 0b011) if rd != 0 => {
+            let imm = bit(12) << 5 | bits(6, 5) << 3 | bits(4, 2) << 6;
+            enc_i(OP_LOAD, 3, rd, 2, imm as i64)
+        }
+        (0b10, 0b100) => match (bit(12), rd, rs2) {
+            (0, 0, _) => return None,
+            (0, _, 0) => enc_i(OP_JALR, 0, 0, rd, 0),
+            (0, _, _) => enc_r(OP_OP, 0, 0, rd, 0, rs2),
+            (1, 0, 0) => 0x0010_0073,
+            (1, _, 0) => enc_i(OP_JALR, 0, 1, rd, 0),
+            (_, _, _) => enc_r(OP_OP, 0, 0, rd, rd, rs2),
+        },
+        (0b10, 0b110) => {
+            let imm = bits(12, 9) << 2 | bits(8, 7) << 6;
+            enc_s(2, 2, rs2, imm as i64)
+        }
+        (0b10, 0b111) => {
+            let imm = bits(12, 10) << 3 | bits(9, 7) << 6;
+            enc_s(3, 2, rs2, imm as i64)
+        }
+        _ => return None,
+    };
+    Some(insn)
+}
+
+/// Disassembly of one instruction, for `Display`
+///
+/// Branch and jump targets are shown as absolute addresses relative to `pc`.
+pub struct Disasm {
+    pub insn: u32,
+    pub pc: usize,
+}
+
+impl fmt::Display for Disasm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw = self.insn;
+        let insn = if insn_len(raw as u16) == 2 {
+            match expand_compressed(raw as u16) {
+                Some(insn) => {
+                    f.write_str("c.")?;
+                    insn
+                }
+                None => return write!(f, ".2byte {:#06x}", raw as u16),
+            }
+        } else {
+            raw
+        };
+
+        let uimm = rs1(insn);
+        let r = |n: usize| REG_NAMES[n];
+        let (rd, rs1, rs2) = (r(rd(insn)), r(rs1(insn)), r(rs2(insn)));
+        let f3 = funct3(insn);
+        let f7 = insn >> 25;
+        let target = |off: i64| self.pc.wrapping_add(off as usize);
+
+        match opcode(insn) {
+            OP_LUI => write!(f, "lui {}, {:#x}", rd, insn >> 12),
+            OP_AUIPC => write!(f, "auipc {}, {:#x}", rd, insn >> 12),
+            OP_JAL => write!(f, "jal {}, {:#x}", rd, target(imm_j(insn))),
+            OP_JALR => write!(f, "jalr {}, {}({})", rd, imm_i(insn), rs1),
+            OP_BRANCH => {
+                let name = match f3 {
+                    0 => "beq",
+                    1 => "bne",
+                    4 => "blt",
+                    5 => "bge",
+                    6 => "bltu",
+                    7 => "bgeu",
+                    _ => return unknown(f, insn),
+                };
+                write!(f, "{} {}, {}, {:#x}", name, rs1, rs2, target(imm_b(insn)))
+            }
+            OP_LOAD => {
+                let name = match f3 {
+                    0 => "lb",
+                    1 => "lh",
+                    2 => "lw",
+                    3 => "ld",
+                    4 => "lbu",
+                    5 => "lhu",
+                    6 => "lwu",
+                    _ => return unknown(f, insn),
+                };
+                write!(f, "{} {}, {}({})", name, rd, imm_i(insn), rs1)
+            }
+            OP_STORE => {
+                let name = match f3 {
+                    0 => "sb",
+                    1 => "sh",
+                    2 => "sw",
+                    3 => "sd",
+                    _ => return unknown(f, insn),
+                };
+                write!(f, "{} {}, {}({})", name, rs2, imm_s(insn), rs1)
+            }
+            OP_IMM => {
+                let shamt = (insn >> 20) & 0x3F;
+                match f3 {
+                    0 => write!(f, "addi {}, {}, {}", rd, rs1, imm_i(insn)),
+                    1 => write!(f, "slli {}, {}, {}", rd, rs1, shamt),
+                    2 => write!(f, "slti {}, {}, {}", rd, rs1, imm_i(insn)),
+                    3 => write!(f, "sltiu {}, {}, {}", rd, rs1, imm_i(insn)),
+                    4 => write!(f, "xori {}, {}, {}", rd, rs1, imm_i(insn)),
+                    5 if insn & (1 << 30) != 0 => write!(f, "srai {}, {}, {}", rd, rs1, shamt),
+                    5 => write!(f, "srli {}, {}, {}", rd, rs1, shamt),
+                    6 => write!(f, "ori {}, {}, {}", rd, rs1, imm_i(insn)),
+                    _ => write!(f, "andi {}, {}, {}", rd, rs1, imm_i(insn)),
+                }
+            }
+            OP_IMM_32 => {
+                let shamt = (insn >> 20) & 0x1F;
+                match f3 {
+                    0 => write!(f, "addiw {}, {}, {}", rd, rs1, imm_i(insn)),
+                    1 => write!(f, "slliw {}, {}, {}", rd, rs1, shamt),
+                    5 if insn & (1 << 30) != 0 => write!(f, "sraiw {}, {}, {}", rd, rs1, shamt),
+                    5 => write!(f, "srliw {}, {}, {}", rd, rs1, shamt),
+                    _ => unknown(f, insn),
+                }
+            }
+            OP_OP => {
+                let name = match (f7, f3) {
+                    (0x00, 0) => "add",
+                    (0x20, 0) => "sub",
+                    (0x00, 1) => "sll",
+                    (0x00, 2) => "slt",
+                    (0x00, 3) => "sltu",
+                    (0x00, 4) => "xor",
+                    (0x00, 5) => "srl",
+                    (0x20, 5) => "sra",
+                    (0x00, 6) => "or",
+                    (0x00, 7) => "and",
+                    (0x01, 0) => "mul",
+                    (0x01, 1) => "mulh",
+                    (0x01, 2) => "mulhsu",
+                    (0x01, 3) => "mulhu",
+                    (0x01, 4) => "div",
+                    (0x01, 5) => "divu",
+                    (0x01, 6) => "rem",
+                    (0x01, 7) => "remu",
+                    _ => return unknown(f, insn),
+                };
+                write!(f, "{} {}, {}, {}", name, rd, rs1, rs2)
+            }
+            OP_OP_32 => {
+                let name = match (f7, f3) {
+                    (0x00, 0) => "addw",
+                    (0x20, 0) => "subw",
+                    (0x00, 1) => "sllw",
+                    (0x00, 5) => "srlw",
+                    (0x20, 5) => "sraw",
+                    (0x01, 0) => "mulw",
+                    (0x01, 4) => "divw",
+                    (0x01, 5) => "divuw",
+                    (0x01, 6) => "remw",
+                    (0x01, 7) => "remuw",
+                    _ => return unknown(f, insn),
+                };
+                write!(f, "{} {}, {}, {}", name, rd, rs1, rs2)
+            }
+            OP_AMO => {
+                let width = match f3 {
+                    2 => "w",
+                    3 => "d",
+                    _ => return unknown(f, insn),
+                };
+                let name = match insn >> 27 {
+                    0x00 => "amoadd",
+                    0x01 => "amoswap",
+                    0x02 => return write!(f, "lr.{} {}, ({})", width, rd, rs1),
+                    0x03 => "sc",
+                    0x04 => "amoxor",
+                    0x08 => "amoor",
+                    0x0C => "amoand",
+                    0x10 => "amomin",
+                    0x14 => "amomax",
+                    0x18 => "amominu",
+                    0x1C => "amomaxu",
+                    _ => return unknown(f, insn),
+                };
+                write!(f, "{}.{} {}, {}, ({})", name, width, rd, rs2, rs1)
+            }
+            OP_MISC_MEM => match f3 {
+                0 => f.write_str("fence"),
+                1 => f.write_str("fence.i"),
+                _ => unknown(f, insn),
+            },
+            OP_SYSTEM => {
+                let csr = insn >> 20;
+                match (f3, insn) {
+                    (0, 0x0000_0073) => f.write_str("ecall"),
+                    (0, 0x0010_0073) => f.write_str("ebreak"),
+                    (0, 0x1020_0073) => f.write_str("sret"),
+                    (0, 0x3020_0073) => f.write_str("mret"),
+                    (0, 0x1050_0073) => f.write_str("wfi"),
+                    (1, _) => write!(f, "csrrw {}, {:#x}, {}", rd, csr, rs1),
+                    (2, _) => write!(f, "csrrs {}, {:#x}, {}", rd, csr, rs1),
+                    (3, _) => write!(f, "csrrc {}, {:#x}, {}", rd, csr, rs1),
+                    (5, _) => write!(f, "csrrwi {}, {:#x}, {}", rd, csr, uimm),
+                    (6, _) => write!(f, "csrrsi {}, {:#x}, {}", rd, csr, uimm),
+                    (7, _) => write!(f, "csrrci {}, {:#x}, {}", rd, csr, uimm),
+                    _ => unknown(f, insn),
+                }
+            }
+            _ => unknown(f, insn),
+        }
+    }
+}
+
+fn unknown(f: &mut fmt::Formatter<'_>, insn: u32) -> fmt::Result {
+    write!(f, ".4byte {:#010x}", insn)
+}
+
+// Field extraction
+
+#[inline]
+fn opcode(insn: u32) -> u32 {
+    insn & 0x7F
+}
+
+#[inline]
+fn funct3(insn: u32) -> u32 {
+    (insn >> 12) & 0b111
+}
+
+#[inline]
+fn rd(insn: u32) -> usize {
+    ((insn >> 7) & 0x1F) as usize
+}
+
+#[inline]
+fn rs1(insn: u32) -> usize {
+    ((insn >> 15) & 0x1F) as usize
+}
+
+#[inline]
+fn rs2(insn: u32) -> usize {
+    ((insn >> 20) & 0x1F) as usize
+}
+
+#[inline]
+fn sext(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+#[inline]
+fn imm_i(insn: u32) -> i64 {
+    (insn as i32 >> 20) as i64
+}
+
+#[inline]
+fn imm_s(insn: u32) -> i64 {
+    (((insn as i32 >> 25) << 5) | ((insn >> 7) & 0x1F) as i32) as i64
+}
+
+#[inline]
+fn imm_b(insn: u32) -> i64 {
+    let imm = (insn >> 31) << 12
+        | ((insn >> 7) & 1) << 11
+        | ((insn >> 25) & 0x3F) << 5
+        | ((insn >> 8) & 0xF) << 1;
+    sext(imm as u64, 13)
+}
+
+#[inline]
+fn imm_j(insn: u32) -> i64 {
+    let imm = (insn >> 31) << 20
+        | ((insn >> 12) & 0xFF) << 12
+        | ((insn >> 20) & 1) << 11
+        | ((insn >> 21) & 0x3FF) << 1;
+    sext(imm as u64, 21)
+}
+
+// Encoding, for expanding compressed instructions
+
+fn enc_r(op: u32, f3: u32, f7: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    f7 << 25 | rs2 << 20 | rs1 << 15 | f3 << 12 | rd << 7 | op
+}
+
+fn enc_i(op: u32, f3: u32, rd: u32, rs1: u32, imm: i64) -> u32 {
+    (imm as u32 & 0xFFF) << 20 | rs1 << 15 | f3 << 12 | rd << 7 | op
+}
+
+fn enc_s(f3: u32, rs1: u32, rs2: u32, imm: i64) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) & 0x7F) << 25 | rs2 << 20 | rs1 << 15 | f3 << 12 | (imm & 0x1F) << 7 | OP_STORE
+}
+
+fn enc_b(f3: u32, rs1: u32, rs2: u32, imm: i64) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 12) & 1) << 31
+        | ((imm >> 5) & 0x3F) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | f3 << 12
+        | ((imm >> 1) & 0xF) << 8
+        | ((imm >> 11) & 1) << 7
+        | OP_BRANCH
+}
+
+fn enc_j(rd: u32, imm: i64) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 20) & 1) << 31
+        | ((imm >> 1) & 0x3FF) << 21
+        | ((imm >> 11) & 1) << 20
+        | ((imm >> 12) & 0xFF) << 12
+        | rd << 7
+        | OP_JAL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand() {
+        let cases: &[(u16, u32)] = &[
+            // c.addi a0, 1
+            (0x0505, 0x0015_0513),
+            // c.addi a0, -1
+            (0x157D, 0xFFF5_0513),
+            // c.lw a0, 4(a1)
+            (0x41C8, 0x0045_A503),
+            // c.j 8
+            (0xA021, 0x0080_006F),
+            // c.beqz a0, 16
+            (0xC901, 0x0005_0863),
+        ];
+        for &(c, insn) in cases {
+            assert_eq!(expand_compressed(c), Some(insn), "{:#06x}", c);
+        }
+        // Illegal instruction
+        assert_eq!(expand_compressed(0), None);
+    }
+
+    #[test]
+    fn branch_and_jump_targets() {
+        let mut regs = [0; 32];
+        regs[15] = 0x4000_0101;
+        let cases: &[(u32, usize, [Option<usize>; 2])] = &[
+            // addi a0, a0, 1
+            (0x0015_0513, 4, [Some(0x1004), None]),
+            // c.j 8
+            (expand_compressed(0xA021).unwrap(), 2, [Some(0x1008), None]),
+            // c.beqz a0, 16
+            (
+                expand_compressed(0xC901).unwrap(),
+                2,
+                [Some(0x1002), Some(0x1010)],
+            ),
+            // jal ra, -8
+            (0xFF9F_F0EF, 4, [Some(0x0FF8), None]),
+            // jalr ra, 0(a5)
+            (0x0007_80E7, 4, [Some(0x4000_0100), None]),
+        ];
+        for &(insn, len, targets) in cases {
+            assert_eq!(
+                successors(insn, 0x1000, len, &regs),
+                targets,
+                "{:#010x}",
+                insn
+            );
+        }
+    }
+}
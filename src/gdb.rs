@@ -0,0 +1,636 @@
+//! GDB remote serial protocol stub
+//!
+//! Runs inside the exception handler as a [`FaultHook`], talking to GDB on
+//! UART0. Once [`install`]ed, any exception (including a breakpoint) stops
+//! the program and waits for GDB:
+//!
+//! ```text
+//! $ riscv64-elf-gdb target/riscv64imac-unknown-none-elf/release/d1-playground
+//! (gdb) set serial baud 115200
+//! (gdb) target remote /dev/ttyUSB0
+//! ```
+//!
+//! To attach, the program has to stop first: call [`breakpoint`], or
+//! [`poll`] regularly so that GDB connecting (or Ctrl-C) breaks in.
+//!
+//! Supported are register and memory access, software breakpoints (`Z0`,
+//! using `ebreak`), continue, and single-stepping. There is no hardware
+//! single-step in M-mode, so a step places temporary breakpoints on every
+//! possible successor of the current instruction and continues.
+//!
+//! The UART is stolen while stopped, so console output from the program
+//! will confuse GDB; [`logger::set_sink`](crate::logger::set_sink) can send
+//! logs elsewhere.
+
+use core::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use d1_pac::UART0;
+use riscv::{interrupt::Mutex, register::mcause::Exception};
+
+use crate::{
+    console, disasm,
+    trap::{self, TrapFrame},
+    uart::Uart,
+};
+
+/// Maximum number of breakpoints set by GDB
+pub const MAX_BREAKPOINTS: usize = 16;
+
+/// Packet buffer size, also advertised to GDB
+const PACKET_SIZE: usize = 1024;
+
+const EBREAK: u32 = 0x0010_0073;
+const C_EBREAK: u16 = 0x9002;
+
+/// `pc` in GDB's RISC-V register numbering, after x0-x31
+const PC_REGNUM: usize = 32;
+
+/// Ctrl-C, sent by GDB to interrupt the program
+const INTERRUPT: u8 = 0x03;
+
+#[derive(Copy, Clone)]
+struct Breakpoint {
+    addr: usize,
+    /// 2 for `c.ebreak`, 4 for `ebreak`
+    len: usize,
+    orig: u32,
+}
+
+struct State {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// Temporary breakpoints for a single-step
+    step: [Option<Breakpoint>; 2],
+}
+
+static STATE: Mutex<RefCell<State>> = Mutex::new(RefCell::new(State {
+    breakpoints: [None; MAX_BREAKPOINTS],
+    step: [None; 2],
+}));
+
+/// Set while the stub accesses memory on GDB's behalf, so faults are
+/// reported as errors instead of re-entering the stub
+static PROBING: AtomicBool = AtomicBool::new(false);
+static PROBE_FAULT: AtomicBool = AtomicBool::new(false);
+
+/// Install the stub as the fault hook
+pub fn install() {
+    trap::set_fault_hook(Some(fault_hook));
+}
+
+/// Stop and wait for GDB
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe { riscv::asm::ebreak() };
+}
+
+/// Break in if GDB has sent something on the console
+///
+/// Other input is left for the console's readers. A packet that arrives
+/// while running is lost, but GDB retransmits it once the stub is
+/// listening.
+pub fn poll() {
+    if let Some(INTERRUPT | b'$') = console::peek() {
+        console::take_peeked();
+        breakpoint();
+    }
+}
+
+/// The [`FaultHook`](trap::FaultHook) running the stub
+pub fn fault_hook(frame: &mut TrapFrame, exception: Exception, _mtval: usize) -> bool {
+    if PROBING.load(Ordering::SeqCst) {
+        PROBE_FAULT.store(true, Ordering::SeqCst);
+        let (_, len) = unsafe { disasm::fetch(frame.mepc) };
+        frame.mepc += len;
+        return true;
+    }
+
+    // Faults while probing memory trap again, and that trap's `mret` leaves
+    // MPP at U-mode and MPIE set; keep the stopped program's trap state for
+    // when it resumes.
+    let csrs = TrapCsrs::save();
+    riscv::interrupt::free(|cs| {
+        let mut state = STATE.borrow(cs).borrow_mut();
+        let mut stub = Stub {
+            uart: unsafe { Uart::<UART0>::steal() },
+            state: &mut *state,
+        };
+        stub.stopped(frame, exception);
+    });
+    csrs.restore();
+    true
+}
+
+/// The trap CSRs a nested trap overwrites
+#[derive(Default)]
+#[cfg_attr(not(target_arch = "riscv64"), allow(dead_code))]
+struct TrapCsrs {
+    mstatus: usize,
+    mepc: usize,
+    mcause: usize,
+}
+
+impl TrapCsrs {
+    fn save() -> Self {
+        #[allow(unused_mut)]
+        let mut csrs = Self::default();
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            core::arch::asm!(
+                "csrr {0}, mstatus",
+                "csrr {1}, mepc",
+                "csrr {2}, mcause",
+                out(reg) csrs.mstatus,
+                out(reg) csrs.mepc,
+                out(reg) csrs.mcause,
+            );
+        }
+        csrs
+    }
+
+    fn restore(&self) {
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            core::arch::asm!(
+                "csrw mstatus, {0}",
+                "csrw mepc, {1}",
+                "csrw mcause, {2}",
+                in(reg) self.mstatus,
+                in(reg) self.mepc,
+                in(reg) self.mcause,
+            );
+        }
+    }
+}
+
+/// Run `f` with memory faults caught, returning `None` if one happened
+fn probe<R>(f: impl FnOnce() -> R) -> Option<R> {
+    PROBE_FAULT.store(false, Ordering::SeqCst);
+    PROBING.store(true, Ordering::SeqCst);
+    let res = f();
+    PROBING.store(false, Ordering::SeqCst);
+    (!PROBE_FAULT.load(Ordering::SeqCst)).then_some(res)
+}
+
+fn read_mem(addr: usize) -> Option<u8> {
+    probe(|| unsafe { (addr as *const u8).read_volatile() })
+}
+
+fn write_mem(addr: usize, value: u8) -> Option<()> {
+    probe(|| unsafe { (addr as *mut u8).write_volatile(value) })
+}
+
+/// Replace the instruction at `addr` with an `ebreak` of length `len`
+fn insert(addr: usize, len: usize) -> Option<Breakpoint> {
+    let orig = probe(|| unsafe {
+        let (insn, _) = disasm::fetch(addr);
+        match len {
+            2 => (addr as *mut u16).write_volatile(C_EBREAK),
+            _ => {
+                (addr as *mut u16).write_volatile(EBREAK as u16);
+                ((addr + 2) as *mut u16).write_volatile((EBREAK >> 16) as u16);
+            }
+        }
+        insn
+    })?;
+    fence_i();
+    Some(Breakpoint { addr, len, orig })
+}
+
+fn remove(bp: Breakpoint) {
+    probe(|| unsafe {
+        (bp.addr as *mut u16).write_volatile(bp.orig as u16);
+        if bp.len == 4 {
+            ((bp.addr + 2) as *mut u16).write_volatile((bp.orig >> 16) as u16);
+        }
+    });
+    fence_i();
+}
+
+/// Make patched code visible to instruction fetch
+fn fence_i() {
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!("fence.i");
+    }
+}
+
+/// Signal number reported to GDB for `exception`
+fn signal(exception: Exception) -> u8 {
+    match exception {
+        Exception::IllegalInstruction => 4,
+        Exception::InstructionMisaligned
+        | Exception::LoadMisaligned
+        | Exception::StoreMisaligned => 7,
+        Exception::InstructionFault
+        | Exception::LoadFault
+        | Exception::StoreFault
+        | Exception::InstructionPageFault
+        | Exception::LoadPageFault
+        | Exception::StorePageFault => 11,
+        _ => 5,
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a hex number from the start of `s`, returning it and the rest
+fn parse_hex(s: &[u8]) -> Option<(usize, &[u8])> {
+    let end = s
+        .iter()
+        .position(|&c| hex_digit(c).is_none())
+        .unwrap_or(s.len());
+    if end == 0 {
+        return None;
+    }
+    let value = s[..end]
+        .iter()
+        .fold(0usize, |acc, &c| acc << 4 | hex_digit(c).unwrap() as usize);
+    Some((value, &s[end..]))
+}
+
+/// Parse a register value sent as little-endian hex bytes
+fn parse_reg(s: &[u8]) -> Option<usize> {
+    if s.len() < 16 {
+        return None;
+    }
+    let mut value = 0usize;
+    for (i, pair) in s[..16].chunks(2).enumerate() {
+        let byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        value |= (byte as usize) << (8 * i);
+    }
+    Some(value)
+}
+
+/// Builds a reply packet
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Self {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn str(mut self, s: &str) -> Self {
+        for &b in s.as_bytes() {
+            self.byte(b);
+        }
+        self
+    }
+
+    fn byte(&mut self, b: u8) {
+        if let Some(slot) = self.buf.get_mut(self.len) {
+            *slot = b;
+            self.len += 1;
+        }
+    }
+
+    fn hex_byte(&mut self, b: u8) {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        self.byte(DIGITS[(b >> 4) as usize]);
+        self.byte(DIGITS[(b & 0xF) as usize]);
+    }
+
+    fn reg(&mut self, value: usize) {
+        for b in value.to_le_bytes() {
+            self.hex_byte(b);
+        }
+    }
+}
+
+/// What to do after handling a packet
+enum Action {
+    Reply(Reply),
+    Resume,
+}
+
+struct Stub<'a> {
+    uart: Uart<UART0>,
+    state: &'a mut State,
+}
+
+impl Stub<'_> {
+    /// Talk to GDB until it resumes the program
+    fn stopped(&mut self, frame: &mut TrapFrame, exception: Exception) {
+        let stepped = self.state.step.iter().any(Option::is_some);
+        for bp in self.state.step.iter_mut().filter_map(Option::take) {
+            remove(bp);
+        }
+
+        // An `ebreak` that's part of the program (e.g. from `breakpoint()`)
+        // has to be skipped when continuing, or it would trap again.
+        let pc = frame.mepc;
+        let ours = stepped
+            || self
+                .state
+                .breakpoints
+                .iter()
+                .flatten()
+                .any(|bp| bp.addr == pc);
+        let skip = match exception {
+            Exception::Breakpoint if !ours => unsafe { disasm::fetch(pc).1 },
+            _ => 0,
+        };
+
+        let sig = signal(exception);
+        self.send(&stop_reply(sig));
+
+        let mut buf = [0; PACKET_SIZE];
+        loop {
+            let len = self.receive(&mut buf);
+            let packet = &buf[..len];
+            let action = match packet.first() {
+                Some(b'?') => Action::Reply(stop_reply(sig)),
+                Some(b'g') => {
+                    let mut reply = Reply::new();
+                    for &reg in frame.regs.iter() {
+                        reply.reg(reg);
+                    }
+                    reply.reg(frame.mepc);
+                    Action::Reply(reply)
+                }
+                Some(b'G') => Action::Reply(write_regs(frame, &packet[1..])),
+                Some(b'p') => Action::Reply(read_reg(frame, &packet[1..])),
+                Some(b'P') => Action::Reply(write_reg(frame, &packet[1..])),
+                Some(b'm') => Action::Reply(read_memory(&packet[1..])),
+                Some(b'M') => Action::Reply(write_memory(&packet[1..])),
+                Some(b'c') => {
+                    resume_at(frame, &packet[1..], pc, skip);
+                    Action::Resume
+                }
+                Some(b's') => {
+                    resume_at(frame, &packet[1..], pc, skip);
+                    if frame.mepc != pc || skip == 0 {
+                        self.arm_step(frame);
+                        Action::Resume
+                    } else {
+                        // Stepping over the program's own ebreak is done.
+                        Action::Reply(stop_reply(5))
+                    }
+                }
+                Some(b'Z') => Action::Reply(self.set_breakpoint(&packet[1..], true)),
+                Some(b'z') => Action::Reply(self.set_breakpoint(&packet[1..], false)),
+                Some(b'q') if packet.starts_with(b"qSupported") => {
+                    Action::Reply(Reply::new().str("PacketSize=400"))
+                }
+                Some(b'q') if packet == b"qAttached" => Action::Reply(Reply::new().str("1")),
+                Some(b'H') => Action::Reply(Reply::new().str("OK")),
+                Some(b'D') => {
+                    for bp in self.state.breakpoints.iter_mut().filter_map(Option::take) {
+                        remove(bp);
+                    }
+                    self.send(&Reply::new().str("OK"));
+                    frame.mepc += skip;
+                    return;
+                }
+                Some(b'k') => crate::panic::reset(),
+                _ => Action::Reply(Reply::new()),
+            };
+            match action {
+                Action::Reply(reply) => self.send(&reply),
+                Action::Resume => return,
+            }
+        }
+    }
+
+    /// Put temporary breakpoints on everywhere the instruction at `mepc`
+    /// can go
+    fn arm_step(&mut self, frame: &TrapFrame) {
+        let pc = frame.mepc;
+        let Some((insn, len)) = probe(|| unsafe { disasm::fetch(pc) }) else {
+            return;
+        };
+        let insn = match len {
+            2 => disasm::expand_compressed(insn as u16).unwrap_or(0),
+            _ => insn,
+        };
+        let [first, second] = disasm::successors(insn, pc, len, &frame.regs);
+        // A branch to the next instruction has the same target twice.
+        let second = second.filter(|&t| Some(t) != first);
+        for (slot, target) in self.state.step.iter_mut().zip([first, second]) {
+            if let Some(target) = target {
+                *slot = insert(target, 2);
+            }
+        }
+    }
+
+    fn set_breakpoint(&mut self, args: &[u8], set: bool) -> Reply {
+        let parsed = (|| {
+            let rest = args.strip_prefix(b"0,")?;
+            let (addr, rest) = parse_hex(rest)?;
+            let (kind, _) = parse_hex(rest.strip_prefix(b",")?)?;
+            Some((addr, kind))
+        })();
+        // Only software breakpoints (type 0) are supported.
+        let Some((addr, kind)) = parsed else {
+            return Reply::new();
+        };
+
+        let slots = &mut self.state.breakpoints;
+        if set {
+            if slots.iter().flatten().any(|bp| bp.addr == addr) {
+                return Reply::new().str("OK");
+            }
+            let Some(slot) = slots.iter_mut().find(|bp| bp.is_none()) else {
+                return Reply::new().str("E01");
+            };
+            match insert(addr, if kind == 2 { 2 } else { 4 }) {
+                Some(bp) => {
+                    *slot = Some(bp);
+                    Reply::new().str("OK")
+                }
+                None => Reply::new().str("E0e"),
+            }
+        } else {
+            if let Some(bp) = slots
+                .iter_mut()
+                .find(|bp| matches!(bp, Some(bp) if bp.addr == addr))
+                .and_then(Option::take)
+            {
+                remove(bp);
+            }
+            Reply::new().str("OK")
+        }
+    }
+
+    /// Read a packet into `buf`, acknowledging it, and return its length
+    fn receive(&mut self, buf: &mut [u8; PACKET_SIZE]) -> usize {
+        loop {
+            while self.getc() != b'$' {}
+
+            let mut len = 0;
+            let mut sum = 0u8;
+            loop {
+                let c = self.getc();
+                if c == b'#' {
+                    break;
+                }
+                if let Some(slot) = buf.get_mut(len) {
+                    *slot = c;
+                    len += 1;
+                }
+                sum = sum.wrapping_add(c);
+            }
+            let hi = hex_digit(self.getc());
+            let lo = hex_digit(self.getc());
+            if let (Some(hi), Some(lo)) = (hi, lo) {
+                if hi << 4 | lo == sum {
+                    self.uart.write_byte(b'+');
+                    return len;
+                }
+            }
+            self.uart.write_byte(b'-');
+        }
+    }
+
+    /// Send a packet, retrying until GDB acknowledges it
+    fn send(&mut self, reply: &Reply) {
+        let data = &reply.buf[..reply.len];
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        loop {
+            self.uart.write_byte(b'$');
+            for &b in data {
+                self.uart.write_byte(b);
+            }
+            self.uart.write_byte(b'#');
+            let mut cs = Reply::new();
+            cs.hex_byte(sum);
+            self.uart.write_byte(cs.buf[0]);
+            self.uart.write_byte(cs.buf[1]);
+
+            loop {
+                match self.getc() {
+                    b'+' => return,
+                    b'-' => break,
+                    // Nobody listening yet, or GDB sending a packet of its
+                    // own: it will ask for the stop reason with `?`.
+                    b'$' => return,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn getc(&mut self) -> u8 {
+        loop {
+            if let Ok(b) = nb::block!(self.uart.try_read_byte()) {
+                return b;
+            }
+        }
+    }
+}
+
+fn stop_reply(sig: u8) -> Reply {
+    let mut reply = Reply::new().str("S");
+    reply.hex_byte(sig);
+    reply
+}
+
+fn resume_at(frame: &mut TrapFrame, args: &[u8], pc: usize, skip: usize) {
+    match parse_hex(args) {
+        Some((addr, _)) => frame.mepc = addr,
+        None if frame.mepc == pc => frame.mepc += skip,
+        None => {}
+    }
+}
+
+fn read_reg(frame: &TrapFrame, args: &[u8]) -> Reply {
+    let mut reply = Reply::new();
+    match parse_hex(args) {
+        Some((n, _)) if n < PC_REGNUM => reply.reg(frame.regs[n]),
+        Some((PC_REGNUM, _)) => reply.reg(frame.mepc),
+        _ => return reply.str("E01"),
+    }
+    reply
+}
+
+fn write_reg(frame: &mut TrapFrame, args: &[u8]) -> Reply {
+    let Some((n, rest)) = parse_hex(args) else {
+        return Reply::new().str("E01");
+    };
+    let Some(value) = rest.strip_prefix(b"=").and_then(parse_reg) else {
+        return Reply::new().str("E01");
+    };
+    match n {
+        // x0 is hardwired; `_start_trap` never restores it anyway.
+        0 => {}
+        n if n < PC_REGNUM => frame.regs[n] = value,
+        PC_REGNUM => frame.mepc = value,
+        _ => return Reply::new().str("E01"),
+    }
+    Reply::new().str("OK")
+}
+
+fn write_regs(frame: &mut TrapFrame, args: &[u8]) -> Reply {
+    let mut values = args.chunks(16).map(parse_reg);
+    for n in 0..=PC_REGNUM {
+        let Some(Some(value)) = values.next() else {
+            return Reply::new().str("E01");
+        };
+        match n {
+            0 => {}
+            PC_REGNUM => frame.mepc = value,
+            n => frame.regs[n] = value,
+        }
+    }
+    Reply::new().str("OK")
+}
+
+fn parse_range(args: &[u8]) -> Option<(usize, usize, &[u8])> {
+    let (addr, rest) = parse_hex(args)?;
+    let (len, rest) = parse_hex(rest.strip_prefix(b",")?)?;
+    Some((addr, len, rest))
+}
+
+fn read_memory(args: &[u8]) -> Reply {
+    let mut reply = Reply::new();
+    let Some((addr, len, _)) = parse_range(args) else {
+        return reply.str("E01");
+    };
+    // Whatever fits in a packet; GDB asks again for the rest.
+    for i in 0..len.min(PACKET_SIZE / 2) {
+        match read_mem(addr + i) {
+            Some(b) => reply.hex_byte(b),
+            None if i == 0 => return reply.str("E0e"),
+            None => break,
+        }
+    }
+    reply
+}
+
+fn write_memory(args: &[u8]) -> Reply {
+    let Some((addr, len, rest)) = parse_range(args) else {
+        return Reply::new().str("E01");
+    };
+    let Some(data) = rest.strip_prefix(b":") else {
+        return Reply::new().str("E01");
+    };
+    if data.len() < len * 2 {
+        return Reply::new().str("E01");
+    }
+    for (i, pair) in data[..len * 2].chunks(2).enumerate() {
+        let (Some(hi), Some(lo)) = (hex_digit(pair[0]), hex_digit(pair[1])) else {
+            return Reply::new().str("E01");
+        };
+        if write_mem(addr + i, hi << 4 | lo).is_none() {
+            return Reply::new().str("E0e");
+        }
+    }
+    // GDB may be patching code.
+    fence_i();
+    Reply::new().str("OK")
+}
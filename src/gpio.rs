@@ -0,0 +1,867 @@
+//! GPIO pins on ports PB to PG
+//!
+//! [`Pins::new`] splits the GPIO peripheral into a [`Pin`] per pad, with
+//! its mode ([`Input`], [`Output`], [`Function`] or [`Disabled`]) tracked
+//! in its type. Every register update is a read-modify-write in a critical
+//! section, so pins on the same port can be owned by different drivers and
+//! changed from interrupt handlers without disturbing each other.
+//!
+//! ```ignore
+//! let pins = Pins::new(p.GPIO);
+//! let mut led = pins.pc1.into_output();
+//! let _tx = pins.pb8.into_function::<6>();
+//! led.toggle();
+//! ```
+//!
+//! Pins start out typed as [`Disabled`], whatever the boot ROM left them
+//! as; convert them before use.
+//!
+//! Every mode change is checked against the [`pinmux`] registry, and
+//! panics if a driver has claimed the pin by name for another function.
+//! Release the claim before converting a claimed pin.
+//!
+//! Pull resistors and drive strength can be set on a pin in any mode, and
+//! the IO voltage of a whole port with [`set_io_voltage`].
+//!
+//! Pins in [`Eint`] mode raise the port's GPIO interrupt. Route it through
+//! the [`Plic`](crate::plic::Plic) (see [`port_interrupt`]) and call
+//! [`handle_interrupt`] with each claim to run the per-pin handlers.
+//!
+//! Input and output pins implement the `embedded-hal` 0.2 and 1.0 digital
+//! traits, and [`Eint`] pins `embedded_hal_async::digital::Wait`.
+
+use core::{
+    cell::RefCell,
+    convert::Infallible,
+    future::poll_fn,
+    marker::PhantomData,
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Poll, Waker},
+};
+
+use d1_pac::{Interrupt, GPIO};
+use riscv::interrupt::Mutex;
+
+use crate::pinmux;
+
+pub(crate) const PORT_STRIDE: usize = 0x30;
+
+// Per-port register offsets, from the D1 user manual section 9.7.
+const CFG0: usize = 0x00;
+pub(crate) const DAT: usize = 0x10;
+const DRV0: usize = 0x14;
+const PULL0: usize = 0x24;
+
+// IO power mode registers, one bit per port
+const POW_MOD_SEL: usize = 0x340;
+const POW_VAL: usize = 0x348;
+
+// External interrupt registers, one bank per port starting with PB's at
+// 0x220.
+const EINT_BASE: usize = 0x200;
+const EINT_STRIDE: usize = 0x20;
+const EINT_CFG0: usize = 0x00;
+const EINT_CTL: usize = 0x10;
+const EINT_STATUS: usize = 0x14;
+const EINT_DEB: usize = 0x18;
+
+const CFG_INPUT: u32 = 0x0;
+const CFG_OUTPUT: u32 = 0x1;
+const CFG_EINT: u32 = 0xE;
+const CFG_DISABLED: u32 = 0xF;
+
+/// Number of ports, counting the non-existent PA
+const PORTS: usize = 7;
+
+/// Pin mode: digital input
+pub struct Input;
+/// Pin mode: push-pull output
+pub struct Output;
+/// Pin mode: peripheral function `F` (2 to 8), as listed in the user
+/// manual's pin multiplexing tables
+pub struct Function<const F: u8>;
+/// Pin mode: input and output disabled (the reset state)
+pub struct Disabled;
+/// Pin mode: external interrupt input
+pub struct Eint;
+
+mod sealed {
+    pub trait Mode {
+        /// Value of the pin's CFG register field in this mode
+        const CFG: u32;
+    }
+}
+
+/// A pin mode
+pub trait Mode: sealed::Mode {}
+
+impl sealed::Mode for Input {
+    const CFG: u32 = CFG_INPUT;
+}
+impl sealed::Mode for Output {
+    const CFG: u32 = CFG_OUTPUT;
+}
+impl<const F: u8> sealed::Mode for Function<F> {
+    const CFG: u32 = F as u32;
+}
+impl sealed::Mode for Disabled {
+    const CFG: u32 = CFG_DISABLED;
+}
+impl sealed::Mode for Eint {
+    const CFG: u32 = CFG_EINT;
+}
+impl Mode for Input {}
+impl Mode for Output {}
+impl<const F: u8> Mode for Function<F> {}
+impl Mode for Disabled {}
+impl Mode for Eint {}
+
+/// Internal pull resistor
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pull {
+    None = 0,
+    Up = 1,
+    Down = 2,
+}
+
+/// Output drive strength, from weakest to strongest
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DriveStrength {
+    L0 = 0,
+    L1 = 1,
+    L2 = 2,
+    L3 = 3,
+}
+
+/// IO voltage of a port's power domain
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IoVoltage {
+    V3_3,
+    V1_8,
+}
+
+/// What makes an [`Eint`] pin raise its interrupt
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    RisingEdge = 0,
+    FallingEdge = 1,
+    HighLevel = 2,
+    LowLevel = 3,
+    BothEdges = 4,
+}
+
+/// Clock sampling a port's [`Eint`] inputs, which sets how long a level
+/// has to be stable to be seen
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebounceClock {
+    /// 32 kHz LOSC
+    Losc32k = 0,
+    /// 24 MHz HOSC
+    Hosc24M = 1,
+}
+
+/// Called from [`handle_interrupt`] when a pin's interrupt is pending
+pub type EintHandler = fn();
+
+static EINT_HANDLERS: Mutex<RefCell<[[Option<EintHandler>; 32]; PORTS]>> =
+    Mutex::new(RefCell::new([[None; 32]; PORTS]));
+
+const NO_WAKER: Option<Waker> = None;
+const NO_WAKERS: [Option<Waker>; 32] = [NO_WAKER; 32];
+
+/// Tasks waiting on each pin, for the `embedded-hal-async` `Wait` impl
+static EINT_WAKERS: Mutex<RefCell<[[Option<Waker>; 32]; PORTS]>> =
+    Mutex::new(RefCell::new([NO_WAKERS; PORTS]));
+
+/// Pins whose interrupt fired while a task was waiting on them
+static EINT_FIRED: [AtomicU32; PORTS] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+/// Fails to compile for function numbers that aren't peripheral functions
+struct CheckFunction<const F: u8>;
+
+impl<const F: u8> CheckFunction<F> {
+    const OK: () = assert!(F >= 2 && F <= 8, "pin functions are numbered 2 to 8");
+}
+
+/// Port and pin number of a pad, with the raw register accessors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct PinId {
+    /// 1 for PB up to 6 for PG
+    port: u8,
+    pin: u8,
+}
+
+impl PinId {
+    /// Offset from [`base`] of the port register at `offset`
+    fn port_bank(self, offset: usize) -> usize {
+        self.port as usize * PORT_STRIDE + offset
+    }
+
+    /// Offset from [`base`] of the port's EINT register at `offset`
+    fn eint_bank(self, offset: usize) -> usize {
+        EINT_BASE + self.port as usize * EINT_STRIDE + offset
+    }
+
+    fn reg(self, offset: usize) -> *mut u32 {
+        (base() + self.port_bank(offset)) as *mut u32
+    }
+
+    /// Replace the `width`-bit field for this pin in the register bank at
+    /// `bank`, whose registers hold `32 / width` pins each
+    fn modify_field(self, bank: usize, width: usize, value: u32) {
+        let per_reg = 32 / width;
+        let pin = self.pin as usize;
+        let reg = (base() + bank + (pin / per_reg) * 4) as *mut u32;
+        let shift = (pin % per_reg) * width;
+        let mask = ((1 << width) - 1) << shift;
+        riscv::interrupt::free(|_| unsafe {
+            let old = read_volatile(reg);
+            write_volatile(reg, (old & !mask) | ((value << shift) & mask));
+        });
+    }
+
+    /// Mux the pin to `cfg`, unless a driver has claimed it by name for
+    /// something else
+    fn set_cfg(self, cfg: u32) {
+        if let Err(error) = pinmux::remux((b'A' + self.port) as char, self.pin, cfg as u8) {
+            panic!("{}", error);
+        }
+        self.modify_field(self.port_bank(CFG0), 4, cfg);
+    }
+
+    fn set_pull(self, pull: Pull) {
+        self.modify_field(self.port_bank(PULL0), 2, pull as u32);
+    }
+
+    fn set_drive_strength(self, strength: DriveStrength) {
+        // Two bits used out of each four-bit field
+        self.modify_field(self.port_bank(DRV0), 4, strength as u32);
+    }
+
+    fn set_level(self, high: bool) {
+        self.modify_field(self.port_bank(DAT), 1, high as u32);
+    }
+
+    fn toggle(self) {
+        let reg = self.reg(DAT);
+        riscv::interrupt::free(|_| unsafe {
+            write_volatile(reg, read_volatile(reg) ^ (1 << self.pin));
+        });
+    }
+
+    fn is_high(self) -> bool {
+        unsafe { read_volatile(self.reg(DAT)) & (1 << self.pin) != 0 }
+    }
+
+    fn set_trigger(self, trigger: Trigger) {
+        self.modify_field(self.eint_bank(EINT_CFG0), 4, trigger as u32);
+    }
+
+    fn set_eint_enabled(self, enabled: bool) {
+        self.modify_field(self.eint_bank(EINT_CTL), 1, enabled as u32);
+    }
+
+    fn eint_pending(self) -> bool {
+        let status = (base() + self.eint_bank(EINT_STATUS)) as *const u32;
+        unsafe { read_volatile(status) & (1 << self.pin) != 0 }
+    }
+
+    fn clear_eint(self) {
+        // Write-one-to-clear, so no read-modify-write needed
+        let status = (base() + self.eint_bank(EINT_STATUS)) as *mut u32;
+        unsafe { write_volatile(status, 1 << self.pin) };
+    }
+
+    fn set_eint_handler(self, handler: Option<EintHandler>) {
+        riscv::interrupt::free(|cs| {
+            EINT_HANDLERS.borrow(cs).borrow_mut()[self.port as usize][self.pin as usize] = handler;
+        });
+    }
+}
+
+const fn port_index(port: char) -> u8 {
+    match port {
+        'B' => 1,
+        'C' => 2,
+        'D' => 3,
+        'E' => 4,
+        'F' => 5,
+        'G' => 6,
+        _ => panic!("no such GPIO port"),
+    }
+}
+
+const PORT_INTERRUPTS: [(u8, Interrupt); PORTS - 1] = [
+    (1, Interrupt::GPIOB_NS),
+    (2, Interrupt::GPIOC_NS),
+    (3, Interrupt::GPIOD_NS),
+    (4, Interrupt::GPIOE_NS),
+    (5, Interrupt::GPIOF_NS),
+    (6, Interrupt::GPIOG_NS),
+];
+
+/// The PLIC interrupt for EINTs on `port` (`'B'` to `'G'`)
+pub fn port_interrupt(port: char) -> Interrupt {
+    PORT_INTERRUPTS[port_index(port) as usize - 1].1
+}
+
+/// Select the IO voltage of `port`'s power domain
+///
+/// This has to match the supply on the port's VCC-Px pin; the pads are
+/// only guaranteed to work, and not be damaged, when it does.
+pub fn set_io_voltage(port: char, voltage: IoVoltage) {
+    let reg = (base() + POW_MOD_SEL) as *mut u32;
+    let bit = 1 << port_index(port);
+    riscv::interrupt::free(|_| unsafe {
+        let old = read_volatile(reg);
+        let new = match voltage {
+            IoVoltage::V3_3 => old & !bit,
+            IoVoltage::V1_8 => old | bit,
+        };
+        write_volatile(reg, new);
+    });
+}
+
+/// The IO voltage the power domain of `port` detects on its supply
+pub fn detected_io_voltage(port: char) -> IoVoltage {
+    let val = unsafe { read_volatile((base() + POW_VAL) as *const u32) };
+    match val & (1 << port_index(port)) {
+        0 => IoVoltage::V3_3,
+        _ => IoVoltage::V1_8,
+    }
+}
+
+/// Select the debounce clock for `port`'s EINTs
+///
+/// Inputs are sampled at the clock divided by `2^prescale`, `prescale`
+/// being 0 to 7.
+pub fn set_debounce(port: char, clock: DebounceClock, prescale: u8) {
+    assert!(prescale < 8, "debounce prescale is 0 to 7");
+    let id = PinId {
+        port: port_index(port),
+        pin: 0,
+    };
+    let deb = (base() + id.eint_bank(EINT_DEB)) as *mut u32;
+    unsafe { write_volatile(deb, (prescale as u32) << 4 | clock as u32) };
+}
+
+/// Run the handlers of the pending EINTs if `interrupt` is a GPIO port's
+///
+/// Meant to be called with each [`Plic::claim`](crate::plic::Plic::claim);
+/// returns `false` if `interrupt` isn't a GPIO interrupt. Pending flags are
+/// cleared before the handlers run, so a level-triggered pin that is still
+/// active will fire again.
+///
+/// Pins being waited on through `embedded_hal_async::digital::Wait` have
+/// their interrupt disabled and the waiting task woken instead.
+pub fn handle_interrupt(interrupt: Interrupt) -> bool {
+    let Some(&(port, _)) = PORT_INTERRUPTS.iter().find(|(_, irq)| *irq == interrupt) else {
+        return false;
+    };
+    let id = PinId { port, pin: 0 };
+    let status = (base() + id.eint_bank(EINT_STATUS)) as *mut u32;
+    let ctl = (base() + id.eint_bank(EINT_CTL)) as *const u32;
+
+    let pending = unsafe { read_volatile(status) & read_volatile(ctl) };
+    unsafe { write_volatile(status, pending) };
+
+    for pin in (0..32).filter(|pin| pending & (1 << pin) != 0) {
+        let (handler, waker) = riscv::interrupt::free(|cs| {
+            (
+                EINT_HANDLERS.borrow(cs).borrow()[port as usize][pin],
+                EINT_WAKERS.borrow(cs).borrow_mut()[port as usize][pin].take(),
+            )
+        });
+        if let Some(waker) = waker {
+            let id = PinId {
+                port,
+                pin: pin as u8,
+            };
+            id.set_eint_enabled(false);
+            EINT_FIRED[port as usize].fetch_or(1 << pin, Ordering::SeqCst);
+            waker.wake();
+        }
+        if let Some(handler) = handler {
+            handler();
+        }
+    }
+    true
+}
+
+/// Address of the GPIO block, which port `n` (1 for PB) starts
+/// `n * PORT_STRIDE` into
+pub(crate) fn base() -> usize {
+    GPIO::PTR as usize
+}
+
+/// Pin `N` of port `P` (e.g. `Pin<'C', 1, Output>` is PC1), in mode `MODE`
+pub struct Pin<const P: char, const N: u8, MODE = Disabled> {
+    _mode: PhantomData<MODE>,
+}
+
+impl<const P: char, const N: u8, MODE: Mode> Pin<P, N, MODE> {
+    const ID: PinId = PinId {
+        port: port_index(P),
+        pin: N,
+    };
+
+    const fn new() -> Self {
+        Self { _mode: PhantomData }
+    }
+
+    /// Conjure up the pin in whatever mode
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may be using the pin. [`Pin::try_steal`] checks that
+    /// through the [`pinmux`] registry instead.
+    pub unsafe fn steal() -> Self {
+        Self::new()
+    }
+
+    /// Take the pin for `owner`, in whatever mode, unless a driver has
+    /// claimed it by name
+    ///
+    /// Drivers that skip the registry can still be using the pin.
+    pub fn try_steal(owner: &'static str) -> Result<Self, pinmux::Error> {
+        pinmux::claim(P, N, MODE::CFG as u8, owner)?;
+        Ok(Self::new())
+    }
+
+    pub fn into_input(self) -> Pin<P, N, Input> {
+        Self::ID.set_cfg(CFG_INPUT);
+        Pin::new()
+    }
+
+    /// Make the pin an output, driving whatever level was last set
+    pub fn into_output(self) -> Pin<P, N, Output> {
+        Self::ID.set_cfg(CFG_OUTPUT);
+        Pin::new()
+    }
+
+    /// Make the pin an output, driving `high` from the start
+    pub fn into_output_with_state(self, high: bool) -> Pin<P, N, Output> {
+        Self::ID.set_level(high);
+        self.into_output()
+    }
+
+    /// Hand the pin to peripheral function `F`
+    pub fn into_function<const F: u8>(self) -> Pin<P, N, Function<F>> {
+        #[allow(clippy::let_unit_value)]
+        let () = CheckFunction::<F>::OK;
+        Self::ID.set_cfg(F as u32);
+        Pin::new()
+    }
+
+    pub fn into_disabled(self) -> Pin<P, N, Disabled> {
+        Self::ID.set_cfg(CFG_DISABLED);
+        Pin::new()
+    }
+
+    /// Make the pin an external interrupt input, initially disabled
+    pub fn into_eint(self, trigger: Trigger) -> Pin<P, N, Eint> {
+        Self::ID.set_eint_enabled(false);
+        Self::ID.set_cfg(CFG_EINT);
+        Self::ID.set_trigger(trigger);
+        Self::ID.clear_eint();
+        Pin::new()
+    }
+
+    pub fn set_pull(&mut self, pull: Pull) {
+        Self::ID.set_pull(pull);
+    }
+
+    pub fn set_drive_strength(&mut self, strength: DriveStrength) {
+        Self::ID.set_drive_strength(strength);
+    }
+
+    /// Record in the [`pinmux`] registry that `owner` uses the pin in its
+    /// current mode
+    pub fn claim(&self, owner: &'static str) -> Result<(), pinmux::Error> {
+        pinmux::claim(P, N, MODE::CFG as u8, owner)
+    }
+
+    /// Drop `owner`'s claim on the pin
+    pub fn release(&self, owner: &'static str) {
+        pinmux::release(P, N, owner);
+    }
+
+    /// Forget the pin number at compile time, e.g. to keep pins in an array
+    pub fn erase(self) -> ErasedPin<MODE> {
+        ErasedPin {
+            id: Self::ID,
+            _mode: PhantomData,
+        }
+    }
+}
+
+impl<const P: char, const N: u8> Pin<P, N, Output> {
+    pub fn set_high(&mut self) {
+        Self::ID.set_level(true);
+    }
+
+    pub fn set_low(&mut self) {
+        Self::ID.set_level(false);
+    }
+
+    pub fn set_state(&mut self, high: bool) {
+        Self::ID.set_level(high);
+    }
+
+    pub fn toggle(&mut self) {
+        Self::ID.toggle();
+    }
+
+    /// Whether the pin is being driven high
+    pub fn is_set_high(&self) -> bool {
+        Self::ID.is_high()
+    }
+}
+
+impl<const P: char, const N: u8> Pin<P, N, Input> {
+    pub fn is_high(&self) -> bool {
+        Self::ID.is_high()
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+impl<const P: char, const N: u8> Pin<P, N, Eint> {
+    /// The PLIC interrupt this pin raises
+    pub fn interrupt(&self) -> Interrupt {
+        port_interrupt(P)
+    }
+
+    pub fn set_trigger(&mut self, trigger: Trigger) {
+        Self::ID.set_trigger(trigger);
+    }
+
+    pub fn enable_interrupt(&mut self) {
+        Self::ID.set_eint_enabled(true);
+    }
+
+    pub fn disable_interrupt(&mut self) {
+        Self::ID.set_eint_enabled(false);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        Self::ID.eint_pending()
+    }
+
+    pub fn clear_pending(&mut self) {
+        Self::ID.clear_eint();
+    }
+
+    /// Set the function [`handle_interrupt`] calls for this pin
+    pub fn set_handler(&mut self, handler: Option<EintHandler>) {
+        Self::ID.set_eint_handler(handler);
+    }
+}
+
+/// A pin whose port and number are only known at run time
+pub struct ErasedPin<MODE> {
+    id: PinId,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE: Mode> ErasedPin<MODE> {
+    /// Port letter, `'B'` to `'G'`
+    pub fn port(&self) -> char {
+        (b'A' + self.id.port) as char
+    }
+
+    pub fn pin(&self) -> u8 {
+        self.id.pin
+    }
+
+    /// Record in the [`pinmux`] registry that `owner` uses the pin in its
+    /// current mode
+    pub fn claim(&self, owner: &'static str) -> Result<(), pinmux::Error> {
+        pinmux::claim(self.port(), self.id.pin, MODE::CFG as u8, owner)
+    }
+
+    /// Drop `owner`'s claim on the pin
+    pub fn release(&self, owner: &'static str) {
+        pinmux::release(self.port(), self.id.pin, owner);
+    }
+
+    pub fn set_pull(&mut self, pull: Pull) {
+        self.id.set_pull(pull);
+    }
+
+    pub fn set_drive_strength(&mut self, strength: DriveStrength) {
+        self.id.set_drive_strength(strength);
+    }
+
+    fn into_mode<M>(self, cfg: u32) -> ErasedPin<M> {
+        self.id.set_cfg(cfg);
+        ErasedPin {
+            id: self.id,
+            _mode: PhantomData,
+        }
+    }
+
+    pub fn into_input(self) -> ErasedPin<Input> {
+        self.into_mode(CFG_INPUT)
+    }
+
+    pub fn into_output(self) -> ErasedPin<Output> {
+        self.into_mode(CFG_OUTPUT)
+    }
+
+    pub fn into_disabled(self) -> ErasedPin<Disabled> {
+        self.into_mode(CFG_DISABLED)
+    }
+}
+
+impl ErasedPin<Output> {
+    pub fn set_high(&mut self) {
+        self.id.set_level(true);
+    }
+
+    pub fn set_low(&mut self) {
+        self.id.set_level(false);
+    }
+
+    pub fn set_state(&mut self, high: bool) {
+        self.id.set_level(high);
+    }
+
+    pub fn toggle(&mut self) {
+        self.id.toggle();
+    }
+
+    pub fn is_set_high(&self) -> bool {
+        self.id.is_high()
+    }
+}
+
+impl ErasedPin<Input> {
+    pub fn is_high(&self) -> bool {
+        self.id.is_high()
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+macro_rules! pins {
+    ($($port:literal => [$($field:ident: $n:literal),+ $(,)?],)+) => {
+        /// Every pin on ports PB to PG
+        pub struct Pins {
+            $($(pub $field: Pin<$port, $n>,)+)+
+        }
+
+        impl Pins {
+            /// Split the GPIO peripheral into its pins
+            pub fn new(_gpio: GPIO) -> Self {
+                Self {
+                    $($($field: Pin::new(),)+)+
+                }
+            }
+        }
+    };
+}
+
+pins! {
+    'B' => [pb0: 0, pb1: 1, pb2: 2, pb3: 3, pb4: 4, pb5: 5, pb6: 6, pb7: 7, pb8: 8, pb9: 9,
+            pb10: 10, pb11: 11, pb12: 12],
+    'C' => [pc0: 0, pc1: 1, pc2: 2, pc3: 3, pc4: 4, pc5: 5, pc6: 6, pc7: 7],
+    'D' => [pd0: 0, pd1: 1, pd2: 2, pd3: 3, pd4: 4, pd5: 5, pd6: 6, pd7: 7, pd8: 8, pd9: 9,
+            pd10: 10, pd11: 11, pd12: 12, pd13: 13, pd14: 14, pd15: 15, pd16: 16, pd17: 17,
+            pd18: 18, pd19: 19, pd20: 20, pd21: 21, pd22: 22],
+    'E' => [pe0: 0, pe1: 1, pe2: 2, pe3: 3, pe4: 4, pe5: 5, pe6: 6, pe7: 7, pe8: 8, pe9: 9,
+            pe10: 10, pe11: 11, pe12: 12, pe13: 13, pe14: 14, pe15: 15, pe16: 16, pe17: 17],
+    'F' => [pf0: 0, pf1: 1, pf2: 2, pf3: 3, pf4: 4, pf5: 5, pf6: 6],
+    'G' => [pg0: 0, pg1: 1, pg2: 2, pg3: 3, pg4: 4, pg5: 5, pg6: 6, pg7: 7, pg8: 8, pg9: 9,
+            pg10: 10, pg11: 11, pg12: 12, pg13: 13, pg14: 14, pg15: 15, pg16: 16, pg17: 17,
+            pg18: 18],
+}
+
+/// Disables a pin's EINT when a `Wait` future is dropped before completing
+struct WaitGuard(PinId);
+
+impl Drop for WaitGuard {
+    fn drop(&mut self) {
+        self.0.set_eint_enabled(false);
+        riscv::interrupt::free(|cs| {
+            EINT_WAKERS.borrow(cs).borrow_mut()[self.0.port as usize][self.0.pin as usize] = None;
+        });
+    }
+}
+
+impl<const P: char, const N: u8> Pin<P, N, Eint> {
+    /// Wait until the pin's interrupt fires with `trigger`
+    async fn wait_for(&mut self, trigger: Trigger) {
+        let id = Self::ID;
+        let fired = &EINT_FIRED[id.port as usize];
+        let bit = 1 << id.pin;
+
+        id.set_eint_enabled(false);
+        id.set_trigger(trigger);
+        id.clear_eint();
+        fired.fetch_and(!bit, Ordering::SeqCst);
+        let _guard = WaitGuard(id);
+
+        poll_fn(|cx| {
+            if fired.fetch_and(!bit, Ordering::SeqCst) & bit != 0 {
+                return Poll::Ready(());
+            }
+            riscv::interrupt::free(|cs| {
+                EINT_WAKERS.borrow(cs).borrow_mut()[id.port as usize][id.pin as usize] =
+                    Some(cx.waker().clone());
+            });
+            id.set_eint_enabled(true);
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+macro_rules! impl_output_pin {
+    ([$($generics:tt)*] $ty:ty) => {
+        impl<$($generics)*> embedded_hal::digital::ErrorType for $ty {
+            type Error = Infallible;
+        }
+
+        impl<$($generics)*> embedded_hal::digital::OutputPin for $ty {
+            fn set_low(&mut self) -> Result<(), Infallible> {
+                self.set_state(false);
+                Ok(())
+            }
+
+            fn set_high(&mut self) -> Result<(), Infallible> {
+                self.set_state(true);
+                Ok(())
+            }
+        }
+
+        impl<$($generics)*> embedded_hal::digital::StatefulOutputPin for $ty {
+            fn is_set_high(&mut self) -> Result<bool, Infallible> {
+                Ok(<$ty>::is_set_high(self))
+            }
+
+            fn is_set_low(&mut self) -> Result<bool, Infallible> {
+                Ok(!<$ty>::is_set_high(self))
+            }
+
+            fn toggle(&mut self) -> Result<(), Infallible> {
+                <$ty>::toggle(self);
+                Ok(())
+            }
+        }
+
+        impl<$($generics)*> embedded_hal_02::digital::v2::OutputPin for $ty {
+            type Error = Infallible;
+
+            fn set_low(&mut self) -> Result<(), Infallible> {
+                self.set_state(false);
+                Ok(())
+            }
+
+            fn set_high(&mut self) -> Result<(), Infallible> {
+                self.set_state(true);
+                Ok(())
+            }
+        }
+
+        impl<$($generics)*> embedded_hal_02::digital::v2::StatefulOutputPin for $ty {
+            fn is_set_high(&self) -> Result<bool, Infallible> {
+                Ok(<$ty>::is_set_high(self))
+            }
+
+            fn is_set_low(&self) -> Result<bool, Infallible> {
+                Ok(!<$ty>::is_set_high(self))
+            }
+        }
+
+        impl<$($generics)*> embedded_hal_02::digital::v2::ToggleableOutputPin for $ty {
+            type Error = Infallible;
+
+            fn toggle(&mut self) -> Result<(), Infallible> {
+                <$ty>::toggle(self);
+                Ok(())
+            }
+        }
+    };
+}
+
+macro_rules! impl_input_pin {
+    ([$($generics:tt)*] $ty:ty) => {
+        impl<$($generics)*> embedded_hal::digital::ErrorType for $ty {
+            type Error = Infallible;
+        }
+
+        impl<$($generics)*> embedded_hal::digital::InputPin for $ty {
+            fn is_high(&mut self) -> Result<bool, Infallible> {
+                Ok(<$ty>::is_high(self))
+            }
+
+            fn is_low(&mut self) -> Result<bool, Infallible> {
+                Ok(<$ty>::is_low(self))
+            }
+        }
+
+        impl<$($generics)*> embedded_hal_02::digital::v2::InputPin for $ty {
+            type Error = Infallible;
+
+            fn is_high(&self) -> Result<bool, Infallible> {
+                Ok(<$ty>::is_high(self))
+            }
+
+            fn is_low(&self) -> Result<bool, Infallible> {
+                Ok(<$ty>::is_low(self))
+            }
+        }
+    };
+}
+
+impl_output_pin!([const P: char, const N: u8] Pin<P, N, Output>);
+impl_output_pin!([] ErasedPin<Output>);
+impl_input_pin!([const P: char, const N: u8] Pin<P, N, Input>);
+impl_input_pin!([] ErasedPin<Input>);
+
+impl<const P: char, const N: u8> embedded_hal::digital::ErrorType for Pin<P, N, Eint> {
+    type Error = Infallible;
+}
+
+/// Waits using the pin's EINT, so [`handle_interrupt`] must be called for
+/// the port's interrupt. The trigger is reprogrammed for each wait.
+impl<const P: char, const N: u8> embedded_hal_async::digital::Wait for Pin<P, N, Eint> {
+    async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Trigger::HighLevel).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Trigger::LowLevel).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Trigger::RisingEdge).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Trigger::FallingEdge).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+        self.wait_for(Trigger::BothEdges).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,482 @@
+//! LEDC driver for WS2812-style addressable LEDs
+//!
+//! The LEDC shifts 24-bit colors out of a 32-word FIFO on `LEDC_DO`
+//! (function 4 on PC0), using the bit timings from [`Timings`], then holds
+//! the line low for the reset time to latch the chain.
+//!
+//! ```ignore
+//! let mut ledc = Ledc::new(p.LEDC, Timings::WS2812, ColorOrder::Grb);
+//! ledc.write_blocking(&[Rgb::new(0, 0, 16)])?;
+//! ```
+//!
+//! Besides [`Ledc::write_blocking`], the FIFO can be fed from the
+//! `FIFO_CPUREQ` interrupt by [`Ledc::write`], or by DMA with
+//! [`Ledc::write_dma`]. Both wait for the transfer-finished interrupt, so
+//! [`Ledc::on_interrupt`] must be called when the PLIC claims
+//! `Interrupt::LEDC`.
+
+use core::{
+    cell::RefCell,
+    future::poll_fn,
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Poll, Waker},
+};
+
+use d1_pac::{DMAC, LEDC};
+use riscv::interrupt::Mutex;
+
+use crate::ccu::{self, Enable, Reset};
+
+const LEDC_CTRL: usize = 0x00;
+const LEDC_T01_TIMING_CTRL: usize = 0x04;
+const LEDC_RESET_TIMING_CTRL: usize = 0x0C;
+const LEDC_DATA: usize = 0x14;
+const LEDC_DMA_CTRL: usize = 0x18;
+const LEDC_INT_CTRL: usize = 0x1C;
+const LEDC_INT_STS: usize = 0x20;
+
+const CTRL_EN: u32 = 1 << 0;
+const CTRL_SOFT_RESET: u32 = 1 << 1;
+const CTRL_RGB_MODE_SHIFT: u32 = 6;
+const CTRL_RGB_MODE: u32 = 0b111 << CTRL_RGB_MODE_SHIFT;
+const CTRL_DATA_LENGTH_SHIFT: u32 = 16;
+const CTRL_DATA_LENGTH: u32 = 0x1FFF << CTRL_DATA_LENGTH_SHIFT;
+
+const DMA_CTRL_EN: u32 = 1 << 5;
+
+const INT_TRANS_FINISH: u32 = 1 << 0;
+const INT_FIFO_CPUREQ: u32 = 1 << 1;
+const INT_WAITDATA_TIMEOUT: u32 = 1 << 3;
+const INT_FIFO_OVERFLOW: u32 = 1 << 4;
+const INT_CTRL_GLOBAL_EN: u32 = 1 << 5;
+const INT_ALL: u32 = INT_TRANS_FINISH | INT_FIFO_CPUREQ | INT_WAITDATA_TIMEOUT | INT_FIFO_OVERFLOW;
+
+const STS_FIFO_WLW_SHIFT: u32 = 10;
+const STS_FIFO_WLW: u32 = 0x3F << STS_FIFO_WLW_SHIFT;
+
+/// Words in the LEDC FIFO
+const FIFO_DEPTH: u32 = 32;
+
+/// Frequency of the LEDC module clock, in which all timings are counted,
+/// as set up by [`ccu::enable_ledc_clock`]
+const LEDC_CLOCK_HZ: u32 = ccu::HOSC_HZ;
+
+// DMA controller registers, from the D1 user manual section 3.9.
+const DMAC_CHAN_BASE: usize = 0x100;
+const DMAC_CHAN_STRIDE: usize = 0x40;
+const DMAC_EN: usize = 0x00;
+const DMAC_DESC_ADDR: usize = 0x08;
+
+/// Number of DMA channels
+const DMA_CHANNELS: u8 = 16;
+const DRQ_SDRAM: u32 = 1;
+const DRQ_LEDC: u32 = 42;
+/// Descriptor `link` value ending the chain
+const DMA_LINK_END: u32 = 0xFFFF_F800;
+/// Descriptor parameter: the usual wait between bursts
+const DMA_PARA_NORMAL_WAIT: u32 = 8;
+/// 16-beat bursts of 32-bit words, linear from DRAM to the LEDC data port
+const DMA_CFG: u32 =
+    DRQ_SDRAM | (3 << 6) | (2 << 9) | (DRQ_LEDC << 16) | (3 << 22) | (1 << 24) | (2 << 25);
+
+/// Largest number of LEDs in one transfer
+pub const MAX_LEDS: usize = 1024;
+
+/// Bit timings of the LED chain, in nanoseconds
+///
+/// The LEDC counts in 24 MHz ticks (about 42 ns), so each time is rounded
+/// to the nearest tick and clamped to its register field.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timings {
+    /// High time of a 0 bit
+    pub t0h_ns: u32,
+    /// Low time of a 0 bit
+    pub t0l_ns: u32,
+    /// High time of a 1 bit
+    pub t1h_ns: u32,
+    /// Low time of a 1 bit
+    pub t1l_ns: u32,
+    /// Low time after the last LED that latches the colors
+    pub reset_ns: u32,
+}
+
+impl Timings {
+    /// WS2812 and WS2812B, with the 280 µs reset newer parts need
+    pub const WS2812: Self = Self {
+        t0h_ns: 350,
+        t0l_ns: 800,
+        t1h_ns: 700,
+        t1l_ns: 600,
+        reset_ns: 300_000,
+    };
+
+    fn t01_bits(&self) -> u32 {
+        (ticks(self.t1h_ns, 0x3F) << 21)
+            | (ticks(self.t1l_ns, 0x1F) << 16)
+            | (ticks(self.t0h_ns, 0x1F) << 6)
+            | ticks(self.t0l_ns, 0x3F)
+    }
+}
+
+/// Convert nanoseconds to LEDC clock ticks, clamped to `1..=max`
+fn ticks(ns: u32, max: u32) -> u32 {
+    let ticks = (ns as u64 * LEDC_CLOCK_HZ as u64 + 500_000_000) / 1_000_000_000;
+    (ticks as u32).clamp(1, max)
+}
+
+/// Order the color components are sent in; WS2812s expect [`ColorOrder::Grb`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorOrder {
+    Grb = 0,
+    Gbr = 1,
+    Rgb = 2,
+    Rbg = 3,
+    Bgr = 4,
+    Brg = 5,
+}
+
+/// Color of one LED; the LEDC reorders it on the wire per [`ColorOrder`]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// The FIFO word for this color
+    pub const fn word(self) -> u32 {
+        ((self.r as u32) << 16) | ((self.g as u32) << 8) | self.b as u32
+    }
+}
+
+impl From<(u8, u8, u8)> for Rgb {
+    fn from((r, g, b): (u8, u8, u8)) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// Transfer error reported by the LEDC
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// More than [`MAX_LEDS`] colors were given
+    TooLong,
+    /// The DMA buffer is shorter than the colors given
+    BufferTooSmall,
+    /// [`Ledc::write_dma`] was called without a DMA channel set
+    NoDmaChannel,
+    /// The FIFO ran dry mid-transfer; the chain got a truncated frame
+    Underrun,
+    /// More words were written than the FIFO had room for
+    Overflow,
+}
+
+/// Interrupt status collected by [`Ledc::on_interrupt`]
+static STATUS: AtomicU32 = AtomicU32::new(0);
+/// Task waiting in [`Ledc::write`] or [`Ledc::write_dma`]
+static WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+
+/// One DMA transfer descriptor, as read by the DMA controller
+#[repr(C, align(4))]
+struct Descriptor {
+    config: u32,
+    source: u32,
+    destination: u32,
+    byte_count: u32,
+    parameter: u32,
+    link: u32,
+}
+
+pub struct Ledc {
+    ledc: LEDC,
+    order: ColorOrder,
+    dma_channel: Option<u8>,
+}
+
+impl Ledc {
+    /// Enable the LEDC clock and configure it for an LED chain
+    pub fn new(ledc: LEDC, timings: Timings, order: ColorOrder) -> Self {
+        ccu::enable_ledc_clock();
+        LEDC::enable();
+        LEDC::deassert_reset();
+        let this = Self {
+            ledc,
+            order,
+            dma_channel: None,
+        };
+        this.reset();
+        this.set_timings(timings);
+        this
+    }
+
+    /// Gate the LEDC, and drop its use of the DMA controller, returning the
+    /// underlying PAC peripheral
+    pub fn free(self) -> LEDC {
+        if self.dma_channel.is_some() {
+            DMAC::disable();
+        }
+        LEDC::disable();
+        self.ledc
+    }
+
+    pub fn set_timings(&self, timings: Timings) {
+        write_reg(LEDC_T01_TIMING_CTRL, timings.t01_bits());
+        let reset = ticks(timings.reset_ns, 0x1FFF);
+        let led_num = read_reg(LEDC_RESET_TIMING_CTRL) & 0x3FF;
+        write_reg(LEDC_RESET_TIMING_CTRL, (reset << 16) | led_num);
+    }
+
+    pub fn set_color_order(&mut self, order: ColorOrder) {
+        self.order = order;
+    }
+
+    /// Use DMA `channel` for [`Ledc::write_dma`]
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may use the channel while this `Ledc` holds it.
+    pub unsafe fn set_dma_channel(&mut self, channel: u8) {
+        assert!(channel < DMA_CHANNELS);
+        if self.dma_channel.is_none() {
+            DMAC::enable();
+            DMAC::deassert_reset();
+        }
+        self.dma_channel = Some(channel);
+    }
+
+    /// Send `colors` down the chain, feeding the FIFO from the CPU
+    pub fn write_blocking(&mut self, colors: &[Rgb]) -> Result<(), Error> {
+        if colors.is_empty() {
+            return Ok(());
+        }
+        self.start(colors.len(), false)?;
+        let mut sent = 0;
+        loop {
+            sent += fill_fifo(&colors[sent..]);
+            let status = read_reg(LEDC_INT_STS);
+            write_reg(LEDC_INT_STS, status & INT_ALL);
+            check(status)?;
+            if status & INT_TRANS_FINISH != 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Send `colors` down the chain, refilling the FIFO from its interrupt
+    pub async fn write(&mut self, colors: &[Rgb]) -> Result<(), Error> {
+        if colors.is_empty() {
+            return Ok(());
+        }
+        self.start(colors.len(), false)?;
+        let guard = StopGuard(None);
+        let mut sent = fill_fifo(colors);
+        loop {
+            let status = wait(sent < colors.len()).await;
+            check(status)?;
+            if status & INT_TRANS_FINISH != 0 {
+                core::mem::forget(guard);
+                return Ok(());
+            }
+            sent += fill_fifo(&colors[sent..]);
+        }
+    }
+
+    /// Send `colors` down the chain by DMA, staging them in `buf`
+    ///
+    /// `buf` needs a word per LED. It is written back from the data cache
+    /// before the transfer starts.
+    pub async fn write_dma(&mut self, buf: &mut [u32], colors: &[Rgb]) -> Result<(), Error> {
+        let channel = self.dma_channel.ok_or(Error::NoDmaChannel)?;
+        if colors.is_empty() {
+            return Ok(());
+        }
+        let words = buf.get_mut(..colors.len()).ok_or(Error::BufferTooSmall)?;
+        for (word, color) in words.iter_mut().zip(colors) {
+            *word = color.word();
+        }
+
+        self.start(colors.len(), true)?;
+        let guard = StopGuard(Some(channel));
+        let descriptor = Descriptor {
+            config: DMA_CFG,
+            source: words.as_ptr() as u32,
+            destination: (LEDC::PTR as usize + LEDC_DATA) as u32,
+            byte_count: (words.len() * 4) as u32,
+            parameter: DMA_PARA_NORMAL_WAIT,
+            link: DMA_LINK_END,
+        };
+        clean_dcache(words.as_ptr() as usize, words.len() * 4);
+        clean_dcache(
+            &descriptor as *const Descriptor as usize,
+            core::mem::size_of::<Descriptor>(),
+        );
+        unsafe {
+            write_dma(
+                channel,
+                DMAC_DESC_ADDR,
+                &descriptor as *const Descriptor as u32,
+            );
+            write_dma(channel, DMAC_EN, 1);
+        }
+
+        loop {
+            let status = wait(false).await;
+            check(status)?;
+            if status & INT_TRANS_FINISH != 0 {
+                core::mem::forget(guard);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Service the LEDC interrupt, waking the task waiting on a transfer
+    ///
+    /// Call this from the `MachineExternal` handler when the PLIC claims
+    /// `Interrupt::LEDC`. The FIFO request interrupt is disabled again
+    /// until the woken task has refilled the FIFO.
+    pub fn on_interrupt() {
+        let status = read_reg(LEDC_INT_STS) & INT_ALL;
+        write_reg(LEDC_INT_STS, status);
+        if status & INT_FIFO_CPUREQ != 0 {
+            write_reg(LEDC_INT_CTRL, read_reg(LEDC_INT_CTRL) & !INT_FIFO_CPUREQ);
+        }
+        STATUS.fetch_or(status, Ordering::SeqCst);
+        if let Some(waker) = riscv::interrupt::free(|cs| WAKER.borrow(cs).borrow_mut().take()) {
+            waker.wake();
+        }
+    }
+
+    /// Soft reset the LEDC, emptying the FIFO and abandoning any transfer
+    fn reset(&self) {
+        write_reg(LEDC_CTRL, read_reg(LEDC_CTRL) | CTRL_SOFT_RESET);
+        while read_reg(LEDC_CTRL) & CTRL_SOFT_RESET != 0 {}
+    }
+
+    /// Reset the LEDC and start a transfer of `len` LEDs
+    fn start(&self, len: usize, dma: bool) -> Result<(), Error> {
+        if len > MAX_LEDS {
+            return Err(Error::TooLong);
+        }
+        self.reset();
+        write_reg(LEDC_INT_CTRL, 0);
+        write_reg(LEDC_INT_STS, INT_ALL);
+        STATUS.store(0, Ordering::SeqCst);
+
+        let reset_timing = read_reg(LEDC_RESET_TIMING_CTRL) & !0x3FF;
+        write_reg(LEDC_RESET_TIMING_CTRL, reset_timing | (len as u32 - 1));
+        // Request more data or DMA when the FIFO is half empty.
+        let dma_ctrl = if dma { DMA_CTRL_EN } else { 0 };
+        write_reg(LEDC_DMA_CTRL, dma_ctrl | (FIFO_DEPTH / 2));
+        write_reg(
+            LEDC_INT_CTRL,
+            INT_CTRL_GLOBAL_EN | INT_TRANS_FINISH | INT_WAITDATA_TIMEOUT | INT_FIFO_OVERFLOW,
+        );
+
+        let ctrl = read_reg(LEDC_CTRL) & !(CTRL_DATA_LENGTH | CTRL_RGB_MODE);
+        write_reg(
+            LEDC_CTRL,
+            ctrl | ((len as u32) << CTRL_DATA_LENGTH_SHIFT)
+                | ((self.order as u32) << CTRL_RGB_MODE_SHIFT)
+                | CTRL_EN,
+        );
+        Ok(())
+    }
+}
+
+/// Abandons a transfer that failed or whose future was dropped, stopping
+/// its DMA channel if it has one
+struct StopGuard(Option<u8>);
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        write_reg(LEDC_INT_CTRL, 0);
+        if let Some(channel) = self.0 {
+            unsafe { write_dma(channel, DMAC_EN, 0) };
+        }
+        riscv::interrupt::free(|cs| WAKER.borrow(cs).borrow_mut().take());
+        write_reg(LEDC_CTRL, read_reg(LEDC_CTRL) | CTRL_SOFT_RESET);
+    }
+}
+
+/// Wait for the next LEDC interrupt, also asking for FIFO requests if
+/// there is more data to send
+async fn wait(more: bool) -> u32 {
+    poll_fn(|cx| {
+        let status = STATUS.swap(0, Ordering::SeqCst);
+        if status != 0 {
+            return Poll::Ready(status);
+        }
+        riscv::interrupt::free(|cs| {
+            *WAKER.borrow(cs).borrow_mut() = Some(cx.waker().clone());
+        });
+        // The interrupt may have come in before the waker was registered.
+        let status = STATUS.swap(0, Ordering::SeqCst);
+        if status != 0 {
+            return Poll::Ready(status);
+        }
+        if more {
+            write_reg(LEDC_INT_CTRL, read_reg(LEDC_INT_CTRL) | INT_FIFO_CPUREQ);
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+fn check(status: u32) -> Result<(), Error> {
+    if status & INT_FIFO_OVERFLOW != 0 {
+        Err(Error::Overflow)
+    } else if status & INT_WAITDATA_TIMEOUT != 0 {
+        Err(Error::Underrun)
+    } else {
+        Ok(())
+    }
+}
+
+/// Write as many colors as fit in the FIFO, returning how many that was
+fn fill_fifo(colors: &[Rgb]) -> usize {
+    let level = (read_reg(LEDC_INT_STS) & STS_FIFO_WLW) >> STS_FIFO_WLW_SHIFT;
+    let room = FIFO_DEPTH.saturating_sub(level) as usize;
+    let n = room.min(colors.len());
+    for color in &colors[..n] {
+        write_reg(LEDC_DATA, color.word());
+    }
+    n
+}
+
+/// Write back the data cache lines covering `len` bytes at `addr`, so the
+/// DMA controller sees them
+fn clean_dcache(addr: usize, len: usize) {
+    const LINE: usize = 64;
+    let mut line = addr & !(LINE - 1);
+    while line < addr + len {
+        // th.dcache.cva a0
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            core::arch::asm!(".long 0x0255000b", in("a0") line);
+        }
+        line += LINE;
+    }
+    // th.sync
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!(".long 0x0180000b");
+    }
+}
+
+fn read_reg(offset: usize) -> u32 {
+    unsafe { read_volatile((LEDC::PTR as usize + offset) as *const u32) }
+}
+
+fn write_reg(offset: usize, value: u32) {
+    unsafe { write_volatile((LEDC::PTR as usize + offset) as *mut u32, value) }
+}
+
+unsafe fn write_dma(channel: u8, offset: usize, value: u32) {
+    let chan = DMAC::PTR as usize + DMAC_CHAN_BASE + channel as usize * DMAC_CHAN_STRIDE;
+    write_volatile((chan + offset) as *mut u32, value)
+}
@@ -1,4 +1,23 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod binlog;
+pub mod board;
+pub mod ccu;
+pub mod console;
+pub mod de;
+pub mod disasm;
+pub mod gdb;
+pub mod gpio;
+pub mod ledc;
+pub mod link;
+pub mod loader;
+pub mod logger;
+pub mod monitor;
+pub mod panic;
+pub mod pinmux;
 pub mod plic;
+pub mod pwm;
+pub mod tcon;
 pub mod timer;
+pub mod trap;
+pub mod uart;
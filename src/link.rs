@@ -0,0 +1,226 @@
+//! Framed host link
+//!
+//! Board side of the protocol in the `d1-link` crate: COBS framed, CRC
+//! checked, `postcard` encoded messages on separate log, RPC and data
+//! channels. The host side is `d1_link::host::Client`, or `tools/link-cli`
+//! from the command line.
+//!
+//! ```ignore
+//! logger::set_sink(Some(link::log_sink));
+//! let mut link = Link::new(Console);
+//! link.set_call_handler(my_methods);
+//! link.run()
+//! ```
+//!
+//! RPC requests are handled inside [`Link::poll`]: memory reads and writes
+//! are built in, anything else goes to the [`CallHandler`].
+
+use core::fmt::{self, Write as _};
+
+use d1_link::{
+    encode_frame, encode_message, Decoder, Frame, LogRecord, Request, RequestBody, Response,
+    ResponseBody, MAX_ENCODED, MAX_MEM_CHUNK,
+};
+use embedded_io::{Read, ReadReady, Write};
+use serde::Serialize;
+
+use crate::{console, logger::mtime_us};
+
+pub use d1_link::{Channel, Error, RpcError};
+
+/// Handles `Call` requests: gets the method number and arguments, and
+/// writes its return value to the buffer, returning the length
+pub type CallHandler = fn(u16, &[u8], &mut [u8]) -> Result<usize, RpcError>;
+
+/// Handles frames received on the data channel
+pub type DataHandler = fn(&[u8]);
+
+pub struct Link<IO> {
+    io: IO,
+    decoder: Decoder,
+    call: Option<CallHandler>,
+    data: Option<DataHandler>,
+}
+
+impl<IO: Read + ReadReady + Write> Link<IO> {
+    pub fn new(io: IO) -> Self {
+        Self {
+            io,
+            decoder: Decoder::new(),
+            call: None,
+            data: None,
+        }
+    }
+
+    /// Release the underlying IO
+    pub fn free(self) -> IO {
+        self.io
+    }
+
+    pub fn set_call_handler(&mut self, handler: Option<CallHandler>) {
+        self.call = handler;
+    }
+
+    pub fn set_data_handler(&mut self, handler: Option<DataHandler>) {
+        self.data = handler;
+    }
+
+    /// Send a message on `channel`
+    pub fn send<T: Serialize>(&mut self, channel: Channel, msg: &T) -> Result<(), Error> {
+        let mut out = [0u8; MAX_ENCODED];
+        let n = encode_message(channel, msg, &mut out)?;
+        write_frame(&mut self.io, &out[..n]);
+        Ok(())
+    }
+
+    /// Send raw bytes on the data channel
+    pub fn send_data(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut out = [0u8; MAX_ENCODED];
+        let n = encode_frame(Channel::Data, data, &mut out)?;
+        write_frame(&mut self.io, &out[..n]);
+        Ok(())
+    }
+
+    /// Handle whatever has been received so far
+    pub fn poll(&mut self) {
+        let mut byte = [0];
+        while let Ok(true) = self.io.read_ready() {
+            if !matches!(self.io.read(&mut byte), Ok(1)) {
+                continue;
+            }
+            // Bad frames are most likely text sharing the line; drop them.
+            let Some(Ok(frame)) = self.decoder.push(byte[0]) else {
+                continue;
+            };
+            match frame.channel {
+                Channel::Rpc => handle_request(&mut self.io, self.call, frame),
+                Channel::Data => {
+                    if let Some(handler) = self.data {
+                        handler(frame.payload);
+                    }
+                }
+                // Only sent by the board
+                Channel::Log => {}
+            }
+        }
+    }
+
+    /// Serve requests forever
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.poll();
+        }
+    }
+}
+
+/// Write a whole frame with interrupts disabled, so that log records sent
+/// from interrupt handlers can't land in the middle of it
+fn write_frame<IO: Write>(io: &mut IO, frame: &[u8]) {
+    riscv::interrupt::free(|_| {
+        let _ = io.write_all(frame);
+        let _ = io.flush();
+    });
+}
+
+fn handle_request<IO: Write>(io: &mut IO, call: Option<CallHandler>, frame: Frame) {
+    // Without an id there is nothing to answer; the host will time out.
+    let Ok(request) = frame.message::<Request>() else {
+        return;
+    };
+    let mut buf = [0u8; MAX_MEM_CHUNK];
+    let body = match request.body {
+        RequestBody::Ping => ResponseBody::Pong {
+            uptime_us: mtime_us(),
+        },
+        RequestBody::ReadMem { len, .. } if len as usize > MAX_MEM_CHUNK => {
+            ResponseBody::Error(RpcError::TooLong)
+        }
+        RequestBody::ReadMem { addr, len } => {
+            let data = &mut buf[..len as usize];
+            for (i, b) in data.iter_mut().enumerate() {
+                *b = unsafe { ((addr as usize + i) as *const u8).read_volatile() };
+            }
+            ResponseBody::Memory(data)
+        }
+        RequestBody::WriteMem { data, .. } if data.len() > MAX_MEM_CHUNK => {
+            ResponseBody::Error(RpcError::TooLong)
+        }
+        RequestBody::WriteMem { addr, data } => {
+            for (i, &b) in data.iter().enumerate() {
+                unsafe { ((addr as usize + i) as *mut u8).write_volatile(b) };
+            }
+            ResponseBody::Done
+        }
+        RequestBody::Call { method, args } => match call {
+            Some(handler) => match handler(method, args, &mut buf) {
+                Ok(n) => ResponseBody::Return(&buf[..n.min(MAX_MEM_CHUNK)]),
+                Err(e) => ResponseBody::Error(e),
+            },
+            None => ResponseBody::Error(RpcError::UnknownMethod),
+        },
+    };
+
+    let mut out = [0u8; MAX_ENCODED];
+    if let Ok(n) = encode_message(
+        Channel::Rpc,
+        &Response {
+            id: request.id,
+            body,
+        },
+        &mut out,
+    ) {
+        write_frame(io, &out[..n]);
+    }
+}
+
+/// [`logger`](crate::logger) sink sending records on the log channel of a
+/// link running on the console
+pub fn log_sink(record: &log::Record, timestamp_us: u64) {
+    let mut message = Truncate {
+        buf: [0; 256],
+        len: 0,
+    };
+    let _ = write!(message, "{}", record.args());
+    let record = LogRecord {
+        level: record.level() as u8,
+        timestamp_us,
+        target: record.target(),
+        message: message.as_str(),
+    };
+
+    let mut out = [0u8; MAX_ENCODED];
+    if let Ok(n) = encode_message(Channel::Log, &record, &mut out) {
+        console::with(|uart| {
+            for &b in &out[..n] {
+                uart.write_byte(b);
+            }
+        });
+    }
+}
+
+/// Formats into a fixed buffer, dropping whatever doesn't fit
+struct Truncate {
+    buf: [u8; 256],
+    len: usize,
+}
+
+impl Truncate {
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever copied in.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl fmt::Write for Truncate {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let n = c.len_utf8();
+            if self.len + n > self.buf.len() {
+                break;
+            }
+            c.encode_utf8(&mut self.buf[self.len..]);
+            self.len += n;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,412 @@
+//! XMODEM/YMODEM image loader
+//!
+//! [`receive`] implements the receiving side of XMODEM-CRC, XMODEM-1K and
+//! (single-file) YMODEM batch transfers, telling them apart by whether the
+//! first block is numbered 0 (a YMODEM header) or 1. With YMODEM the
+//! trailing padding is trimmed using the size in the header.
+//!
+//! The [`LOAD`], [`BOOT`] and [`GO`] monitor commands wrap this for use from
+//! the `loader` binary, e.g. with `sx -k out.bin` or `sb out.bin` from the
+//! host:
+//!
+//! ```text
+//! d1> load
+//! d1> boot
+//! ```
+//!
+//! Images are staged at [`STAGING_ADDR`] and only copied to their final
+//! address (which may well be where the loader itself is running) by a
+//! small trampoline just before jumping to them.
+
+use core::{cell::Cell, fmt};
+
+use d1_link::crc16;
+use embedded_io::{Read, ReadReady, Write};
+use riscv::interrupt::Mutex;
+
+use crate::{
+    console,
+    logger::mtime_us,
+    monitor::{self, arg_u64, Command},
+};
+
+/// Where [`LOAD`] receives images to
+pub const STAGING_ADDR: usize = 0x4400_0000;
+/// Largest image [`LOAD`] accepts
+pub const STAGING_LEN: usize = 0x0400_0000;
+/// Default destination for [`BOOT`]: where `memory.x` links images
+pub const BOOT_ADDR: usize = 0x4000_0000;
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C';
+
+/// How often to ask the sender to start, and how many times
+const START_INTERVAL_US: u64 = 3_000_000;
+const START_ATTEMPTS: usize = 20;
+/// How long to wait for the next block, and between bytes of a block
+const BLOCK_TIMEOUT_US: u64 = 10_000_000;
+const BYTE_TIMEOUT_US: u64 = 1_000_000;
+const MAX_ERRORS: usize = 10;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The sender never started, or stopped responding
+    Timeout,
+    /// The sender cancelled the transfer
+    Cancelled,
+    /// The image doesn't fit in the destination buffer
+    TooLarge,
+    /// A block arrived out of sequence
+    Sequence,
+    /// Too many consecutive bad blocks
+    TooManyErrors,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::Timeout => "timed out",
+            Error::Cancelled => "cancelled by sender",
+            Error::TooLarge => "image too large",
+            Error::Sequence => "block out of sequence",
+            Error::TooManyErrors => "too many errors",
+        })
+    }
+}
+
+fn read_byte<IO: Read + ReadReady>(io: &mut IO, timeout_us: u64) -> Option<u8> {
+    let start = mtime_us();
+    loop {
+        if let Ok(true) = io.read_ready() {
+            let mut b = [0];
+            return match io.read(&mut b) {
+                Ok(1) => Some(b[0]),
+                // A receive error: treat it like a missing byte so the block
+                // is retried.
+                _ => None,
+            };
+        }
+        if mtime_us() - start > timeout_us {
+            return None;
+        }
+    }
+}
+
+fn send<IO: Write>(io: &mut IO, byte: u8) {
+    let _ = io.write_all(&[byte]);
+    let _ = io.flush();
+}
+
+fn cancel<IO: Write>(io: &mut IO) {
+    let _ = io.write_all(&[CAN, CAN, CAN]);
+    let _ = io.flush();
+}
+
+/// Drain the line until it has been quiet for a second
+fn purge<IO: Read + ReadReady>(io: &mut IO) {
+    while read_byte(io, BYTE_TIMEOUT_US).is_some() {}
+}
+
+/// Read the rest of a block after its header byte
+///
+/// Returns the block number and payload length, or `None` if the block was
+/// corrupted or cut short and should be NAKed.
+fn read_block<IO: Read + ReadReady>(
+    io: &mut IO,
+    header: u8,
+    buf: &mut [u8; 1024],
+) -> Option<(u8, usize)> {
+    let len = if header == STX { 1024 } else { 128 };
+    let mut byte = || read_byte(io, BYTE_TIMEOUT_US);
+
+    let blk = byte()?;
+    let blk_inv = byte()?;
+    for b in buf[..len].iter_mut() {
+        *b = byte()?;
+    }
+    let crc = (byte()? as u16) << 8 | byte()? as u16;
+
+    if blk != !blk_inv || crc != crc16(&buf[..len]) {
+        return None;
+    }
+    Some((blk, len))
+}
+
+/// Parse the declared file size from a YMODEM header block
+///
+/// The block holds the file name, a NUL, then the size in decimal,
+/// optionally followed by a space and more fields.
+fn ymodem_size(block: &[u8]) -> Option<usize> {
+    let name_end = block.iter().position(|&b| b == 0)?;
+    let rest = &block[name_end + 1..];
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit());
+    let mut size = 0usize;
+    let mut any = false;
+    for &d in digits {
+        size = size.checked_mul(10)?.checked_add((d - b'0') as usize)?;
+        any = true;
+    }
+    any.then_some(size)
+}
+
+/// Receive one file into `dest`, returning its length
+///
+/// Blocks until the transfer completes or fails. The receiver starts the
+/// transfer, so the sender can be started before or after calling this.
+pub fn receive<IO>(io: &mut IO, dest: &mut [u8]) -> Result<usize, Error>
+where
+    IO: Read + ReadReady + Write,
+{
+    let mut buf = [0u8; 1024];
+    let mut offset = 0;
+    let mut expected: u8 = 1;
+    let mut first = true;
+    let mut ymodem = false;
+    let mut declared = None;
+    let mut errors = 0;
+    let mut eot_seen = false;
+
+    // Ask for CRC mode until the sender starts.
+    let mut header = None;
+    for _ in 0..START_ATTEMPTS {
+        send(io, CRC_MODE);
+        header = read_byte(io, START_INTERVAL_US);
+        if header.is_some() {
+            break;
+        }
+    }
+    let mut header = header.ok_or(Error::Timeout)?;
+
+    loop {
+        match header {
+            SOH | STX => match read_block(io, header, &mut buf) {
+                Some((0, len)) if first => {
+                    // YMODEM header. An empty name means an empty batch.
+                    if buf[0] == 0 {
+                        send(io, ACK);
+                        return Ok(0);
+                    }
+                    ymodem = true;
+                    declared = ymodem_size(&buf[..len]);
+                    if matches!(declared, Some(size) if size > dest.len()) {
+                        cancel(io);
+                        return Err(Error::TooLarge);
+                    }
+                    first = false;
+                    errors = 0;
+                    send(io, ACK);
+                    send(io, CRC_MODE);
+                }
+                Some((blk, len)) if blk == expected => {
+                    let Some(chunk) = dest.get_mut(offset..offset + len) else {
+                        cancel(io);
+                        return Err(Error::TooLarge);
+                    };
+                    chunk.copy_from_slice(&buf[..len]);
+                    offset += len;
+                    expected = expected.wrapping_add(1);
+                    first = false;
+                    errors = 0;
+                    send(io, ACK);
+                }
+                // Our ACK for the previous block was lost.
+                Some((blk, _)) if blk == expected.wrapping_sub(1) => send(io, ACK),
+                Some(_) => {
+                    cancel(io);
+                    return Err(Error::Sequence);
+                }
+                None => {
+                    errors += 1;
+                    if errors == MAX_ERRORS {
+                        cancel(io);
+                        return Err(Error::TooManyErrors);
+                    }
+                    purge(io);
+                    send(io, NAK);
+                }
+            },
+            EOT if ymodem && !eot_seen => {
+                // YMODEM senders expect the first EOT to be NAKed.
+                eot_seen = true;
+                send(io, NAK);
+            }
+            EOT => {
+                send(io, ACK);
+                if ymodem {
+                    // Take the (empty) header that ends the batch.
+                    send(io, CRC_MODE);
+                    if let Some(h @ (SOH | STX)) = read_byte(io, BYTE_TIMEOUT_US) {
+                        if read_block(io, h, &mut buf).is_some() {
+                            send(io, ACK);
+                        }
+                    }
+                }
+                break;
+            }
+            CAN => {
+                if read_byte(io, BYTE_TIMEOUT_US) == Some(CAN) {
+                    return Err(Error::Cancelled);
+                }
+            }
+            // Line noise between blocks
+            _ => {}
+        }
+
+        header = read_byte(io, BLOCK_TIMEOUT_US).ok_or_else(|| {
+            cancel(io);
+            Error::Timeout
+        })?;
+    }
+
+    Ok(match declared {
+        Some(size) => size.min(offset),
+        None => offset,
+    })
+}
+
+extern "C" {
+    fn d1_loader_trampoline(dst: usize, src: usize, len: usize, entry: usize) -> !;
+    static d1_loader_trampoline_end: u8;
+}
+
+// Copies `len` bytes from `src` to `dst`, then jumps to `entry`. Only uses
+// PC-relative branches, so it can be copied anywhere and run from there.
+#[cfg(target_arch = "riscv64")]
+core::arch::global_asm!(
+    r#"
+    .section .text.d1_loader_trampoline, "ax"
+    .global d1_loader_trampoline
+    .global d1_loader_trampoline_end
+    .align 2
+d1_loader_trampoline:
+    beqz a2, 2f
+1:
+    lbu t0, 0(a1)
+    sb t0, 0(a0)
+    addi a0, a0, 1
+    addi a1, a1, 1
+    addi a2, a2, -1
+    bnez a2, 1b
+2:
+    fence.i
+    jr a3
+d1_loader_trampoline_end:
+"#
+);
+
+/// Jump to `entry` with interrupts disabled
+///
+/// # Safety
+///
+/// There has to be code at `entry` that doesn't expect anything of the
+/// state the current program leaves behind.
+pub unsafe fn jump(entry: usize) -> ! {
+    riscv::interrupt::disable();
+    console::with(|uart| uart.flush_blocking());
+    #[cfg(target_arch = "riscv64")]
+    core::arch::asm!("fence.i");
+    let entry: extern "C" fn() -> ! = core::mem::transmute(entry);
+    entry()
+}
+
+/// Copy `len` bytes from `src` to `dst` and jump to `dst`
+///
+/// The copy is done by a trampoline placed just after the source image,
+/// so `dst` may overlap the currently running program, but must not
+/// overlap the source or the trampoline.
+///
+/// # Safety
+///
+/// As for [`jump`]; also the source, destination and the space after the
+/// source must all be valid RAM.
+pub unsafe fn copy_and_jump(dst: usize, src: usize, len: usize) -> ! {
+    riscv::interrupt::disable();
+    console::with(|uart| uart.flush_blocking());
+
+    let start = d1_loader_trampoline as usize;
+    let size = &d1_loader_trampoline_end as *const u8 as usize - start;
+    let tramp = (src + len + 7) & !7;
+    core::ptr::copy_nonoverlapping(start as *const u8, tramp as *mut u8, size);
+    #[cfg(target_arch = "riscv64")]
+    core::arch::asm!("fence.i");
+
+    let tramp: extern "C" fn(usize, usize, usize, usize) -> ! = core::mem::transmute(tramp);
+    tramp(dst, src, len, dst)
+}
+
+/// Length of the image in the staging area, if any
+static STAGED: Mutex<Cell<Option<usize>>> = Mutex::new(Cell::new(None));
+
+/// Add [`LOAD`], [`BOOT`] and [`GO`] to the monitor
+pub fn register_commands() -> Result<(), monitor::TableFull> {
+    monitor::register(&LOAD)?;
+    monitor::register(&BOOT)?;
+    monitor::register(&GO)
+}
+
+pub static LOAD: Command = Command {
+    name: "load",
+    usage: "",
+    help: "receive an image with XMODEM/YMODEM",
+    run: cmd_load,
+};
+
+pub static BOOT: Command = Command {
+    name: "boot",
+    usage: "[addr]",
+    help: "copy the loaded image to addr (default 0x40000000) and run it",
+    run: cmd_boot,
+};
+
+pub static GO: Command = Command {
+    name: "go",
+    usage: "<addr>",
+    help: "jump to addr",
+    run: cmd_go,
+};
+
+fn cmd_load(out: &mut dyn fmt::Write, _args: &[&str]) -> Result<(), &'static str> {
+    let _ = writeln!(
+        out,
+        "receiving to {:#x}, start the XMODEM/YMODEM upload now",
+        STAGING_ADDR
+    );
+    let dest = unsafe { core::slice::from_raw_parts_mut(STAGING_ADDR as *mut u8, STAGING_LEN) };
+    let res = console::with(|uart| receive(uart, dest)).ok_or("console unavailable")?;
+    // Give the host's terminal a moment to get out of file transfer mode.
+    let start = mtime_us();
+    while mtime_us() - start < 500_000 {}
+
+    match res {
+        Ok(len) => {
+            riscv::interrupt::free(|cs| STAGED.borrow(cs).set(Some(len)));
+            let _ = writeln!(out, "received {} bytes", len);
+        }
+        Err(e) => {
+            let _ = writeln!(out, "transfer failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_boot(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str> {
+    let dst = arg_u64(args, 1, Some(BOOT_ADDR as u64))? as usize;
+    let len = riscv::interrupt::free(|cs| STAGED.borrow(cs).get()).ok_or("nothing loaded")?;
+    let end = dst.checked_add(len).ok_or("destination out of range")?;
+    if dst < STAGING_ADDR + STAGING_LEN + 4096 && STAGING_ADDR < end {
+        return Err("destination overlaps the staging area");
+    }
+    let _ = writeln!(out, "booting {} bytes at {:#x}", len, dst);
+    unsafe { copy_and_jump(dst, STAGING_ADDR, len) }
+}
+
+fn cmd_go(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str> {
+    let entry = arg_u64(args, 1, None)? as usize;
+    let _ = writeln!(out, "jumping to {:#x}", entry);
+    unsafe { jump(entry) }
+}
@@ -0,0 +1,194 @@
+//! [`log`] backend writing to the [console](crate::console)
+//!
+//! Records are filtered three ways:
+//!
+//! * at compile time, by the `log` crate's `max_level_*` and
+//!   `release_max_level_*` features (this crate enables
+//!   `release_max_level_info`, so `debug!` and `trace!` compile away in
+//!   release builds);
+//! * at run time, by a global level set with [`set_level`];
+//! * per module, by overrides set with [`set_module_level`], matched on the
+//!   longest prefix of the record's target.
+//!
+//! Each line is prefixed with a timestamp in seconds since reset. Records
+//! can be sent somewhere other than the console with [`set_sink`].
+
+use core::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+};
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use riscv::interrupt::Mutex;
+
+use crate::ccu::HOSC_HZ;
+
+/// Maximum number of per-module overrides
+pub const MAX_MODULE_FILTERS: usize = 8;
+
+/// Returned by [`set_module_level`] when all override slots are in use
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TooManyFilters;
+
+/// Source of timestamps, in microseconds
+pub type TimestampFn = fn() -> u64;
+
+/// Receives records that passed the filters, with their timestamp
+pub type SinkFn = fn(&Record, u64);
+
+struct Logger {
+    level: Mutex<Cell<LevelFilter>>,
+    modules: Mutex<RefCell<[Option<(&'static str, LevelFilter)>; MAX_MODULE_FILTERS]>>,
+    timestamp: Mutex<Cell<TimestampFn>>,
+    sink: Mutex<Cell<Option<SinkFn>>>,
+}
+
+static LOGGER: Logger = Logger {
+    level: Mutex::new(Cell::new(LevelFilter::Info)),
+    modules: Mutex::new(RefCell::new([None; MAX_MODULE_FILTERS])),
+    timestamp: Mutex::new(Cell::new(mtime_us as TimestampFn)),
+    sink: Mutex::new(Cell::new(None)),
+};
+
+/// Install the logger with the given global level
+pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_logger(&LOGGER)?;
+    set_level(level);
+    Ok(())
+}
+
+/// Change the global level
+pub fn set_level(level: LevelFilter) {
+    riscv::interrupt::free(|cs| LOGGER.level.borrow(cs).set(level));
+    update_max_level();
+}
+
+/// Override the level for `module` and everything below it
+///
+/// `module` is a target prefix such as `"d1_playground::plic"`. Setting an
+/// existing override again replaces it.
+pub fn set_module_level(module: &'static str, level: LevelFilter) -> Result<(), TooManyFilters> {
+    riscv::interrupt::free(|cs| {
+        let mut modules = LOGGER.modules.borrow(cs).borrow_mut();
+        let slot = match modules
+            .iter()
+            .position(|m| matches!(m, Some((name, _)) if *name == module))
+        {
+            Some(i) => i,
+            None => modules
+                .iter()
+                .position(Option::is_none)
+                .ok_or(TooManyFilters)?,
+        };
+        modules[slot] = Some((module, level));
+        Ok(())
+    })?;
+    update_max_level();
+    Ok(())
+}
+
+/// Remove the override for `module`, if any
+pub fn clear_module_level(module: &'static str) {
+    riscv::interrupt::free(|cs| {
+        for slot in LOGGER.modules.borrow(cs).borrow_mut().iter_mut() {
+            if matches!(slot, Some((name, _)) if *name == module) {
+                *slot = None;
+            }
+        }
+    });
+    update_max_level();
+}
+
+/// Use `f` as the timestamp source instead of the `time` CSR
+pub fn set_timestamp_source(f: TimestampFn) {
+    riscv::interrupt::free(|cs| LOGGER.timestamp.borrow(cs).set(f));
+}
+
+/// Send records to `sink` instead of printing them, or to the console again
+/// if `None`
+pub fn set_sink(sink: Option<SinkFn>) {
+    riscv::interrupt::free(|cs| LOGGER.sink.borrow(cs).set(sink));
+}
+
+/// Current time from the configured timestamp source, in microseconds
+pub fn timestamp_us() -> u64 {
+    riscv::interrupt::free(|cs| LOGGER.timestamp.borrow(cs).get())()
+}
+
+/// Microseconds since reset, from the D1's 64-bit system counter
+///
+/// The `time` CSR reads the counter the CCU clocks from the 24 MHz
+/// oscillator, the same one behind the C906's `mtime`. The TIMER
+/// peripheral's channels aren't used: they are 32 bits, wrapping in under
+/// three minutes at that rate, and belong to the application through
+/// [`Timers`](crate::timer::Timers).
+pub fn mtime_us() -> u64 {
+    riscv::register::time::read64() / (HOSC_HZ as u64 / 1_000_000)
+}
+
+/// The `log` macros check against `log::max_level()` before calling into
+/// the logger, so it has to be the most verbose of all the filters.
+fn update_max_level() {
+    let max = riscv::interrupt::free(|cs| {
+        LOGGER
+            .modules
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .flatten()
+            .map(|(_, level)| *level)
+            .fold(LOGGER.level.borrow(cs).get(), core::cmp::max)
+    });
+    log::set_max_level(max);
+}
+
+impl Logger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        riscv::interrupt::free(|cs| {
+            self.modules
+                .borrow(cs)
+                .borrow()
+                .iter()
+                .flatten()
+                .filter(|(name, _)| {
+                    target.starts_with(name)
+                        && matches!(target.as_bytes().get(name.len()), None | Some(b':'))
+                })
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, level)| *level)
+                .unwrap_or_else(|| self.level.borrow(cs).get())
+        })
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = timestamp_us();
+        if let Some(sink) = riscv::interrupt::free(|cs| self.sink.borrow(cs).get()) {
+            sink(record, now);
+            return;
+        }
+        crate::console::with(|uart| {
+            let _ = write!(
+                uart,
+                "[{:>5}.{:06}] {:<5} {}: {}\r\n",
+                now / 1_000_000,
+                now % 1_000_000,
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        });
+    }
+
+    fn flush(&self) {
+        crate::console::with(|uart| uart.flush_blocking());
+    }
+}
@@ -2,76 +2,28 @@
 #![no_main]
 
 use d1_pac::{Interrupt, TIMER};
-use panic_halt as _;
-
-mod de;
 
+use d1_playground::board::Board;
+use d1_playground::gpio;
+use d1_playground::ledc::Rgb;
 use d1_playground::plic::{Plic, Priority};
 use d1_playground::timer::{Timer, TimerMode, TimerPrescaler, TimerSource, Timers};
-
-struct Uart(d1_pac::UART0);
-static mut PRINTER: Option<Uart> = None;
-impl core::fmt::Write for Uart {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for byte in s.as_bytes() {
-            self.0.thr().write(|w| unsafe { w.thr().bits(*byte) });
-            while self.0.usr.read().tfnf().bit_is_clear() {}
-        }
-        Ok(())
-    }
-}
-pub fn _print(args: core::fmt::Arguments) {
-    use core::fmt::Write;
-    unsafe {
-        PRINTER.as_mut().unwrap().write_fmt(args).ok();
-    }
-}
-#[macro_export]
-macro_rules! print {
-    ($($arg:tt)*) => {
-        $crate::_print(core::format_args!($($arg)*));
-    }
-}
-#[macro_export]
-macro_rules! println {
-    ($($arg:tt)*) => {
-        $crate::_print(core::format_args!($($arg)*));
-        $crate::print!("\r\n");
-    }
-}
+use d1_playground::{console, logger, println};
 
 #[riscv_rt::entry]
 fn main() -> ! {
     let p = d1_pac::Peripherals::take().unwrap();
 
-    // Enable UART0 clock.
-    let ccu = &p.CCU;
-    ccu.uart_bgr
-        .write(|w| w.uart0_gating().pass().uart0_rst().deassert());
-
-    // Set PC1 LED to output.
-    let gpio = &p.GPIO;
-    gpio.pc_cfg0
-        .write(|w| w.pc1_select().output().pc0_select().ledc_do());
-
-    // Set PB8 and PB9 to function 6, UART0, internal pullup.
-    gpio.pb_cfg1
-        .write(|w| w.pb8_select().uart0_tx().pb9_select().uart0_rx());
-    gpio.pb_pull0
-        .write(|w| w.pc8_pull().pull_up().pc9_pull().pull_up());
-
-    // Configure UART0 for 115200 8n1.
-    // By default APB1 is 24MHz, use divisor 13 for 115200.
-    let uart0 = p.UART0;
-    uart0.mcr.write(|w| unsafe { w.bits(0) });
-    uart0.fcr().write(|w| w.fifoe().set_bit());
-    uart0.halt.write(|w| w.halt_tx().enabled());
-    uart0.lcr.write(|w| w.dlab().divisor_latch());
-    uart0.dll().write(|w| unsafe { w.dll().bits(13) });
-    uart0.dlh().write(|w| unsafe { w.dlh().bits(0) });
-    uart0.lcr.write(|w| w.dlab().rx_buffer().dls().eight());
-    uart0.halt.write(|w| w.halt_tx().disabled());
-    unsafe { PRINTER = Some(Uart(uart0)) };
+    let board = Board::new(p.GPIO, p.UART0, p.LEDC);
+    let mut led = board.status_led;
+    console::init(board.debug_uart);
+
+    // Light the WS2812, if there's one besides the status LED, a dim blue.
+    if let Some(mut ledc) = board.rgb_led {
+        let _ = ledc.write_blocking(&[Rgb::new(0, 0, 16)]);
+    }
+
+    logger::init(log::LevelFilter::Info).unwrap();
 
     // Set up timers
     let Timers {
@@ -114,13 +66,13 @@ fn main() -> ! {
         // and 4s for timer 1, for a 25% duty cycle
         timer0.start_counter(3_000_000);
         timer1.start_counter(3_000_000);
-        gpio.pc_dat.write(|w| unsafe { w.bits(2) });
+        led.on();
 
         unsafe { riscv::asm::wfi() };
         // while !timer0.get_and_clear_interrupt() { }
         println!("T0 DONE");
 
-        gpio.pc_dat.write(|w| unsafe { w.bits(0) });
+        led.off();
         unsafe { riscv::asm::wfi() };
         println!("T1 DONE");
     }
@@ -149,6 +101,7 @@ fn im_an_interrupt() {
             // Wait for the interrupt to clear to avoid repeat interrupts
             while timer.tmr_irq_sta.read().tmr1_irq_pend().bit_is_set() {}
         }
+        x if gpio::handle_interrupt(x) => {}
         x => {
             println!("Unexpected claim: {:?}", x);
             panic!();
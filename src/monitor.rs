@@ -0,0 +1,687 @@
+//! Interactive monitor shell
+//!
+//! A small command line for bring-up work, reading from and writing to any
+//! `embedded-io` stream, normally the [`Console`](crate::console::Console):
+//!
+//! ```ignore
+//! let mut mon = Monitor::new(console::Console);
+//! mon.run();
+//! ```
+//!
+//! The line editor understands backspace, left/right arrows, up/down for
+//! history, Ctrl-A/Ctrl-E, Ctrl-U and Ctrl-C. Drivers can add their own
+//! commands with [`register`]; see [`Command`].
+
+use core::{
+    cell::RefCell,
+    fmt::{self, Write as _},
+    ptr::{read_volatile, write_volatile},
+};
+
+use d1_pac::{Interrupt, PLIC, TIMER};
+use embedded_io::{Read, ReadReady, Write};
+use riscv::interrupt::Mutex;
+
+use crate::{
+    gpio::{self, DAT, PORT_STRIDE},
+    pinmux,
+};
+
+const PROMPT: &str = "d1> ";
+const LINE_LEN: usize = 96;
+const HISTORY_LEN: usize = 8;
+const MAX_ARGS: usize = 8;
+const MAX_COMMANDS: usize = 16;
+
+/// A shell command
+///
+/// `args[0]` is the command name. Output goes to `out`; on failure, return
+/// a short message and it will be printed along with the command's usage.
+pub struct Command {
+    pub name: &'static str,
+    /// Arguments, for `help`
+    pub usage: &'static str,
+    /// One-line description, for `help`
+    pub help: &'static str,
+    pub run: fn(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str>,
+}
+
+/// Returned by [`register`] when the command table is full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TableFull;
+
+static COMMANDS: Mutex<RefCell<[Option<&'static Command>; MAX_COMMANDS]>> =
+    Mutex::new(RefCell::new([None; MAX_COMMANDS]));
+
+/// Add a command to every monitor
+///
+/// A registered command with the same name as a built-in one replaces it.
+pub fn register(command: &'static Command) -> Result<(), TableFull> {
+    riscv::interrupt::free(|cs| {
+        let mut commands = COMMANDS.borrow(cs).borrow_mut();
+        let slot = commands.iter_mut().find(|c| c.is_none()).ok_or(TableFull)?;
+        *slot = Some(command);
+        Ok(())
+    })
+}
+
+fn find(name: &str) -> Option<&'static Command> {
+    let registered = riscv::interrupt::free(|cs| {
+        COMMANDS
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .flatten()
+            .find(|c| c.name == name)
+            .copied()
+    });
+    registered.or_else(|| BUILTINS.iter().find(|c| c.name == name))
+}
+
+#[derive(Copy, Clone)]
+struct Line {
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Self = Self {
+        buf: [0; LINE_LEN],
+        len: 0,
+    };
+
+    fn as_str(&self) -> &str {
+        // Only printable ASCII is ever inserted.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+/// A monitor session on one stream
+pub struct Monitor<IO> {
+    io: IO,
+    line: Line,
+    cursor: usize,
+    escape: Escape,
+    history: [Line; HISTORY_LEN],
+    /// Number of entries in `history`, newest first
+    history_len: usize,
+    /// Position while browsing with up/down; 0 is the line being edited
+    history_pos: usize,
+    /// The line being edited, saved while browsing history
+    scratch: Line,
+}
+
+impl<IO: Read + ReadReady + Write> Monitor<IO> {
+    pub fn new(io: IO) -> Self {
+        Self {
+            io,
+            line: Line::EMPTY,
+            cursor: 0,
+            escape: Escape::None,
+            history: [Line::EMPTY; HISTORY_LEN],
+            history_len: 0,
+            history_pos: 0,
+            scratch: Line::EMPTY,
+        }
+    }
+
+    /// Give back the underlying stream
+    pub fn free(self) -> IO {
+        self.io
+    }
+
+    /// Print a banner and prompt, then process input forever
+    pub fn run(&mut self) -> ! {
+        let _ = self
+            .io
+            .write_all(b"\r\nd1-playground monitor, 'help' for commands\r\n");
+        self.prompt();
+        loop {
+            let mut byte = [0];
+            if let Ok(1) = self.io.read(&mut byte) {
+                self.input(byte[0]);
+            }
+        }
+    }
+
+    /// Process any input that is already available, without blocking
+    ///
+    /// Call [`Monitor::prompt`] once before the first `poll`.
+    pub fn poll(&mut self) {
+        while let Ok(true) = self.io.read_ready() {
+            let mut byte = [0];
+            if let Ok(1) = self.io.read(&mut byte) {
+                self.input(byte[0]);
+            }
+        }
+    }
+
+    /// Print the prompt and the current line
+    pub fn prompt(&mut self) {
+        let _ = self.io.write_all(PROMPT.as_bytes());
+        let _ = self.io.write_all(&self.line.buf[..self.line.len]);
+        self.move_left(self.line.len - self.cursor);
+    }
+
+    /// Feed one byte of input to the line editor
+    pub fn input(&mut self, byte: u8) {
+        match (self.escape, byte) {
+            (Escape::Esc, b'[') => {
+                self.escape = Escape::Csi;
+                return;
+            }
+            (Escape::Csi, b'A') => self.history_prev(),
+            (Escape::Csi, b'B') => self.history_next(),
+            (Escape::Csi, b'C') => self.right(),
+            (Escape::Csi, b'D') => self.left(),
+            // Swallow the parameters of sequences we don't handle.
+            (Escape::Csi, b'0'..=b'9' | b';') => return,
+            (Escape::Esc | Escape::Csi, _) => {}
+            (Escape::None, 0x1B) => {
+                self.escape = Escape::Esc;
+                return;
+            }
+            (Escape::None, b'\r' | b'\n') => self.enter(),
+            (Escape::None, 0x08 | 0x7F) => self.backspace(),
+            (Escape::None, 0x01) => {
+                self.move_left(self.cursor);
+                self.cursor = 0;
+            }
+            (Escape::None, 0x05) => {
+                let n = self.line.len - self.cursor;
+                self.move_right(n);
+                self.cursor = self.line.len;
+            }
+            (Escape::None, 0x03) => {
+                let _ = self.io.write_all(b"^C\r\n");
+                self.line.len = 0;
+                self.cursor = 0;
+                self.history_pos = 0;
+                self.prompt();
+            }
+            (Escape::None, 0x15) => self.replace_line(Line::EMPTY),
+            (Escape::None, 0x20..=0x7E) => self.insert(byte),
+            _ => {}
+        }
+        self.escape = Escape::None;
+    }
+
+    fn insert(&mut self, byte: u8) {
+        if self.line.len == LINE_LEN {
+            return;
+        }
+        let (cursor, len) = (self.cursor, self.line.len);
+        self.line.buf.copy_within(cursor..len, cursor + 1);
+        self.line.buf[cursor] = byte;
+        self.line.len += 1;
+        self.cursor += 1;
+        let _ = self.io.write_all(&self.line.buf[cursor..=len]);
+        self.move_left(len - cursor);
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor == 0 {
+            return;
+        }
+        let (cursor, len) = (self.cursor, self.line.len);
+        self.line.buf.copy_within(cursor..len, cursor - 1);
+        self.line.len -= 1;
+        self.cursor -= 1;
+        let _ = self.io.write_all(b"\x08");
+        let _ = self
+            .io
+            .write_all(&self.line.buf[self.cursor..self.line.len]);
+        let _ = self.io.write_all(b" ");
+        self.move_left(len - cursor + 1);
+    }
+
+    fn left(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.move_left(1);
+        }
+    }
+
+    fn right(&mut self) {
+        if self.cursor < self.line.len {
+            self.cursor += 1;
+            self.move_right(1);
+        }
+    }
+
+    fn move_left(&mut self, n: usize) {
+        if n > 0 {
+            let _ = write!(Out(&mut self.io), "\x1B[{}D", n);
+        }
+    }
+
+    fn move_right(&mut self, n: usize) {
+        if n > 0 {
+            let _ = write!(Out(&mut self.io), "\x1B[{}C", n);
+        }
+    }
+
+    /// Replace the line on screen with `line`, cursor at the end
+    fn replace_line(&mut self, line: Line) {
+        self.move_left(self.cursor);
+        let _ = self.io.write_all(b"\x1B[K");
+        self.line = line;
+        self.cursor = line.len;
+        let _ = self.io.write_all(&line.buf[..line.len]);
+    }
+
+    fn history_prev(&mut self) {
+        if self.history_pos == self.history_len {
+            return;
+        }
+        if self.history_pos == 0 {
+            self.scratch = self.line;
+        }
+        self.history_pos += 1;
+        self.replace_line(self.history[self.history_pos - 1]);
+    }
+
+    fn history_next(&mut self) {
+        match self.history_pos {
+            0 => {}
+            1 => {
+                self.history_pos = 0;
+                self.replace_line(self.scratch);
+            }
+            _ => {
+                self.history_pos -= 1;
+                self.replace_line(self.history[self.history_pos - 1]);
+            }
+        }
+    }
+
+    fn enter(&mut self) {
+        let _ = self.io.write_all(b"\r\n");
+        let line = self.line;
+        self.line.len = 0;
+        self.cursor = 0;
+        self.history_pos = 0;
+
+        let text = line.as_str().trim();
+        if !text.is_empty() {
+            // Don't fill the history with repeats of the same command.
+            if self.history_len == 0 || self.history[0].as_str().trim() != text {
+                self.history.copy_within(0..HISTORY_LEN - 1, 1);
+                self.history[0] = line;
+                self.history_len = (self.history_len + 1).min(HISTORY_LEN);
+            }
+            self.execute(text);
+        }
+        self.prompt();
+    }
+
+    fn execute(&mut self, text: &str) {
+        let mut args = [""; MAX_ARGS];
+        let mut argc = 0;
+        for word in text.split_ascii_whitespace() {
+            if argc == MAX_ARGS {
+                let _ = self.io.write_all(b"too many arguments\r\n");
+                return;
+            }
+            args[argc] = word;
+            argc += 1;
+        }
+
+        let mut out = Out(&mut self.io);
+        match find(args[0]) {
+            Some(cmd) => {
+                if let Err(msg) = (cmd.run)(&mut out, &args[..argc]) {
+                    let _ = write!(
+                        out,
+                        "error: {}\r\nusage: {} {}\r\n",
+                        msg, cmd.name, cmd.usage
+                    );
+                }
+            }
+            None => {
+                let _ = write!(out, "unknown command '{}', try 'help'\r\n", args[0]);
+            }
+        }
+    }
+}
+
+/// `fmt::Write` adapter for the monitor's stream, translating `\n` to `\r\n`
+struct Out<'a, IO>(&'a mut IO);
+
+impl<IO: Write> fmt::Write for Out<'_, IO> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.0.write_all(b"\r\n").map_err(|_| fmt::Error)?;
+            }
+            self.0.write_all(part.as_bytes()).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+// Argument parsing helpers, also useful to registered commands
+
+/// Parse a number in decimal, or hex with a `0x` prefix; `_` is ignored
+pub fn parse_u64(s: &str) -> Result<u64, &'static str> {
+    let (digits, radix) = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => (hex, 16),
+        None => (s, 10),
+    };
+    let mut value: u64 = 0;
+    let mut any = false;
+    for c in digits.chars().filter(|&c| c != '_') {
+        let d = c.to_digit(radix).ok_or("invalid number")?;
+        value = value
+            .checked_mul(radix as u64)
+            .and_then(|v| v.checked_add(d as u64))
+            .ok_or("number too large")?;
+        any = true;
+    }
+    if any {
+        Ok(value)
+    } else {
+        Err("invalid number")
+    }
+}
+
+/// Get and parse the argument at `idx`, or `default` if it is missing
+pub fn arg_u64(args: &[&str], idx: usize, default: Option<u64>) -> Result<u64, &'static str> {
+    match args.get(idx) {
+        Some(s) => parse_u64(s),
+        None => default.ok_or("missing argument"),
+    }
+}
+
+fn access_width(args: &[&str], idx: usize) -> Result<usize, &'static str> {
+    match args.get(idx).copied() {
+        None | Some("w") => Ok(4),
+        Some("b") => Ok(1),
+        Some("h") => Ok(2),
+        Some("d") => Ok(8),
+        Some(_) => Err("width must be b, h, w or d"),
+    }
+}
+
+// Built-in commands
+
+static BUILTINS: &[Command] = &[
+    Command {
+        name: "help",
+        usage: "",
+        help: "list commands",
+        run: cmd_help,
+    },
+    Command {
+        name: "peek",
+        usage: "<addr> [b|h|w|d]",
+        help: "read memory or a register",
+        run: cmd_peek,
+    },
+    Command {
+        name: "poke",
+        usage: "<addr> <value> [b|h|w|d]",
+        help: "write memory or a register",
+        run: cmd_poke,
+    },
+    Command {
+        name: "dump",
+        usage: "<addr> [len]",
+        help: "hex dump memory",
+        run: cmd_dump,
+    },
+    Command {
+        name: "timer",
+        usage: "",
+        help: "show TIMER0/1 state",
+        run: cmd_timer,
+    },
+    Command {
+        name: "plic",
+        usage: "",
+        help: "show enabled and pending interrupts",
+        run: cmd_plic,
+    },
+    Command {
+        name: "gpio",
+        usage: "<pin> [0|1|t|in]",
+        help: "read, set, toggle or float a pin, e.g. 'gpio pc1 t'",
+        run: cmd_gpio,
+    },
+    Command {
+        name: "reset",
+        usage: "",
+        help: "reset the SoC",
+        run: cmd_reset,
+    },
+];
+
+fn cmd_help(out: &mut dyn fmt::Write, _args: &[&str]) -> Result<(), &'static str> {
+    let mut show = |cmd: &Command| {
+        let _ = writeln!(out, "  {:<6} {:<24} {}", cmd.name, cmd.usage, cmd.help);
+    };
+    for cmd in BUILTINS {
+        show(cmd);
+    }
+    riscv::interrupt::free(|cs| {
+        for cmd in COMMANDS.borrow(cs).borrow().iter().flatten() {
+            show(cmd);
+        }
+    });
+    Ok(())
+}
+
+fn cmd_peek(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str> {
+    let addr = arg_u64(args, 1, None)? as usize;
+    let width = access_width(args, 2)?;
+    if addr % width != 0 {
+        return Err("unaligned address");
+    }
+    let value = unsafe {
+        match width {
+            1 => read_volatile(addr as *const u8) as u64,
+            2 => read_volatile(addr as *const u16) as u64,
+            4 => read_volatile(addr as *const u32) as u64,
+            _ => read_volatile(addr as *const u64),
+        }
+    };
+    let _ = writeln!(out, "{:#010x}: {:#0w$x}", addr, value, w = width * 2 + 2);
+    Ok(())
+}
+
+fn cmd_poke(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str> {
+    let addr = arg_u64(args, 1, None)? as usize;
+    let value = arg_u64(args, 2, None)?;
+    let width = access_width(args, 3)?;
+    if addr % width != 0 {
+        return Err("unaligned address");
+    }
+    if width < 8 && value >> (width * 8) != 0 {
+        return Err("value too large for width");
+    }
+    unsafe {
+        match width {
+            1 => write_volatile(addr as *mut u8, value as u8),
+            2 => write_volatile(addr as *mut u16, value as u16),
+            4 => write_volatile(addr as *mut u32, value as u32),
+            _ => write_volatile(addr as *mut u64, value),
+        }
+    }
+    let _ = writeln!(out, "{:#010x} <- {:#x}", addr, value);
+    Ok(())
+}
+
+fn cmd_dump(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str> {
+    let addr = arg_u64(args, 1, None)? as usize;
+    let len = arg_u64(args, 2, Some(64))? as usize;
+    let end = addr.checked_add(len).ok_or("range wraps around")?;
+    let mut bytes = [0u8; 16];
+    for row in (addr..end).step_by(16) {
+        let n = (end - row).min(16);
+        for (i, b) in bytes[..n].iter_mut().enumerate() {
+            *b = unsafe { read_volatile((row + i) as *const u8) };
+        }
+        let _ = write!(out, "{:08x}: ", row);
+        for (i, b) in bytes.iter().enumerate() {
+            let _ = if i < n {
+                write!(out, "{:02x} ", b)
+            } else {
+                out.write_str("   ")
+            };
+        }
+        let _ = out.write_str(" ");
+        for &b in &bytes[..n] {
+            let c = if (0x20..0x7F).contains(&b) {
+                b as char
+            } else {
+                '.'
+            };
+            let _ = out.write_char(c);
+        }
+        let _ = writeln!(out);
+    }
+    Ok(())
+}
+
+fn cmd_timer(out: &mut dyn fmt::Write, _args: &[&str]) -> Result<(), &'static str> {
+    let timer = unsafe { &*TIMER::PTR };
+    let _ = writeln!(
+        out,
+        "irq_en: {:#04x}  irq_sta: {:#04x}",
+        timer.tmr_irq_en.read().bits(),
+        timer.tmr_irq_sta.read().bits()
+    );
+    let _ = writeln!(
+        out,
+        "timer0: ctrl {:#06x}  interval {:>10}  current {:>10}",
+        timer.tmr0_ctrl.read().bits(),
+        timer.tmr0_intv_value.read().bits(),
+        timer.tmr0_cur_value.read().bits()
+    );
+    let _ = writeln!(
+        out,
+        "timer1: ctrl {:#06x}  interval {:>10}  current {:>10}",
+        timer.tmr1_ctrl.read().bits(),
+        timer.tmr1_intv_value.read().bits(),
+        timer.tmr1_cur_value.read().bits()
+    );
+    Ok(())
+}
+
+fn cmd_plic(out: &mut dyn fmt::Write, _args: &[&str]) -> Result<(), &'static str> {
+    let plic = unsafe { &*PLIC::PTR };
+    let _ = writeln!(out, "threshold: {}", plic.mth.read().bits());
+    for (word, (mie, ip)) in plic.mie.iter().zip(plic.ip.iter()).enumerate() {
+        let (enabled, pending) = (mie.read().bits(), ip.read().bits());
+        for bit in 0..32 {
+            let (en, pend) = (enabled & (1 << bit) != 0, pending & (1 << bit) != 0);
+            if !en && !pend {
+                continue;
+            }
+            let nr = word * 32 + bit;
+            let prio = plic.prio[nr].read().bits();
+            let _ = match Interrupt::try_from(nr as u8) {
+                Ok(irq) => write!(out, "{:>4} {:?}", nr, irq),
+                Err(_) => write!(out, "{:>4} ?", nr),
+            };
+            let _ = writeln!(
+                out,
+                " prio {:>2}{}{}",
+                prio,
+                if en { "  enabled" } else { "" },
+                if pend { "  pending" } else { "" }
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parse a pin name like `pc1` into a port index (PB = 1) and pin number
+fn parse_pin(s: &str) -> Result<(usize, usize), &'static str> {
+    let s = s.as_bytes();
+    if s.len() < 3 || !s[0].eq_ignore_ascii_case(&b'p') {
+        return Err("pin must look like pc1");
+    }
+    let port = match s[1].to_ascii_lowercase() {
+        p @ b'b'..=b'g' => (p - b'a') as usize,
+        _ => return Err("port must be b..g"),
+    };
+    let pin = core::str::from_utf8(&s[2..])
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| n < 32)
+        .ok_or("invalid pin number")?;
+    Ok((port, pin))
+}
+
+fn cmd_gpio(out: &mut dyn fmt::Write, args: &[&str]) -> Result<(), &'static str> {
+    let (port, pin) = parse_pin(args.get(1).ok_or("missing pin")?)?;
+    let base = gpio::base() + port * PORT_STRIDE;
+    let cfg = (base + (pin / 8) * 4) as *mut u32;
+    let shift = (pin % 8) * 4;
+    let dat = (base + DAT) as *mut u32;
+
+    // Poking at a pin a driver has claimed would pull it out from under it.
+    let port_char = (b'A' + port as u8) as char;
+    if args.len() > 2 {
+        let function = unsafe { read_volatile(cfg) >> shift } as u8 & 0xF;
+        pinmux::claim(port_char, pin as u8, function, "monitor").map_err(|error| match error {
+            pinmux::Error::NoSuchPin { .. } => "no such pin",
+            pinmux::Error::Conflict(_) => "pin is claimed by a driver, see pins",
+        })?;
+    }
+
+    unsafe {
+        let set_function = |f: u32| {
+            let v = read_volatile(cfg);
+            write_volatile(cfg, (v & !(0xF << shift)) | (f << shift));
+        };
+        let set_level = |high: bool| {
+            let v = read_volatile(dat);
+            write_volatile(dat, if high { v | 1 << pin } else { v & !(1 << pin) });
+        };
+        match args.get(2).copied() {
+            None => {}
+            Some("in") => set_function(0),
+            Some("0") => {
+                set_level(false);
+                set_function(1);
+            }
+            Some("1") => {
+                set_level(true);
+                set_function(1);
+            }
+            Some("t") => {
+                set_level(read_volatile(dat) & (1 << pin) == 0);
+                set_function(1);
+            }
+            Some(_) => return Err("action must be 0, 1, t or in"),
+        }
+
+        let function = (read_volatile(cfg) >> shift) & 0xF;
+        let level = (read_volatile(dat) >> pin) & 1;
+        if args.len() > 2 {
+            let _ = pinmux::claim(port_char, pin as u8, function as u8, "monitor");
+        }
+        let _ = writeln!(
+            out,
+            "p{}{}: function {}, level {}",
+            (b'a' + port as u8) as char,
+            pin,
+            function,
+            level
+        );
+    }
+    Ok(())
+}
+
+fn cmd_reset(_out: &mut dyn fmt::Write, _args: &[&str]) -> Result<(), &'static str> {
+    crate::panic::reset()
+}
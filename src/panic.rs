@@ -0,0 +1,154 @@
+//! Diagnostic panic handler
+//!
+//! Prints the panic message and location, the trap CSRs and (with the
+//! `panic-backtrace` feature) a frame-pointer backtrace to UART0, then does
+//! one of the following, depending on the enabled feature:
+//!
+//! * `panic-halt` (default): disable interrupts and spin in `wfi`
+//! * `panic-reset`: reset the SoC through the watchdog
+//! * `panic-monitor`: start the [monitor](crate::monitor) on UART0
+//!
+//! UART0 is taken with [`Uart::steal`](crate::uart::Uart::steal) rather
+//! than through whatever the application uses for printing, so this works
+//! even if the panic happened while that was borrowed. If it hasn't been
+//! set up yet, its clock gate is opened and it is configured for
+//! [`DEBUG_BAUDRATE`](crate::board::DEBUG_BAUDRATE), which still relies on
+//! the pins having been muxed.
+
+use core::ptr::write_volatile;
+
+use d1_pac::{TIMER, UART0};
+
+use crate::board::DEBUG_BAUDRATE;
+use crate::ccu::Enable;
+use crate::uart::Uart;
+
+/// Watchdog soft reset register, in the TIMER block
+const WDOG_SOFT_RST: usize = 0x0A8;
+const WDOG_KEY: u32 = 0x16AA << 16;
+
+#[cfg(all(feature = "panic-reset", feature = "panic-monitor"))]
+compile_error!("features `panic-reset` and `panic-monitor` are mutually exclusive");
+
+// Host builds (e.g. `cargo test`) get their panic handler from std.
+#[cfg(all(target_arch = "riscv64", not(test)))]
+mod handler {
+    use core::{
+        arch::asm,
+        fmt::Write,
+        panic::PanicInfo,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use d1_pac::UART0;
+
+    use super::{halt, report_uart};
+    use crate::uart::Uart;
+
+    static PANICKING: AtomicBool = AtomicBool::new(false);
+
+    #[panic_handler]
+    fn panic(info: &PanicInfo) -> ! {
+        let ra: usize;
+        unsafe { asm!("mv {}, ra", out(reg) ra) };
+
+        unsafe { riscv::interrupt::disable() };
+
+        // A panic while printing the last panic: don't try again.
+        if PANICKING.swap(true, Ordering::SeqCst) {
+            halt();
+        }
+
+        let mut uart = report_uart();
+        let _ = write!(uart, "\r\n*** PANIC: {}\r\n", info);
+        let _ = write!(
+            uart,
+            "    mepc: {:#018x}  mcause: {:#018x}  ra: {:#018x}\r\n",
+            riscv::register::mepc::read(),
+            riscv::register::mcause::read().bits(),
+            ra,
+        );
+
+        #[cfg(feature = "panic-backtrace")]
+        backtrace(&mut uart);
+
+        uart.flush_blocking();
+        finish(uart)
+    }
+
+    /// Walk the frame-pointer chain
+    ///
+    /// This needs the whole program built with `-C force-frame-pointers=yes`,
+    /// as set in `.cargo/config.toml`.
+    #[cfg(feature = "panic-backtrace")]
+    fn backtrace(uart: &mut Uart<UART0>) {
+        // Start and end of DRAM, used to sanity-check frame pointers
+        const RAM_START: usize = 0x4000_0000;
+        const RAM_END: usize = 0x6000_0000;
+        const MAX_FRAMES: usize = 32;
+
+        let mut fp: usize;
+        unsafe { asm!("mv {}, s0", out(reg) fp) };
+
+        let _ = uart.write_str("    backtrace:\r\n");
+        for frame in 0..MAX_FRAMES {
+            // The return address and caller's frame pointer are saved just
+            // below the frame pointer.
+            if fp % 8 != 0 || fp < RAM_START + 16 || fp >= RAM_END {
+                break;
+            }
+            let (ra, prev_fp) =
+                unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+            if ra == 0 {
+                break;
+            }
+            let _ = write!(uart, "      #{:<2} {:#018x}\r\n", frame, ra);
+            // The stack grows down, so callers' frames are at higher addresses.
+            if prev_fp <= fp {
+                break;
+            }
+            fp = prev_fp;
+        }
+    }
+
+    #[cfg(feature = "panic-reset")]
+    fn finish(_uart: Uart<UART0>) -> ! {
+        super::reset()
+    }
+
+    #[cfg(feature = "panic-monitor")]
+    fn finish(uart: Uart<UART0>) -> ! {
+        crate::monitor::Monitor::new(uart).run()
+    }
+
+    #[cfg(not(any(feature = "panic-reset", feature = "panic-monitor")))]
+    fn finish(_uart: Uart<UART0>) -> ! {
+        halt()
+    }
+}
+
+/// UART0 for printing a report from a panic or trap, set up at
+/// [`DEBUG_BAUDRATE`] if nothing has opened its clock gate yet
+pub(crate) fn report_uart() -> Uart<UART0> {
+    if UART0::is_enabled() {
+        unsafe { Uart::steal() }
+    } else {
+        Uart::new(
+            unsafe { d1_pac::Peripherals::steal().UART0 },
+            DEBUG_BAUDRATE,
+        )
+    }
+}
+
+fn halt() -> ! {
+    loop {
+        unsafe { riscv::asm::wfi() };
+    }
+}
+
+/// Reset the SoC through the watchdog
+pub fn reset() -> ! {
+    let reg = (TIMER::PTR as usize + WDOG_SOFT_RST) as *mut u32;
+    unsafe { write_volatile(reg, WDOG_KEY | 1) };
+    halt()
+}
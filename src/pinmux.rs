@@ -0,0 +1,287 @@
+//! Pin-mux claim registry
+//!
+//! Typed [`Pin`](crate::gpio::Pin)s stop two drivers owning the same pin at
+//! compile time, but not `Pin::steal`, raw register writes or code that
+//! muxes pins it was never handed. Drivers [`claim`] the pins they mux,
+//! naming themselves, and a second claim on a pin by another owner fails
+//! with a [`Conflict`] instead of silently re-muxing it.
+//!
+//! Changing a pin's mode through [`gpio`](crate::gpio) holds it for
+//! [`GPIO`], so the map never shows an unclaimed pin that was muxed through
+//! a typed pin, and is refused while another owner has claimed the pin by
+//! name for a different function. A driver can take over a pin held for
+//! [`GPIO`] by claiming it for the function it is already muxed to.
+//!
+//! ```ignore
+//! let led = pins.pc1.into_output();
+//! led.claim("status-led")?;
+//! ```
+//!
+//! [`write_map`] (or the monitor's `pins` command, see
+//! [`register_commands`]) lists every pin's actual function next to its
+//! claim, marking pins whose function doesn't match what was claimed.
+
+use core::{cell::RefCell, fmt, ptr::read_volatile};
+
+use riscv::interrupt::Mutex;
+
+use crate::{
+    gpio::{self, PORT_STRIDE},
+    monitor::{self, Command},
+};
+
+/// Number of ports, counting the non-existent PA
+const PORTS: usize = 7;
+
+/// Pins on each port from PB, as listed in the user manual
+const PORT_PINS: [(char, u8); PORTS - 1] = [
+    ('B', 13),
+    ('C', 8),
+    ('D', 23),
+    ('E', 18),
+    ('F', 7),
+    ('G', 19),
+];
+
+/// Function number of a pin in input mode, as in the CFG registers
+pub const INPUT: u8 = 0x0;
+/// Function number of a pin in output mode
+pub const OUTPUT: u8 = 0x1;
+/// Function number of a pin in external interrupt mode
+pub const EINT: u8 = 0xE;
+/// Function number of a disabled pin
+pub const DISABLED: u8 = 0xF;
+
+/// Owner of pins muxed through [`gpio`](crate::gpio) but not yet claimed by
+/// name
+pub const GPIO: &str = "gpio";
+
+/// A driver's claim on a pin
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Claim {
+    pub owner: &'static str,
+    /// What the CFG register is set to: [`INPUT`], [`OUTPUT`], a
+    /// peripheral function 2 to 8, or [`EINT`]
+    pub function: u8,
+}
+
+/// Why a claim or mode change was refused
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The port has no such pin
+    NoSuchPin { port: char, pin: u8 },
+    /// Another owner has the pin
+    Conflict(Conflict),
+}
+
+impl From<Conflict> for Error {
+    fn from(conflict: Conflict) -> Self {
+        Error::Conflict(conflict)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoSuchPin { port, pin } => write!(f, "there is no P{}{}", port, pin),
+            Error::Conflict(conflict) => conflict.fmt(f),
+        }
+    }
+}
+
+/// A pin already claimed by another owner
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub port: char,
+    pub pin: u8,
+    /// The claim already on the pin
+    pub existing: Claim,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "P{}{} is already {} for {}",
+            self.port,
+            self.pin,
+            FunctionName(self.existing.function),
+            self.existing.owner
+        )
+    }
+}
+
+static CLAIMS: Mutex<RefCell<[[Option<Claim>; 32]; PORTS]>> =
+    Mutex::new(RefCell::new([[None; 32]; PORTS]));
+
+/// Record that `owner` muxes pin `pin` of `port` (`'B'` to `'G'`) to
+/// `function`
+///
+/// An owner may claim its own pins again, e.g. to change their function,
+/// and pins held by [`GPIO`] for the same function.
+pub fn claim(port: char, pin: u8, function: u8, owner: &'static str) -> Result<(), Error> {
+    let (index, pin_index) = slot_index(port, pin)?;
+    riscv::interrupt::free(|cs| {
+        let mut claims = CLAIMS.borrow(cs).borrow_mut();
+        let slot = &mut claims[index][pin_index];
+        match *slot {
+            Some(existing)
+                if existing.owner != owner
+                    && (existing.owner != GPIO || existing.function != function) =>
+            {
+                Err(Conflict {
+                    port,
+                    pin,
+                    existing,
+                }
+                .into())
+            }
+            _ => {
+                *slot = Some(Claim { owner, function });
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Check and record a mode change made through [`gpio`](crate::gpio)
+///
+/// The pin is held for [`GPIO`], or let go when disabled. Changing the
+/// function of a pin claimed by name is refused.
+pub(crate) fn remux(port: char, pin: u8, function: u8) -> Result<(), Error> {
+    let (index, pin_index) = slot_index(port, pin)?;
+    riscv::interrupt::free(|cs| {
+        let mut claims = CLAIMS.borrow(cs).borrow_mut();
+        let slot = &mut claims[index][pin_index];
+        match *slot {
+            Some(existing) if existing.owner != GPIO => {
+                if existing.function == function {
+                    Ok(())
+                } else {
+                    Err(Conflict {
+                        port,
+                        pin,
+                        existing,
+                    }
+                    .into())
+                }
+            }
+            _ => {
+                *slot = (function != DISABLED).then_some(Claim {
+                    owner: GPIO,
+                    function,
+                });
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Drop `owner`'s claim on a pin; claims by anyone else are kept
+pub fn release(port: char, pin: u8, owner: &'static str) {
+    let Ok((index, pin_index)) = slot_index(port, pin) else {
+        return;
+    };
+    riscv::interrupt::free(|cs| {
+        let mut claims = CLAIMS.borrow(cs).borrow_mut();
+        let slot = &mut claims[index][pin_index];
+        if slot.is_some_and(|claim| claim.owner == owner) {
+            *slot = None;
+        }
+    });
+}
+
+/// The claim on a pin, if any
+pub fn claimed(port: char, pin: u8) -> Option<Claim> {
+    let (index, pin_index) = slot_index(port, pin).ok()?;
+    riscv::interrupt::free(|cs| CLAIMS.borrow(cs).borrow()[index][pin_index])
+}
+
+/// Write a port-by-port map of the pins' functions and claims
+///
+/// Unclaimed pins are only listed if they aren't disabled. A `!` marks
+/// pins whose function differs from the claimed one.
+pub fn write_map(out: &mut dyn fmt::Write) -> fmt::Result {
+    for (port, pins) in PORT_PINS {
+        writeln!(out, "port {}:", port)?;
+        for pin in 0..pins {
+            let function = current_function(port, pin);
+            match claimed(port, pin) {
+                Some(claim) => writeln!(
+                    out,
+                    "  P{}{:<3} {:<7} {}{}",
+                    port,
+                    pin,
+                    FunctionName(function),
+                    claim.owner,
+                    if claim.function != function { " !" } else { "" }
+                )?,
+                None if function != DISABLED => {
+                    writeln!(out, "  P{}{:<3} {:<7} -", port, pin, FunctionName(function))?
+                }
+                None => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print the pin map on the console
+pub fn print_map() {
+    let _ = crate::console::with(|uart| write_map(uart));
+}
+
+/// Add the `pins` command to the monitor
+pub fn register_commands() -> Result<(), monitor::TableFull> {
+    monitor::register(&PINS)
+}
+
+pub static PINS: Command = Command {
+    name: "pins",
+    usage: "",
+    help: "show each pin's function and which driver claimed it",
+    run: cmd_pins,
+};
+
+fn cmd_pins(out: &mut dyn fmt::Write, _args: &[&str]) -> Result<(), &'static str> {
+    write_map(out).map_err(|_| "write failed")
+}
+
+/// The function a pin is actually muxed to, read from its CFG register
+fn current_function(port: char, pin: u8) -> u8 {
+    let base = gpio::base() + port_index(port) * PORT_STRIDE;
+    let cfg = (base + (pin as usize / 8) * 4) as *const u32;
+    let shift = (pin % 8) * 4;
+    (unsafe { read_volatile(cfg) } >> shift) as u8 & 0xF
+}
+
+/// Where in `CLAIMS` a pin goes, if the port has it
+fn slot_index(port: char, pin: u8) -> Result<(usize, usize), Error> {
+    match PORT_PINS.iter().find(|&&(p, _)| p == port) {
+        Some(&(_, pins)) if pin < pins => Ok((port_index(port), pin as usize)),
+        _ => Err(Error::NoSuchPin { port, pin }),
+    }
+}
+
+fn port_index(port: char) -> usize {
+    match port {
+        'B'..='G' => (port as u8 - b'A') as usize,
+        _ => panic!("no such GPIO port"),
+    }
+}
+
+/// Formats a function number the way the pin map shows it
+struct FunctionName(u8);
+
+impl fmt::Display for FunctionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            INPUT => f.pad("input"),
+            OUTPUT => f.pad("output"),
+            EINT => f.pad("eint"),
+            DISABLED => f.pad("off"),
+            n @ 2..=9 => f.pad(core::str::from_utf8(&[b'f', b'0' + n]).unwrap_or("?")),
+            _ => f.pad("reserved"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use d1_pac::{plic, Interrupt, PLIC};
+use log::{debug, trace};
 
 /// Interrupt Priority from 0..31
 pub type Priority = plic::prio::PRIORITY_A;
@@ -36,6 +37,7 @@ impl Plic {
     ///
     /// May effect normal interrupt processing
     pub unsafe fn unmask(&self, interrupt: Interrupt) {
+        debug!("unmask {:?}", interrupt);
         let nr = interrupt.into_bits() as usize;
         let (reg_offset, irq_en) = (nr / 32, 1 << (nr % 32));
         self.plic.mie[reg_offset].modify(|r, w| w.bits(r.bits() | irq_en));
@@ -43,6 +45,7 @@ impl Plic {
 
     /// Disable an interrupt
     pub fn mask(&self, interrupt: Interrupt) {
+        debug!("mask {:?}", interrupt);
         let nr = interrupt.into_bits() as usize;
         let (reg_offset, irq_en) = (nr / 32, 1 << (nr % 32));
         self.plic.mie[reg_offset].modify(|r, w| unsafe { w.bits(r.bits() & !irq_en) });
@@ -54,6 +57,7 @@ impl Plic {
     ///
     /// May effect normal interrupt processing
     pub unsafe fn set_priority(&self, interrupt: Interrupt, priority: Priority) {
+        debug!("set priority of {:?} to {:?}", interrupt, priority);
         let nr = interrupt.into_bits() as usize;
         self.plic.prio[nr].write(|w| w.bits(priority.into_bits()));
     }
@@ -61,7 +65,10 @@ impl Plic {
     pub fn claim(&self) -> Interrupt {
         let claim = self.plic.mclaim.read().mclaim().bits() as u8;
         match Interrupt::try_from(claim) {
-            Ok(interrupt) => interrupt,
+            Ok(interrupt) => {
+                trace!("claim {:?}", interrupt);
+                interrupt
+            }
             Err(_) => {
                 panic!("error claiming interrupt");
             }
@@ -69,6 +76,7 @@ impl Plic {
     }
 
     pub fn complete(&self, interrupt: Interrupt) {
+        trace!("complete {:?}", interrupt);
         self.plic
             .mclaim
             .write(|w| w.mclaim().variant(interrupt.into_bits() as u16));
@@ -0,0 +1,631 @@
+//! PWM controller
+//!
+//! [`Pwm::new`] splits the PWM block into its eight [`Channel`]s. Channels
+//! `2n` and `2n + 1` share a clock source and power-of-two divider, so
+//! changing the frequency of one channel while its partner is running is
+//! limited to the divider the partner already uses.
+//!
+//! ```ignore
+//! let Pwm { mut ch0, .. } = Pwm::new(p.PWM);
+//! ch0.set_frequency(25_000)?;
+//! ch0.set_duty_cycle_percent(50)?;
+//! ch0.enable();
+//! ```
+//!
+//! Besides continuous output, a channel can send a fixed number of pulses
+//! ([`Channel::start_pulses`]), measure an external signal
+//! ([`Channel::into_capture`]), or drive a complementary output with dead
+//! time together with its partner ([`Channel::into_complementary`]).
+
+use core::{
+    convert::Infallible,
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use d1_pac::PWM;
+
+use crate::ccu::{self, Clock, Enable, Reset};
+
+const PWM_CISR: usize = 0x0014;
+const PWM_PCCR: usize = 0x0020;
+const PWM_PCGR: usize = 0x0040;
+const PWM_PDZCR: usize = 0x0060;
+const PWM_PER: usize = 0x0080;
+const PWM_CER: usize = 0x00C0;
+
+const PWM_CHAN_BASE: usize = 0x0100;
+const PWM_CHAN_STRIDE: usize = 0x20;
+const PWM_PCR: usize = 0x00;
+const PWM_PPR: usize = 0x04;
+const PWM_PPCNTR: usize = 0x0C;
+const PWM_CCR: usize = 0x10;
+const PWM_CRLR: usize = 0x14;
+const PWM_CFLR: usize = 0x18;
+
+const PCCR_SRC_SHIFT: u32 = 7;
+const PCCR_SRC: u32 = 0b11 << PCCR_SRC_SHIFT;
+const PCCR_DIV_M: u32 = 0xF;
+const MAX_DIV_M: u32 = 8;
+
+const PCR_PRESCAL_K: u32 = 0xFF;
+const PCR_ACT_STA: u32 = 1 << 8;
+const PCR_MODE_PULSE: u32 = 1 << 9;
+const PCR_PUL_START: u32 = 1 << 10;
+
+const PDZCR_EN: u32 = 1 << 0;
+const PDZCR_INTV_SHIFT: u32 = 8;
+const MAX_DEAD_TICKS: u64 = 0xFF;
+
+const CCR_CRLF: u32 = 1 << 2;
+const CCR_CFLF: u32 = 1 << 1;
+
+/// Longest period in ticks of the channel clock; one less than the
+/// hardware allows, so that 100% duty fits in the `u16` duty cycle
+const MAX_PERIOD: u64 = 0xFFFF;
+
+/// Frequency last set on each channel, or 0, kept across APB0 changes
+static TARGET_HZ: [AtomicU32; 8] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+/// Dead time asked for on each complementary pair, kept across divider
+/// changes
+static DEAD_TIME_NS: [AtomicU32; 4] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+static SUBSCRIBED: AtomicBool = AtomicBool::new(false);
+
+/// Clock feeding a channel pair
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The 24 MHz oscillator
+    Hosc,
+    /// The APB0 bus clock, at whatever frequency the CCU has it
+    Apb0,
+}
+
+/// Level of the output during the active part of each period
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The frequency can't be reached from the channel's clock source
+    FrequencyOutOfRange,
+    /// The frequency needs a different divider from the one the running
+    /// partner channel uses
+    PairClockConflict,
+    /// The dead time is longer than the pair's clock can count
+    DeadTimeOutOfRange,
+}
+
+impl embedded_hal::pwm::Error for Error {
+    fn kind(&self) -> embedded_hal::pwm::ErrorKind {
+        embedded_hal::pwm::ErrorKind::Other
+    }
+}
+
+/// Every PWM channel
+pub struct Pwm {
+    pub ch0: Channel<0>,
+    pub ch1: Channel<1>,
+    pub ch2: Channel<2>,
+    pub ch3: Channel<3>,
+    pub ch4: Channel<4>,
+    pub ch5: Channel<5>,
+    pub ch6: Channel<6>,
+    pub ch7: Channel<7>,
+}
+
+impl Pwm {
+    /// Enable the PWM bus clock and split the block into its channels,
+    /// all stopped, active high and clocked from the 24 MHz oscillator
+    pub fn new(_pwm: PWM) -> Self {
+        PWM::enable();
+        PWM::deassert_reset();
+        write_reg(PWM_PER, 0);
+        write_reg(PWM_CER, 0);
+        for pair in 0..4 {
+            write_reg(PWM_PCCR + pair * 4, 0);
+            write_reg(PWM_PDZCR + pair * 4, 0);
+        }
+        for (n, target) in (0..8).zip(&TARGET_HZ) {
+            write_chan(n, PWM_PCR, PCR_ACT_STA);
+            write_chan(n, PWM_PPR, 0);
+            target.store(0, Ordering::Relaxed);
+        }
+        // If the table is full, callers changing APB0 have to set the
+        // frequency of channels clocked from it again themselves.
+        if !SUBSCRIBED.swap(true, Ordering::Relaxed) {
+            let _ = ccu::subscribe(Clock::Apb0, apb0_changed);
+        }
+        Self {
+            ch0: Channel { _x: () },
+            ch1: Channel { _x: () },
+            ch2: Channel { _x: () },
+            ch3: Channel { _x: () },
+            ch4: Channel { _x: () },
+            ch5: Channel { _x: () },
+            ch6: Channel { _x: () },
+            ch7: Channel { _x: () },
+        }
+    }
+}
+
+/// Fails to compile for channels that don't form a pair
+struct CheckPair<const N: u8, const M: u8>;
+
+impl<const N: u8, const M: u8> CheckPair<N, M> {
+    const OK: () = assert!(
+        N.is_multiple_of(2) && M == N + 1,
+        "pairs are channels 2n and 2n + 1"
+    );
+}
+
+/// One PWM output
+pub struct Channel<const N: u8> {
+    _x: (),
+}
+
+impl<const N: u8> Channel<N> {
+    const PAIR: usize = N as usize / 2;
+    const PARTNER: u8 = N ^ 1;
+
+    /// Select the clock feeding this channel and its partner
+    ///
+    /// The frequency of both needs setting again afterwards. Channels on
+    /// APB0 keep their frequency when it is changed through
+    /// [`ccu::set_bus`].
+    pub fn set_clock_source(&mut self, source: ClockSource) {
+        let sel = match source {
+            ClockSource::Hosc => 0,
+            ClockSource::Apb0 => 1,
+        };
+        modify_reg(PWM_PCCR + Self::PAIR * 4, |r| {
+            (r & !PCCR_SRC) | (sel << PCCR_SRC_SHIFT)
+        });
+    }
+
+    /// Set the output frequency, keeping the duty cycle as a fraction of
+    /// the period, and return the frequency actually reached
+    ///
+    /// For the primary of a [`Complementary`] pair the divider is free to
+    /// change, and the dead time is kept.
+    pub fn set_frequency(&mut self, hz: u32) -> Result<u32, Error> {
+        if hz == 0 {
+            return Err(Error::FrequencyOutOfRange);
+        }
+        let source = source_hz(Self::PAIR);
+        let total = source as u64 / hz as u64;
+        let current_m = read_reg(PWM_PCCR + Self::PAIR * 4) & PCCR_DIV_M;
+        let complementary = read_reg(PWM_PDZCR + Self::PAIR * 4) & PDZCR_EN != 0;
+
+        let (m, k, period) = if self.partner_busy() && !complementary {
+            dividers(total, current_m..=current_m).ok_or(Error::PairClockConflict)?
+        } else {
+            dividers(total, 0..=MAX_DIV_M).ok_or(Error::FrequencyOutOfRange)?
+        };
+        let dead_ticks = if complementary {
+            let ns = DEAD_TIME_NS[Self::PAIR].load(Ordering::Relaxed);
+            Some(dead_ticks(source, m, ns).ok_or(Error::DeadTimeOutOfRange)?)
+        } else {
+            None
+        };
+
+        let old_period = self.period();
+        let duty = (self.active_cycles() as u64 * period / old_period).min(period);
+        self.set_dividers(m, k);
+        write_chan(N, PWM_PPR, ((period as u32 - 1) << 16) | duty as u32);
+        if let Some(ticks) = dead_ticks {
+            write_dead_ticks(Self::PAIR, ticks);
+        }
+        TARGET_HZ[N as usize].store(hz, Ordering::Relaxed);
+
+        Ok((source as u64 / ((1 << m) * (k as u64 + 1) * period)) as u32)
+    }
+
+    /// Current output frequency in Hz
+    pub fn frequency(&self) -> u32 {
+        (self.tick_hz() / self.period()) as u32
+    }
+
+    pub fn set_polarity(&mut self, polarity: Polarity) {
+        let active_high = match polarity {
+            Polarity::ActiveHigh => PCR_ACT_STA,
+            Polarity::ActiveLow => 0,
+        };
+        modify_chan(N, PWM_PCR, |r| (r & !PCR_ACT_STA) | active_high);
+    }
+
+    /// Start continuous output
+    pub fn enable(&mut self) {
+        modify_chan(N, PWM_PCR, |r| r & !PCR_MODE_PULSE);
+        modify_reg(PWM_PER, |r| r | (1 << N));
+    }
+
+    pub fn disable(&mut self) {
+        modify_reg(PWM_PER, |r| r & !(1 << N));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        read_reg(PWM_PER) & (1 << N) != 0
+    }
+
+    /// Send `count` periods of the current waveform, then stop
+    ///
+    /// Poll [`Channel::is_pulsing`] to find out when they have gone out.
+    pub fn start_pulses(&mut self, count: u16) {
+        if count == 0 {
+            return;
+        }
+        write_chan(N, PWM_PPCNTR, count as u32 - 1);
+        modify_chan(N, PWM_PCR, |r| r | PCR_MODE_PULSE);
+        modify_reg(PWM_PER, |r| r | (1 << N));
+        modify_chan(N, PWM_PCR, |r| r | PCR_PUL_START);
+    }
+
+    /// Whether pulses started by [`Channel::start_pulses`] are still going
+    pub fn is_pulsing(&self) -> bool {
+        read_chan(N, PWM_PCR) & PCR_PUL_START != 0
+    }
+
+    /// Measure the input on this channel's pin instead, counting at close
+    /// to `resolution_hz`
+    ///
+    /// Shares the pair's divider like [`Channel::set_frequency`] does. On
+    /// error the channel is handed back untouched.
+    pub fn into_capture(mut self, resolution_hz: u32) -> Result<Capture<N>, (Error, Self)> {
+        if resolution_hz == 0 {
+            return Err((Error::FrequencyOutOfRange, self));
+        }
+        let source = source_hz(Self::PAIR);
+        let div = (source / resolution_hz).max(1) as u64;
+        let m = if self.partner_busy() {
+            let m = read_reg(PWM_PCCR + Self::PAIR * 4) & PCCR_DIV_M;
+            if div >> m > 256 {
+                return Err((Error::PairClockConflict, self));
+            }
+            m
+        } else {
+            match (0..=MAX_DIV_M).find(|&m| div >> m <= 256) {
+                Some(m) => m,
+                None => return Err((Error::FrequencyOutOfRange, self)),
+            }
+        };
+        self.disable();
+        self.set_dividers(m, (div >> m).max(1) as u32 - 1);
+        modify_chan(N, PWM_CCR, |r| r | CCR_CRLF | CCR_CFLF);
+        write_reg(PWM_CISR, 0b11 << (2 * N));
+        modify_reg(PWM_CER, |r| r | (1 << N));
+        TARGET_HZ[N as usize].store(0, Ordering::Relaxed);
+        Ok(Capture { channel: self })
+    }
+
+    /// Drive this channel's partner with the complement of its output,
+    /// both edges delayed by `dead_time_ns`
+    ///
+    /// The pair is configured through this channel's frequency and duty.
+    /// On error both channels are handed back.
+    pub fn into_complementary<const M: u8>(
+        self,
+        partner: Channel<M>,
+        dead_time_ns: u32,
+    ) -> Result<Complementary<N, M>, (Error, Self, Channel<M>)> {
+        #[allow(clippy::let_unit_value)]
+        let () = CheckPair::<N, M>::OK;
+        let mut pair = Complementary {
+            primary: self,
+            partner,
+        };
+        match pair.set_dead_time(dead_time_ns) {
+            Ok(_) => {
+                // The partner follows this channel, so APB0 changes mustn't
+                // retune it on its own.
+                TARGET_HZ[M as usize].store(0, Ordering::Relaxed);
+                Ok(pair)
+            }
+            Err(e) => {
+                let (primary, partner) = pair.free();
+                Err((e, primary, partner))
+            }
+        }
+    }
+
+    /// Whether the partner channel is running, and so needs its divider kept
+    fn partner_busy(&self) -> bool {
+        (read_reg(PWM_PER) | read_reg(PWM_CER)) & (1 << Self::PARTNER) != 0
+    }
+
+    /// Set the pair's divider to `2^m` and this channel's prescaler to
+    /// `k + 1`, and ungate the channel clock
+    fn set_dividers(&mut self, m: u32, k: u32) {
+        modify_reg(PWM_PCCR + Self::PAIR * 4, |r| (r & !PCCR_DIV_M) | m);
+        modify_reg(PWM_PCGR, |r| (r | (1 << N)) & !(1 << (N + 16)));
+        modify_chan(N, PWM_PCR, |r| (r & !PCR_PRESCAL_K) | k);
+    }
+
+    /// Ticks of the channel clock, after the divider and prescaler, per second
+    fn tick_hz(&self) -> u64 {
+        let source = source_hz(Self::PAIR) as u64;
+        let m = read_reg(PWM_PCCR + Self::PAIR * 4) & PCCR_DIV_M;
+        let k = read_chan(N, PWM_PCR) & PCR_PRESCAL_K;
+        source / (1 << m) / (k as u64 + 1)
+    }
+
+    /// Ticks per period
+    fn period(&self) -> u64 {
+        (read_chan(N, PWM_PPR) >> 16) as u64 + 1
+    }
+
+    fn active_cycles(&self) -> u16 {
+        read_chan(N, PWM_PPR) as u16
+    }
+}
+
+impl<const N: u8> embedded_hal::pwm::ErrorType for Channel<N> {
+    type Error = Infallible;
+}
+
+impl<const N: u8> embedded_hal::pwm::SetDutyCycle for Channel<N> {
+    fn max_duty_cycle(&self) -> u16 {
+        self.period() as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+        let duty = duty.min(self.period() as u16);
+        modify_chan(N, PWM_PPR, |r| (r & 0xFFFF_0000) | duty as u32);
+        Ok(())
+    }
+}
+
+/// A channel measuring the period and duty of an external signal
+pub struct Capture<const N: u8> {
+    channel: Channel<N>,
+}
+
+/// Result of a [`Capture`]: the lengths of one high and one low phase
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Measurement {
+    pub high_ticks: u16,
+    pub low_ticks: u16,
+    /// Rate the ticks were counted at
+    pub tick_hz: u32,
+}
+
+impl Measurement {
+    pub fn period_ticks(&self) -> u32 {
+        self.high_ticks as u32 + self.low_ticks as u32
+    }
+
+    /// Frequency of the signal in Hz, or 0 if it was too slow to measure
+    pub fn frequency(&self) -> u32 {
+        self.tick_hz.checked_div(self.period_ticks()).unwrap_or(0)
+    }
+
+    /// Fraction of the period the signal was high, in percent
+    pub fn duty_percent(&self) -> u8 {
+        (self.high_ticks as u32 * 100)
+            .checked_div(self.period_ticks())
+            .unwrap_or(0) as u8
+    }
+}
+
+impl<const N: u8> Capture<N> {
+    /// The latest full measurement, if both edges have been seen since
+    /// the previous one
+    ///
+    /// The counter restarts on every edge, so the value locked on a rising
+    /// edge is the length of the low phase, and the other way round.
+    pub fn measure(&mut self) -> Option<Measurement> {
+        let ccr = read_chan(N, PWM_CCR);
+        if ccr & (CCR_CRLF | CCR_CFLF) != CCR_CRLF | CCR_CFLF {
+            return None;
+        }
+        let measurement = Measurement {
+            high_ticks: read_chan(N, PWM_CFLR) as u16,
+            low_ticks: read_chan(N, PWM_CRLR) as u16,
+            tick_hz: self.channel.tick_hz() as u32,
+        };
+        write_chan(N, PWM_CCR, ccr | CCR_CRLF | CCR_CFLF);
+        Some(measurement)
+    }
+
+    /// Go back to driving the pin
+    pub fn into_pwm(self) -> Channel<N> {
+        modify_reg(PWM_CER, |r| r & !(1 << N));
+        self.channel
+    }
+}
+
+/// A channel pair driving complementary outputs with dead time
+pub struct Complementary<const N: u8, const M: u8> {
+    primary: Channel<N>,
+    partner: Channel<M>,
+}
+
+impl<const N: u8, const M: u8> Complementary<N, M> {
+    /// Set the delay before either output goes active, returning the dead
+    /// time actually used
+    ///
+    /// It is kept through later frequency changes, which fail if it no
+    /// longer fits.
+    pub fn set_dead_time(&mut self, ns: u32) -> Result<u32, Error> {
+        let pair = N as usize / 2;
+        let source = source_hz(pair);
+        let m = read_reg(PWM_PCCR + pair * 4) & PCCR_DIV_M;
+        let ticks = dead_ticks(source, m, ns).ok_or(Error::DeadTimeOutOfRange)?;
+        DEAD_TIME_NS[pair].store(ns, Ordering::Relaxed);
+        write_dead_ticks(pair, ticks);
+        Ok((ticks * 1_000_000_000 / (source as u64 >> m)) as u32)
+    }
+
+    /// Set the frequency of both outputs; see [`Channel::set_frequency`]
+    pub fn set_frequency(&mut self, hz: u32) -> Result<u32, Error> {
+        self.primary.set_frequency(hz)
+    }
+
+    pub fn enable(&mut self) {
+        self.primary.enable();
+        self.partner.enable();
+    }
+
+    pub fn disable(&mut self) {
+        self.primary.disable();
+        self.partner.disable();
+    }
+
+    /// Turn off the dead-time generator and split the pair up again
+    pub fn free(self) -> (Channel<N>, Channel<M>) {
+        write_reg(PWM_PDZCR + N as usize / 2 * 4, 0);
+        (self.primary, self.partner)
+    }
+}
+
+impl<const N: u8, const M: u8> embedded_hal::pwm::ErrorType for Complementary<N, M> {
+    type Error = Infallible;
+}
+
+impl<const N: u8, const M: u8> embedded_hal::pwm::SetDutyCycle for Complementary<N, M> {
+    fn max_duty_cycle(&self) -> u16 {
+        self.primary.max_duty_cycle()
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+        self.primary.set_duty_cycle(duty)
+    }
+}
+
+/// Dead time generator ticks for `ns` from a pair clock of `source` Hz
+/// divided by `2^m`, if the generator can count that far
+fn dead_ticks(source: u32, m: u32, ns: u32) -> Option<u64> {
+    let clock = source as u64 >> m;
+    let ticks = (ns as u64 * clock).div_ceil(1_000_000_000);
+    (ticks <= MAX_DEAD_TICKS).then_some(ticks)
+}
+
+/// Turn on a pair's dead time generator with `ticks` of delay
+fn write_dead_ticks(pair: usize, ticks: u64) {
+    write_reg(
+        PWM_PDZCR + pair * 4,
+        ((ticks as u32) << PDZCR_INTV_SHIFT) | PDZCR_EN,
+    );
+}
+
+/// Frequency of a channel pair's clock source
+fn source_hz(pair: usize) -> u32 {
+    match (read_reg(PWM_PCCR + pair * 4) & PCCR_SRC) >> PCCR_SRC_SHIFT {
+        1 => ccu::frequency(Clock::Apb0),
+        _ => ccu::HOSC_HZ,
+    }
+}
+
+/// Bring every channel clocked from APB0 back to the frequency it was set to
+fn apb0_changed(_hz: u32) {
+    retune::<0>();
+    retune::<1>();
+    retune::<2>();
+    retune::<3>();
+    retune::<4>();
+    retune::<5>();
+    retune::<6>();
+    retune::<7>();
+}
+
+fn retune<const N: u8>() {
+    let target = TARGET_HZ[N as usize].load(Ordering::Relaxed);
+    let on_apb0 = (read_reg(PWM_PCCR + N as usize / 2 * 4) & PCCR_SRC) >> PCCR_SRC_SHIFT == 1;
+    let capturing = read_reg(PWM_CER) & (1 << N) != 0;
+    if target != 0 && on_apb0 && !capturing {
+        // The channel is owned elsewhere, but nothing else touches its
+        // registers while the CCU is running listeners. If the new APB0
+        // can't reach the old frequency, the channel is left as it was.
+        let _ = Channel::<N> { _x: () }.set_frequency(target);
+    }
+}
+
+/// Find the smallest divider `2^m` in `m_range`, then the smallest
+/// prescaler `k + 1`, that fit `total` source ticks into one period
+fn dividers(total: u64, m_range: core::ops::RangeInclusive<u32>) -> Option<(u32, u32, u64)> {
+    for m in m_range {
+        let ticks = total >> m;
+        let k = ticks.div_ceil(MAX_PERIOD).max(1);
+        if k > 256 {
+            continue;
+        }
+        let period = ticks / k;
+        // Less than two ticks leaves no room for a duty cycle.
+        return (period >= 2).then_some((m, k as u32 - 1, period));
+    }
+    None
+}
+
+fn read_reg(offset: usize) -> u32 {
+    unsafe { read_volatile((PWM::PTR as usize + offset) as *const u32) }
+}
+
+fn write_reg(offset: usize, value: u32) {
+    unsafe { write_volatile((PWM::PTR as usize + offset) as *mut u32, value) }
+}
+
+/// Read-modify-write a register shared between channels
+fn modify_reg(offset: usize, f: impl FnOnce(u32) -> u32) {
+    riscv::interrupt::free(|_| write_reg(offset, f(read_reg(offset))));
+}
+
+fn chan_offset(n: u8, offset: usize) -> usize {
+    PWM_CHAN_BASE + n as usize * PWM_CHAN_STRIDE + offset
+}
+
+fn read_chan(n: u8, offset: usize) -> u32 {
+    read_reg(chan_offset(n, offset))
+}
+
+fn write_chan(n: u8, offset: usize, value: u32) {
+    write_reg(chan_offset(n, offset), value)
+}
+
+fn modify_chan(n: u8, offset: usize, f: impl FnOnce(u32) -> u32) {
+    let offset = chan_offset(n, offset);
+    write_reg(offset, f(read_reg(offset)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dividers_fit_period() {
+        // 25 kHz from the 24 MHz oscillator needs no division.
+        assert_eq!(dividers(960, 0..=MAX_DIV_M), Some((0, 0, 960)));
+        // One period just over the counter's range takes the prescaler.
+        assert_eq!(dividers(MAX_PERIOD + 1, 0..=MAX_DIV_M), Some((0, 1, 32768)));
+        // 1 Hz from 24 MHz needs both: 2^1 then 184.
+        assert_eq!(dividers(24_000_000, 0..=MAX_DIV_M), Some((1, 183, 65217)));
+    }
+
+    #[test]
+    fn dividers_limits() {
+        // No room for a duty cycle.
+        assert_eq!(dividers(1, 0..=MAX_DIV_M), None);
+        // Too slow for a fixed divider, but fine with a free one.
+        assert_eq!(dividers(1 << 26, 0..=0), None);
+        assert!(dividers(1 << 26, 0..=MAX_DIV_M).is_some());
+        // Too slow for anything.
+        assert_eq!(dividers(u64::MAX, 0..=MAX_DIV_M), None);
+    }
+}
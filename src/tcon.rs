@@ -0,0 +1,342 @@
+//! TCON_LCD0 timing controller, for parallel RGB panels
+//!
+//! The TCON takes pixels from the display engine and drives them out on
+//! port D with the panel's sync timings. [`Tcon::new`] clocks it for the
+//! panel's dot clock and programs the timings; the output stays off until
+//! [`Tcon::start`].
+//!
+//! ```ignore
+//! let lcd = board.lcd.unwrap();
+//! let mut tcon = Tcon::new(lcd, VideoPll::Video0, &Timings::LCD_480X272)
+//!     .map_err(|(error, _pins)| error)?;
+//! tcon.start();
+//! ```
+//!
+//! Only 18 data lines are wired, so the panel gets the top six bits of
+//! each channel.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use d1_pac::TCON_LCD0;
+
+use crate::ccu::{self, DpssTop, Enable, Reset, TconLcd0, VideoPll};
+use crate::gpio::{ErasedPin, Function, Pin};
+use crate::pinmux;
+
+const LCD_GCTL: usize = 0x000;
+const LCD_GINT0: usize = 0x004;
+const LCD_GINT1: usize = 0x008;
+const LCD_FRM_CTL: usize = 0x010;
+const LCD_CTL: usize = 0x040;
+const LCD_DCLK: usize = 0x044;
+const LCD_BASIC0: usize = 0x048;
+const LCD_BASIC1: usize = 0x04C;
+const LCD_BASIC2: usize = 0x050;
+const LCD_BASIC3: usize = 0x054;
+const LCD_HV_IF: usize = 0x058;
+const LCD_IO_POL: usize = 0x088;
+const LCD_IO_TRI: usize = 0x08C;
+
+const GCTL_EN: u32 = 1 << 31;
+const CTL_EN: u32 = 1 << 31;
+const CTL_START_DLY_SHIFT: u32 = 4;
+/// All four DCLK outputs enabled
+const DCLK_EN: u32 = 0xF << 28;
+const IO_POL_VSYNC_HIGH: u32 = 1 << 24;
+const IO_POL_HSYNC_HIGH: u32 = 1 << 25;
+/// Data driven on the falling edge of the dot clock, for panels sampling
+/// on the rising one
+const IO_POL_DCLK_NEGEDGE: u32 = 1 << 26;
+const IO_POL_DE_LOW: u32 = 1 << 27;
+/// Every output tristated
+const IO_TRI_ALL: u32 = 0xFFFF_FFFF;
+
+/// Largest active width or height
+const MAX_ACTIVE: u32 = 4096;
+/// Largest horizontal total; twice the vertical total must stay below it
+const MAX_TOTAL: u32 = 8192;
+/// Largest back porch plus sync width
+const MAX_BACK_PORCH: u32 = 4096;
+/// Largest sync pulse width
+const MAX_SYNC: u32 = 1024;
+
+/// Pins the TCON drives on port D, all muxed to function 2
+pub struct LcdPins {
+    pub clk: Pin<'D', 18, Function<2>>,
+    pub de: Pin<'D', 19, Function<2>>,
+    pub hsync: Pin<'D', 20, Function<2>>,
+    pub vsync: Pin<'D', 21, Function<2>>,
+    /// PD0-PD17, carrying R7-R2, G7-G2 and B7-B2
+    pub data: [ErasedPin<Function<2>>; 18],
+}
+
+impl LcdPins {
+    /// Claim every pin in the [`pinmux`](crate::pinmux) registry
+    pub fn claim(&self, owner: &'static str) -> Result<(), pinmux::Error> {
+        self.clk.claim(owner)?;
+        self.de.claim(owner)?;
+        self.hsync.claim(owner)?;
+        self.vsync.claim(owner)?;
+        self.data.iter().try_for_each(|pin| pin.claim(owner))
+    }
+}
+
+/// Active level of a sync or data enable signal
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// Panel timings, in pixels and lines
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timings {
+    pub width: u32,
+    pub height: u32,
+    pub h_front_porch: u32,
+    pub h_sync: u32,
+    pub h_back_porch: u32,
+    pub v_front_porch: u32,
+    pub v_sync: u32,
+    pub v_back_porch: u32,
+    pub hsync: Polarity,
+    pub vsync: Polarity,
+    pub de: Polarity,
+    /// Whether the panel samples data on the falling edge of the dot clock
+    pub sample_on_falling: bool,
+    pub dot_clock_hz: u32,
+}
+
+impl Timings {
+    /// The common 4.3 inch 480x272 panel, as on the Lichee RV Dock
+    pub const LCD_480X272: Timings = Timings {
+        width: 480,
+        height: 272,
+        h_front_porch: 8,
+        h_sync: 4,
+        h_back_porch: 43,
+        v_front_porch: 8,
+        v_sync: 4,
+        v_back_porch: 12,
+        hsync: Polarity::ActiveLow,
+        vsync: Polarity::ActiveLow,
+        de: Polarity::ActiveHigh,
+        sample_on_falling: false,
+        dot_clock_hz: 9_000_000,
+    };
+
+    pub const fn h_total(&self) -> u32 {
+        self.width + self.h_front_porch + self.h_sync + self.h_back_porch
+    }
+
+    pub const fn v_total(&self) -> u32 {
+        self.height + self.v_front_porch + self.v_sync + self.v_back_porch
+    }
+
+    /// Check the timings fit the TCON's registers
+    pub fn validate(&self) -> Result<(), Error> {
+        let active = 1..=MAX_ACTIVE;
+        let sync = 1..=MAX_SYNC;
+        let back_porch = 1..=MAX_BACK_PORCH;
+        // Out-of-range parts could overflow the sums, so check those too.
+        let sum = |parts: &[u32]| parts.iter().try_fold(0u32, |a, &b| a.checked_add(b));
+        let h_bp = sum(&[self.h_sync, self.h_back_porch]);
+        let v_bp = sum(&[self.v_sync, self.v_back_porch]);
+        let h_total = sum(&[
+            self.width,
+            self.h_front_porch,
+            self.h_sync,
+            self.h_back_porch,
+        ]);
+        let v_total = sum(&[
+            self.height,
+            self.v_front_porch,
+            self.v_sync,
+            self.v_back_porch,
+        ]);
+        // The horizontal total is programmed less one, the vertical one
+        // doubled, in the same width of field.
+        let fits = active.contains(&self.width)
+            && active.contains(&self.height)
+            && sync.contains(&self.h_sync)
+            && sync.contains(&self.v_sync)
+            && h_bp.is_some_and(|bp| back_porch.contains(&bp))
+            && v_bp.is_some_and(|bp| back_porch.contains(&bp))
+            && h_total.is_some_and(|total| total <= MAX_TOTAL)
+            && v_total
+                .and_then(|total| total.checked_mul(2))
+                .is_some_and(|total| total < MAX_TOTAL);
+        if !fits {
+            return Err(Error::InvalidTimings);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The timings don't fit the TCON's registers
+    InvalidTimings,
+    /// The dot clock couldn't be set up
+    Clock(ccu::Error),
+}
+
+impl From<ccu::Error> for Error {
+    fn from(error: ccu::Error) -> Self {
+        Error::Clock(error)
+    }
+}
+
+/// TCON_LCD0 driving a parallel RGB panel
+pub struct Tcon {
+    pins: LcdPins,
+    dot_clock_hz: u32,
+}
+
+impl Tcon {
+    /// Clock TCON_LCD0 from `pll`, which it takes over, and set it up for a
+    /// panel with `timings`, fed from the display engine
+    ///
+    /// On error the pins are handed back.
+    pub fn new(pins: LcdPins, pll: VideoPll, timings: &Timings) -> Result<Self, (Error, LcdPins)> {
+        if let Err(error) = timings.validate() {
+            return Err((error, pins));
+        }
+        let factors = match ccu::set_pixel_clock(pll, timings.dot_clock_hz) {
+            Ok(factors) => factors,
+            Err(error) => return Err((error.into(), pins)),
+        };
+        DpssTop::enable();
+        DpssTop::deassert_reset();
+        TconLcd0::enable();
+        TconLcd0::deassert_reset();
+
+        write_reg(LCD_GCTL, 0);
+        write_reg(LCD_GINT0, 0);
+        write_reg(LCD_GINT1, 0);
+        write_reg(LCD_FRM_CTL, 0);
+        write_reg(LCD_IO_TRI, IO_TRI_ALL);
+
+        // Parallel sync + DE mode, with the DE as the source. The start
+        // delay is as long as the vertical blanking allows.
+        let delay = (timings.v_total() - timings.height).min(30);
+        write_reg(LCD_CTL, delay << CTL_START_DLY_SHIFT);
+        write_reg(LCD_HV_IF, 0);
+        write_reg(LCD_DCLK, DCLK_EN | factors.dclk_div);
+
+        // Sizes are programmed less one; the back porches count from the
+        // start of the sync pulse, and the vertical total is in half lines.
+        write_reg(
+            LCD_BASIC0,
+            ((timings.width - 1) << 16) | (timings.height - 1),
+        );
+        let h_bp = timings.h_sync + timings.h_back_porch;
+        write_reg(LCD_BASIC1, ((timings.h_total() - 1) << 16) | (h_bp - 1));
+        let v_bp = timings.v_sync + timings.v_back_porch;
+        write_reg(LCD_BASIC2, ((timings.v_total() * 2) << 16) | (v_bp - 1));
+        write_reg(
+            LCD_BASIC3,
+            ((timings.h_sync - 1) << 16) | (timings.v_sync - 1),
+        );
+
+        let mut io_pol = 0;
+        if timings.hsync == Polarity::ActiveHigh {
+            io_pol |= IO_POL_HSYNC_HIGH;
+        }
+        if timings.vsync == Polarity::ActiveHigh {
+            io_pol |= IO_POL_VSYNC_HIGH;
+        }
+        if timings.de == Polarity::ActiveLow {
+            io_pol |= IO_POL_DE_LOW;
+        }
+        if !timings.sample_on_falling {
+            io_pol |= IO_POL_DCLK_NEGEDGE;
+        }
+        write_reg(LCD_IO_POL, io_pol);
+
+        Ok(Self {
+            pins,
+            dot_clock_hz: factors.hz(),
+        })
+    }
+
+    /// The dot clock actually achieved
+    pub fn dot_clock_hz(&self) -> u32 {
+        self.dot_clock_hz
+    }
+
+    /// Drive the panel
+    pub fn start(&mut self) {
+        write_reg(LCD_CTL, read_reg(LCD_CTL) | CTL_EN);
+        write_reg(LCD_IO_TRI, 0);
+        write_reg(LCD_GCTL, GCTL_EN);
+    }
+
+    /// Stop the timing generator and float the pins
+    pub fn stop(&mut self) {
+        write_reg(LCD_GCTL, 0);
+        write_reg(LCD_IO_TRI, IO_TRI_ALL);
+        write_reg(LCD_CTL, read_reg(LCD_CTL) & !CTL_EN);
+    }
+
+    pub fn is_running(&self) -> bool {
+        read_reg(LCD_GCTL) & GCTL_EN != 0
+    }
+
+    /// Stop and gate the TCON, returning its pins
+    pub fn free(mut self) -> LcdPins {
+        self.stop();
+        TconLcd0::disable();
+        DpssTop::disable();
+        self.pins
+    }
+}
+
+fn read_reg(offset: usize) -> u32 {
+    unsafe { read_volatile((TCON_LCD0::PTR as usize + offset) as *const u32) }
+}
+
+fn write_reg(offset: usize, value: u32) {
+    unsafe { write_volatile((TCON_LCD0::PTR as usize + offset) as *mut u32, value) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panel_timings_fit() {
+        assert_eq!(Timings::LCD_480X272.validate(), Ok(()));
+    }
+
+    #[test]
+    fn vertical_total_limit() {
+        // Twice the vertical total has to stay below the field's limit.
+        let mut timings = Timings::LCD_480X272;
+        timings.v_front_porch = 3;
+        timings.v_sync = 1;
+        timings.v_back_porch = 1;
+        timings.height = (MAX_TOTAL - 1) / 2 - 5;
+        assert_eq!(timings.v_total() * 2, 8190);
+        assert_eq!(timings.validate(), Ok(()));
+        timings.v_front_porch += 1;
+        assert_eq!(timings.v_total() * 2, MAX_TOTAL);
+        assert_eq!(timings.validate(), Err(Error::InvalidTimings));
+    }
+
+    #[test]
+    fn overflowing_sums() {
+        let mut timings = Timings::LCD_480X272;
+        timings.h_front_porch = u32::MAX - 10;
+        assert_eq!(timings.validate(), Err(Error::InvalidTimings));
+
+        let mut timings = Timings::LCD_480X272;
+        timings.v_back_porch = u32::MAX;
+        assert_eq!(timings.validate(), Err(Error::InvalidTimings));
+
+        // A vertical total whose double overflows
+        let mut timings = Timings::LCD_480X272;
+        timings.v_front_porch = u32::MAX / 2;
+        assert_eq!(timings.validate(), Err(Error::InvalidTimings));
+    }
+}
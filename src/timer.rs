@@ -2,6 +2,7 @@ pub use d1_pac::timer::tmr_ctrl::{
     TMR_CLK_PRES_A as TimerPrescaler, TMR_CLK_SRC_A as TimerSource, TMR_MODE_A as TimerMode,
 };
 use d1_pac::TIMER;
+use log::{debug, trace};
 
 pub struct Timers {
     pub timer0: Timer0,
@@ -152,6 +153,7 @@ pub trait Timer: sealed::TimerSealed {
 
     #[inline]
     fn start_counter(&mut self, interval: u32) {
+        trace!("start counter, interval {}", interval);
         self.interval().write(|w| unsafe {
             w.bits(interval);
             w
@@ -183,6 +185,7 @@ pub trait Timer: sealed::TimerSealed {
 
 impl Timers {
     pub fn new(periph: TIMER) -> Self {
+        debug!("disabling timer interrupts");
         // 1. Configure the timer parameters clock source, prescale factor, and timing mode by writing **TMRn_CTRL_REG**. There is no sequence requirement of configuring the parameters.
         // 2. Write the interval value.
         //     * Write TMRn_INTV_VALUE_REG to configure the interval value for the timer.
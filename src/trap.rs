@@ -0,0 +1,283 @@
+//! Exception handling
+//!
+//! riscv-rt's trap entry only saves the caller-saved registers, and sends
+//! every exception to a handler that spins forever. This replaces the trap
+//! entry (`_start_trap`) with one that passes interrupts through to
+//! riscv-rt unchanged, but saves the full register file for exceptions and
+//! hands it to `exception_handler`.
+//!
+//! Exceptions are first offered to the hook installed with
+//! [`set_fault_hook`]; if there is none, or it declines, a report is
+//! printed on UART0 and the exception is turned into a panic.
+
+use core::{
+    cell::Cell,
+    fmt::{self, Write},
+};
+
+use riscv::{
+    interrupt::Mutex,
+    register::{
+        mcause::{self, Exception, Trap},
+        mtval,
+    },
+};
+
+use crate::disasm::{self, Access, Disasm, REG_NAMES};
+
+/// Registers saved on exception entry
+///
+/// `regs[0]` is always zero and `regs[2]` is `sp` as it was before the trap.
+/// Changes to the frame, including `mepc`, take effect when the hook
+/// returns.
+#[repr(C)]
+pub struct TrapFrame {
+    pub regs: [usize; 32],
+    pub mepc: usize,
+    _pad: usize,
+}
+
+/// Handler offered every exception before it is reported
+///
+/// Returns `true` if the exception was dealt with and execution should
+/// resume at `frame.mepc`.
+pub type FaultHook = fn(frame: &mut TrapFrame, exception: Exception, mtval: usize) -> bool;
+
+static FAULT_HOOK: Mutex<Cell<Option<FaultHook>>> = Mutex::new(Cell::new(None));
+
+// x5 (t0) is stashed in mscratch while checking mcause; everything else is
+// saved as-is, with slot 2 holding the pre-trap sp.
+#[cfg(target_arch = "riscv64")]
+core::arch::global_asm!(
+    r#"
+    .section .trap, "ax"
+    .global _start_trap
+    .align 2
+_start_trap:
+    csrw mscratch, t0
+    csrr t0, mcause
+    bltz t0, 1f
+    csrr t0, mscratch
+
+    addi sp, sp, -34 * 8
+    sd x1, 1 * 8(sp)
+    sd x3, 3 * 8(sp)
+    sd x4, 4 * 8(sp)
+    sd x5, 5 * 8(sp)
+    sd x6, 6 * 8(sp)
+    sd x7, 7 * 8(sp)
+    sd x8, 8 * 8(sp)
+    sd x9, 9 * 8(sp)
+    sd x10, 10 * 8(sp)
+    sd x11, 11 * 8(sp)
+    sd x12, 12 * 8(sp)
+    sd x13, 13 * 8(sp)
+    sd x14, 14 * 8(sp)
+    sd x15, 15 * 8(sp)
+    sd x16, 16 * 8(sp)
+    sd x17, 17 * 8(sp)
+    sd x18, 18 * 8(sp)
+    sd x19, 19 * 8(sp)
+    sd x20, 20 * 8(sp)
+    sd x21, 21 * 8(sp)
+    sd x22, 22 * 8(sp)
+    sd x23, 23 * 8(sp)
+    sd x24, 24 * 8(sp)
+    sd x25, 25 * 8(sp)
+    sd x26, 26 * 8(sp)
+    sd x27, 27 * 8(sp)
+    sd x28, 28 * 8(sp)
+    sd x29, 29 * 8(sp)
+    sd x30, 30 * 8(sp)
+    sd x31, 31 * 8(sp)
+    addi t0, sp, 34 * 8
+    sd t0, 2 * 8(sp)
+    sd zero, 0 * 8(sp)
+    csrr t0, mepc
+    sd t0, 32 * 8(sp)
+
+    mv a0, sp
+    call d1_exception_handler
+
+    ld t0, 32 * 8(sp)
+    csrw mepc, t0
+    ld x1, 1 * 8(sp)
+    ld x3, 3 * 8(sp)
+    ld x4, 4 * 8(sp)
+    ld x5, 5 * 8(sp)
+    ld x6, 6 * 8(sp)
+    ld x7, 7 * 8(sp)
+    ld x8, 8 * 8(sp)
+    ld x9, 9 * 8(sp)
+    ld x10, 10 * 8(sp)
+    ld x11, 11 * 8(sp)
+    ld x12, 12 * 8(sp)
+    ld x13, 13 * 8(sp)
+    ld x14, 14 * 8(sp)
+    ld x15, 15 * 8(sp)
+    ld x16, 16 * 8(sp)
+    ld x17, 17 * 8(sp)
+    ld x18, 18 * 8(sp)
+    ld x19, 19 * 8(sp)
+    ld x20, 20 * 8(sp)
+    ld x21, 21 * 8(sp)
+    ld x22, 22 * 8(sp)
+    ld x23, 23 * 8(sp)
+    ld x24, 24 * 8(sp)
+    ld x25, 25 * 8(sp)
+    ld x26, 26 * 8(sp)
+    ld x27, 27 * 8(sp)
+    ld x28, 28 * 8(sp)
+    ld x29, 29 * 8(sp)
+    ld x30, 30 * 8(sp)
+    ld x31, 31 * 8(sp)
+    ld x2, 2 * 8(sp)
+    mret
+
+1:
+    csrr t0, mscratch
+    j default_start_trap
+"#
+);
+
+/// Install (or with `None`, remove) the fault hook
+pub fn set_fault_hook(hook: Option<FaultHook>) {
+    riscv::interrupt::free(|cs| FAULT_HOOK.borrow(cs).set(hook));
+}
+
+#[export_name = "d1_exception_handler"]
+extern "C" fn exception_handler(frame: &mut TrapFrame) {
+    let mtval = mtval::read();
+    let exception = match mcause::read().cause() {
+        Trap::Exception(exception) => exception,
+        // Interrupts never get here, see `_start_trap`.
+        Trap::Interrupt(_) => unreachable!(),
+    };
+
+    let hook = riscv::interrupt::free(|cs| FAULT_HOOK.borrow(cs).get());
+    if let Some(hook) = hook {
+        if hook(frame, exception, mtval) {
+            return;
+        }
+    }
+
+    // Opens and sets up UART0 if the fault came before the console did.
+    let mut uart = crate::panic::report_uart();
+    let _ = report(&mut uart, frame, exception, mtval);
+    uart.flush_blocking();
+    panic!("unhandled exception: {:?}", exception);
+}
+
+fn report(
+    w: &mut impl Write,
+    frame: &TrapFrame,
+    exception: Exception,
+    mtval: usize,
+) -> fmt::Result {
+    write!(
+        w,
+        "\r\n*** EXCEPTION: {:?} (mcause {})\r\n",
+        exception,
+        mcause::read().code()
+    )?;
+    write!(
+        w,
+        "    mepc: {:#018x}  mtval: {:#018x}\r\n",
+        frame.mepc, mtval
+    )?;
+
+    // Don't go poking at mepc if fetching from it is what went wrong.
+    let fetchable = !matches!(
+        exception,
+        Exception::InstructionMisaligned
+            | Exception::InstructionFault
+            | Exception::InstructionPageFault
+    );
+    if fetchable {
+        let (insn, len) = unsafe { disasm::fetch(frame.mepc) };
+        let width = len * 2;
+        write!(
+            w,
+            "    insn: {:0width$x}  {}\r\n",
+            insn,
+            Disasm {
+                insn,
+                pc: frame.mepc
+            },
+            width = width,
+        )?;
+    }
+
+    for (row, regs) in frame.regs.chunks(4).enumerate() {
+        w.write_str("   ")?;
+        for (col, value) in regs.iter().enumerate() {
+            write!(w, " {:>4}: {:016x}", REG_NAMES[row * 4 + col], value)?;
+        }
+        w.write_str("\r\n")?;
+    }
+    Ok(())
+}
+
+/// A [`FaultHook`] that emulates misaligned integer loads and stores
+///
+/// The access is performed a byte at a time and execution resumes after the
+/// faulting instruction. Any other exception is declined.
+pub fn emulate_misaligned(frame: &mut TrapFrame, exception: Exception, _mtval: usize) -> bool {
+    if !matches!(
+        exception,
+        Exception::LoadMisaligned | Exception::StoreMisaligned
+    ) {
+        return false;
+    }
+
+    let (insn, len) = unsafe { disasm::fetch(frame.mepc) };
+    let insn = match len {
+        2 => match disasm::expand_compressed(insn as u16) {
+            Some(insn) => insn,
+            None => return false,
+        },
+        _ => insn,
+    };
+
+    match disasm::decode_access(insn) {
+        Some(Access::Load {
+            rd,
+            rs1,
+            offset,
+            width,
+            signed,
+        }) => {
+            let addr = frame.regs[rs1].wrapping_add(offset as usize);
+            let mut value = 0u64;
+            for i in 0..width {
+                let byte = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+                value |= (byte as u64) << (8 * i);
+            }
+            if signed && width < 8 {
+                let shift = 64 - 8 * width as u32;
+                value = (((value << shift) as i64) >> shift) as u64;
+            }
+            if rd != 0 {
+                frame.regs[rd] = value as usize;
+            }
+        }
+        Some(Access::Store {
+            rs1,
+            rs2,
+            offset,
+            width,
+        }) => {
+            let addr = frame.regs[rs1].wrapping_add(offset as usize);
+            let value = frame.regs[rs2];
+            for i in 0..width {
+                unsafe {
+                    core::ptr::write_volatile((addr + i) as *mut u8, (value >> (8 * i)) as u8)
+                };
+            }
+        }
+        None => return false,
+    }
+
+    frame.mepc += len;
+    true
+}
@@ -0,0 +1,914 @@
+use core::{
+    cell::RefCell,
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::{Poll, Waker},
+};
+
+use core::ptr::{read_volatile, write_volatile};
+use d1_pac::{uart::RegisterBlock, UART0, UART1, UART2, UART3, UART4, UART5};
+use riscv::interrupt::Mutex;
+
+use crate::ccu::{self, Clock, Enable, Reset};
+
+/// Rates tried by [`Uart::autobaud`], most likely first
+///
+/// Not all of them can be generated from every APB1 frequency; those that
+/// can't are skipped.
+pub const STANDARD_BAUDRATES: &[u32] = &[
+    115_200, 1_500_000, 921_600, 460_800, 230_400, 57_600, 38_400, 19_200, 9_600,
+];
+
+/// How long [`Uart::autobaud`] listens at each rate
+pub const AUTOBAUD_WINDOW_US: u64 = 250_000;
+
+/// Largest baud rate error, in percent, that a receiver tolerates
+pub const MAX_BAUDRATE_ERROR_PERCENT: u32 = 2;
+
+/// Frequency of the `time` CSR, used for autobaud timing
+const TIME_HZ: u64 = 24_000_000;
+
+// RS-485 registers and mode bits, from the D1 user manual section 9.2.
+const UART_RS485_CTL: usize = 0x0C0;
+const UART_RS485_ADDR_MATCH: usize = 0x0C4;
+const UART_BUS_IDLE_CHK: usize = 0x0C8;
+const UART_TX_DLY: usize = 0x0CC;
+
+const RS485_CTL_SLAVE_MODE_AAD: u32 = 1 << 0;
+const RS485_CTL_RX_AF_ADDR: u32 = 1 << 2;
+const RS485_CTL_RX_BF_ADDR: u32 = 1 << 3;
+const RS485_CTL_ADDR_DET_F: u32 = 1 << 5;
+
+const BUS_IDLE_CHK_EN: u32 = 1 << 7;
+const BUS_IDLE_CHK_ADJ_TIME: u32 = 0x3F;
+
+const MCR_FUNCTION: u32 = 0b11 << 6;
+const MCR_FUNCTION_RS485: u32 = 0b10 << 6;
+
+const LCR_PEN: u32 = 1 << 3;
+const LCR_EPS_9BIT_ONE: u32 = 0b10 << 4;
+const LCR_EPS_9BIT_ZERO: u32 = 0b11 << 4;
+const LCR_PARITY: u32 = LCR_PEN | (0b11 << 4);
+
+/// Receive error reported by the line status register
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A received byte was lost because the RX FIFO was full
+    Overrun,
+    /// A received byte had the wrong parity
+    Parity,
+    /// A received byte had no valid stop bit
+    Framing,
+    /// The RX line was held low for longer than a full character
+    Break,
+}
+
+/// Returned by [`Uart::autobaud`] when no rate matched in time
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AutobaudTimeout;
+
+/// UART interface, owning one of the [`UART0`](d1_pac::UART0)..[`UART5`](d1_pac::UART5) peripherals
+///
+/// The pin mux must be configured before calling [`Uart::new`], which opens
+/// the UART's bus clock gate and takes it out of reset.
+pub struct Uart<U: Instance> {
+    uart: U,
+}
+
+/// Hardware flow control mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FlowControl {
+    /// No flow control; RTS and CTS are ignored
+    None,
+    /// Transmission pauses while CTS is deasserted, RTS is not driven
+    Cts,
+    /// As `Cts`, plus RTS is deasserted while the RX FIFO is at its trigger level
+    RtsCts,
+}
+
+/// RS-485 receive mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rs485Mode {
+    /// Normal multidrop mode: every byte is received, addresses and data alike
+    Normal,
+    /// Auto address detection: only receive data following a matching address
+    AutoAddress {
+        /// Address to match against incoming 9th-bit-set bytes
+        address: u8,
+        /// Also receive data bytes seen before the first address match
+        receive_before_match: bool,
+    },
+}
+
+/// RS-485 configuration for [`Uart::enable_rs485`]
+#[derive(Debug, Copy, Clone)]
+pub struct Rs485Config {
+    pub mode: Rs485Mode,
+    /// Delay between the end of the last stop bit and releasing the
+    /// direction pin, in bit times
+    pub tx_delay: u8,
+    /// Wait for the bus to be idle for this many bit times (0..=63) before
+    /// transmitting, or `None` to transmit immediately
+    pub bus_idle_check: Option<u8>,
+}
+
+impl Default for Rs485Config {
+    fn default() -> Self {
+        Self {
+            mode: Rs485Mode::Normal,
+            tx_delay: 0,
+            bus_idle_check: None,
+        }
+    }
+}
+
+/// Wakers for tasks blocked in the `embedded-io-async` implementations
+pub struct State {
+    rx: Mutex<RefCell<Option<Waker>>>,
+    tx: Mutex<RefCell<Option<Waker>>>,
+    /// Rate to keep when APB1 changes
+    baudrate: AtomicU32,
+    /// Whether the APB1 listener is registered
+    subscribed: AtomicBool,
+    /// Receive error held back by a read that returned data first
+    pending: Mutex<RefCell<Option<Error>>>,
+}
+
+mod sealed {
+    use super::*;
+
+    pub trait Instance {
+        fn regs() -> &'static RegisterBlock;
+        fn state() -> &'static State;
+        unsafe fn steal() -> Self;
+    }
+
+    macro_rules! impl_instance {
+        ($($uart:ident),+) => {
+            $(
+                impl Instance for $uart {
+                    #[inline(always)]
+                    fn regs() -> &'static RegisterBlock {
+                        unsafe { &*$uart::PTR }
+                    }
+
+                    #[inline(always)]
+                    fn state() -> &'static State {
+                        static STATE: State = State::new();
+                        &STATE
+                    }
+
+                    #[inline(always)]
+                    unsafe fn steal() -> Self {
+                        d1_pac::Peripherals::steal().$uart
+                    }
+                }
+
+                impl super::Instance for $uart {}
+            )+
+        };
+    }
+
+    impl_instance!(UART0, UART1, UART2, UART3, UART4, UART5);
+}
+
+/// A UART peripheral usable by [`Uart`]
+pub trait Instance: sealed::Instance + Enable + Reset {}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            rx: Mutex::new(RefCell::new(None)),
+            tx: Mutex::new(RefCell::new(None)),
+            baudrate: AtomicU32::new(0),
+            subscribed: AtomicBool::new(false),
+            pending: Mutex::new(RefCell::new(None)),
+        }
+    }
+}
+
+fn register(slot: &Mutex<RefCell<Option<Waker>>>, waker: &Waker) {
+    riscv::interrupt::free(|cs| {
+        let mut slot = slot.borrow(cs).borrow_mut();
+        match slot.as_ref() {
+            Some(w) if w.will_wake(waker) => {}
+            _ => *slot = Some(waker.clone()),
+        }
+    });
+}
+
+fn wake(slot: &Mutex<RefCell<Option<Waker>>>) {
+    if let Some(waker) = riscv::interrupt::free(|cs| slot.borrow(cs).borrow_mut().take()) {
+        waker.wake();
+    }
+}
+
+/// Divisor latch value for `baudrate` from an APB1 clock of `apb1` Hz
+///
+/// Rates out of reach are clamped to the fastest or slowest divisor; a rate
+/// of 0 gets the slowest.
+fn divisor(apb1: u32, baudrate: u32) -> u32 {
+    let (apb1, baudrate) = (apb1 as u64, baudrate as u64);
+    // Round to the nearest divisor rather than truncating, so that
+    // e.g. 115200 baud from 24 MHz picks 13 (0.16% error) rather than 12.
+    (apb1 + 8 * baudrate)
+        .checked_div(16 * baudrate)
+        .map_or(0xFFFF, |divisor| divisor.clamp(1, 0xFFFF)) as u32
+}
+
+/// Whether `baudrate` can be generated from an APB1 clock of `apb1` Hz to
+/// within [`MAX_BAUDRATE_ERROR_PERCENT`]
+pub fn is_achievable(apb1: u32, baudrate: u32) -> bool {
+    if baudrate == 0 {
+        return false;
+    }
+    let actual = apb1 / (16 * divisor(apb1, baudrate));
+    actual.abs_diff(baudrate) as u64 * 100 <= baudrate as u64 * MAX_BAUDRATE_ERROR_PERCENT as u64
+}
+
+/// Set the divisor latch for `baudrate` from an APB1 clock of `apb1` Hz
+fn set_divisor<U: Instance>(apb1: u32, baudrate: u32) {
+    let regs = U::regs();
+    let divisor = divisor(apb1, baudrate);
+
+    regs.halt.write(|w| w.halt_tx().enabled());
+    regs.lcr.modify(|_r, w| w.dlab().divisor_latch());
+    regs.dll().write(|w| unsafe { w.dll().bits(divisor as u8) });
+    regs.dlh()
+        .write(|w| unsafe { w.dlh().bits((divisor >> 8) as u8) });
+    regs.lcr.modify(|_r, w| w.dlab().rx_buffer());
+    regs.halt.write(|w| w.halt_tx().disabled());
+}
+
+/// Keeps the baud rate across APB1 changes made through [`ccu`]
+fn apb1_changed<U: Instance>(apb1: u32) {
+    set_divisor::<U>(apb1, U::state().baudrate.load(Ordering::Relaxed));
+}
+
+impl<U: Instance> Uart<U> {
+    /// Configure the UART for 8n1 at `baudrate` with the FIFOs enabled
+    pub fn new(uart: U, baudrate: u32) -> Self {
+        U::enable();
+        U::deassert_reset();
+        let regs = U::regs();
+        regs.mcr.write(|w| unsafe { w.bits(0) });
+        regs.fcr().write(|w| w.fifoe().set_bit());
+        regs.ier().write(|w| unsafe { w.bits(0) });
+        regs.lcr.write(|w| w.dls().eight());
+        riscv::interrupt::free(|cs| *U::state().pending.borrow(cs).borrow_mut() = None);
+
+        let mut this = Self { uart };
+        this.set_baudrate(baudrate);
+        // If the table is full, callers changing APB1 have to set the baud
+        // rate again themselves.
+        if !U::state().subscribed.swap(true, Ordering::Relaxed) {
+            let _ = ccu::subscribe(Clock::Apb1, apb1_changed::<U>);
+        }
+        this
+    }
+
+    /// Obtain a `Uart` for an already-configured UART, e.g. from a panic handler
+    ///
+    /// # Safety
+    ///
+    /// This aliases any other `Uart` for the same peripheral; interleaved
+    /// output is the least of the possible consequences.
+    pub unsafe fn steal() -> Self {
+        Self {
+            uart: <U as sealed::Instance>::steal(),
+        }
+    }
+
+    /// Let queued output drain, close the UART's bus clock gate and release
+    /// the underlying PAC peripheral
+    pub fn free(mut self) -> U {
+        self.flush_blocking();
+        if U::state().subscribed.swap(false, Ordering::Relaxed) {
+            ccu::unsubscribe(Clock::Apb1, apb1_changed::<U>);
+        }
+        U::disable();
+        self.uart
+    }
+
+    /// Change the baud rate, dividing down APB1 as the CCU currently has it
+    ///
+    /// The rate is kept if APB1 is later changed through [`ccu::set_bus`].
+    /// Rates APB1 can't reach get the nearest divisor; see [`is_achievable`].
+    pub fn set_baudrate(&mut self, baudrate: u32) {
+        U::state().baudrate.store(baudrate, Ordering::Relaxed);
+        set_divisor::<U>(ccu::frequency(Clock::Apb1), baudrate);
+    }
+
+    /// Configure hardware flow control on the RTS/CTS pins
+    ///
+    /// The RTS and CTS pins must be muxed to this UART for this to have any
+    /// effect.
+    pub fn set_flow_control(&mut self, flow: FlowControl) {
+        let (afce, rts) = match flow {
+            FlowControl::None => (false, false),
+            FlowControl::Cts => (true, false),
+            FlowControl::RtsCts => (true, true),
+        };
+        U::regs()
+            .mcr
+            .modify(|_r, w| w.afce().bit(afce).rts().bit(rts));
+    }
+
+    /// Internally connect TX to RX for self-test
+    ///
+    /// While enabled the TX pin idles high and nothing from the RX pin is
+    /// received, so this can be used without disconnecting the UART.
+    pub fn set_loopback(&mut self, enabled: bool) {
+        U::regs().mcr.modify(|_r, w| w.loop_().bit(enabled));
+    }
+
+    /// Send `data` in loopback mode and check that it is received unchanged
+    ///
+    /// Returns `false` on a mismatch or receive error. Anything already in the
+    /// RX FIFO is discarded first.
+    pub fn self_test(&mut self, data: &[u8]) -> bool {
+        self.flush_blocking();
+        self.set_loopback(true);
+        while self.is_rx_ready() {
+            let _ = self.try_read_byte();
+        }
+
+        let mut ok = true;
+        for &byte in data {
+            self.write_byte(byte);
+            match nb::block!(self.try_read_byte()) {
+                Ok(b) if b == byte => {}
+                _ => {
+                    ok = false;
+                    break;
+                }
+            }
+        }
+
+        self.flush_blocking();
+        self.set_loopback(false);
+        ok
+    }
+
+    /// Find the host's baud rate by listening at each of `rates` in turn
+    ///
+    /// The host should keep sending `\r` or `U` (e.g. by pressing Enter):
+    /// at the wrong rate these come in with framing errors or as different
+    /// bytes. Each rate gets [`AUTOBAUD_WINDOW_US`], cycling through `rates`
+    /// until one receives a clean `\r` or `U`, which is left configured and
+    /// returned, or `timeout_us` runs out. Rates that APB1 can't generate
+    /// (see [`is_achievable`]) are skipped.
+    ///
+    /// If the RX pin can be sampled as a GPIO, [`Uart::measure_baudrate`] is
+    /// faster and also finds non-standard rates.
+    pub fn autobaud(&mut self, rates: &[u32], timeout_us: u64) -> Result<u32, AutobaudTimeout> {
+        let start = time_ticks();
+        let timeout = timeout_us * TIME_HZ / 1_000_000;
+        let window = AUTOBAUD_WINDOW_US * TIME_HZ / 1_000_000;
+        let apb1 = ccu::frequency(Clock::Apb1);
+        let rates = rates
+            .iter()
+            .copied()
+            .filter(|&rate| is_achievable(apb1, rate));
+
+        for rate in rates.cycle() {
+            if time_ticks() - start > timeout {
+                break;
+            }
+            self.set_baudrate(rate);
+            while self.is_rx_ready() {
+                let _ = self.try_read_byte();
+            }
+
+            let listen = time_ticks();
+            while time_ticks() - listen < window {
+                match self.try_read_byte() {
+                    Ok(b'\r' | b'U') => return Ok(rate),
+                    // Garbage, try the next rate
+                    Ok(_) | Err(nb::Error::Other(_)) => break,
+                    Err(nb::Error::WouldBlock) => {}
+                }
+            }
+        }
+        Err(AutobaudTimeout)
+    }
+
+    /// Measure the baud rate of the next character on an RX line
+    ///
+    /// `rx_level` samples the line (`true` when high), e.g. by reading the RX
+    /// pin with it temporarily muxed as a GPIO input. The shortest low or high
+    /// run within the character is taken as one bit time, so the character
+    /// needs an isolated 0 or 1 bit: `U` and `\r` both work. The result is
+    /// rounded to the nearest of [`STANDARD_BAUDRATES`] if within 5%.
+    ///
+    /// The rate is left configured and returned. Returns `None` if no
+    /// character starts within `timeout_us`, or APB1 can't generate the rate
+    /// measured.
+    pub fn measure_baudrate(
+        &mut self,
+        rx_level: impl FnMut() -> bool,
+        timeout_us: u64,
+    ) -> Option<u32> {
+        let rate = measure_bit_time(rx_level, timeout_us).and_then(baudrate_from_ticks)?;
+        if !is_achievable(ccu::frequency(Clock::Apb1), rate) {
+            return None;
+        }
+        self.set_baudrate(rate);
+        Some(rate)
+    }
+
+    /// Switch the UART into RS-485 mode
+    ///
+    /// In RS-485 mode the RTS pin becomes the transceiver direction pin,
+    /// driven active while a character is being transmitted, so RTS must be
+    /// muxed to this UART and wired to the transceiver's DE/RE# pins.
+    ///
+    /// The parity bit is used as the 9th (address) bit: data bytes are sent
+    /// with it clear, [`Uart::send_rs485_address`] sends with it set.
+    pub fn enable_rs485(&mut self, config: Rs485Config) {
+        let regs = U::regs();
+        self.flush_blocking();
+
+        let mut ctl = RS485_CTL_RX_AF_ADDR;
+        if let Rs485Mode::AutoAddress {
+            address,
+            receive_before_match,
+        } = config.mode
+        {
+            ctl |= RS485_CTL_SLAVE_MODE_AAD;
+            if receive_before_match {
+                ctl |= RS485_CTL_RX_BF_ADDR;
+            }
+            unsafe { write_volatile(Self::reg(UART_RS485_ADDR_MATCH), address as u32) };
+        }
+
+        let idle = match config.bus_idle_check {
+            Some(adjust) => BUS_IDLE_CHK_EN | (adjust as u32 & BUS_IDLE_CHK_ADJ_TIME),
+            None => 0,
+        };
+
+        unsafe {
+            write_volatile(Self::reg(UART_RS485_CTL), ctl);
+            write_volatile(Self::reg(UART_BUS_IDLE_CHK), idle);
+            write_volatile(Self::reg(UART_TX_DLY), config.tx_delay as u32);
+        }
+
+        self.set_parity_bit(false);
+        regs.mcr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !MCR_FUNCTION) | MCR_FUNCTION_RS485) });
+    }
+
+    /// Return the UART to normal (RS-232 style) mode
+    pub fn disable_rs485(&mut self) {
+        let regs = U::regs();
+        self.flush_blocking();
+        regs.mcr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !MCR_FUNCTION) });
+        regs.lcr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !LCR_PARITY) });
+    }
+
+    /// Send an address byte (9th bit set) on an RS-485 bus
+    ///
+    /// Bytes queued afterwards go out as data (9th bit clear).
+    pub fn send_rs485_address(&mut self, address: u8) {
+        // The 9th bit applies to whatever is in the shift register, so
+        // drain everything queued before and after the address byte.
+        self.flush_blocking();
+        self.set_parity_bit(true);
+        self.write_byte(address);
+        self.flush_blocking();
+        self.set_parity_bit(false);
+    }
+
+    /// Check and clear the "own address received" flag in auto address mode
+    pub fn take_rs485_address_match(&mut self) -> bool {
+        let reg = Self::reg(UART_RS485_CTL);
+        unsafe {
+            let ctl = read_volatile(reg);
+            if ctl & RS485_CTL_ADDR_DET_F != 0 {
+                // Write-1-to-clear
+                write_volatile(reg, ctl);
+                true
+            } else {
+                false
+            }
+        }
+    }
+
+    fn set_parity_bit(&mut self, set: bool) {
+        let eps = if set {
+            LCR_EPS_9BIT_ONE
+        } else {
+            LCR_EPS_9BIT_ZERO
+        };
+        U::regs()
+            .lcr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !LCR_PARITY) | LCR_PEN | eps) });
+    }
+
+    #[inline(always)]
+    fn reg(offset: usize) -> *mut u32 {
+        (U::regs() as *const RegisterBlock as usize + offset) as *mut u32
+    }
+
+    /// Whether at least one byte is waiting in the RX FIFO
+    #[inline]
+    pub fn is_rx_ready(&self) -> bool {
+        U::regs().usr.read().rfne().bit_is_set()
+    }
+
+    /// Whether there is room for at least one byte in the TX FIFO
+    #[inline]
+    pub fn is_tx_ready(&self) -> bool {
+        U::regs().usr.read().tfnf().bit_is_set()
+    }
+
+    /// Whether the TX FIFO and shift register are both empty
+    #[inline]
+    pub fn is_tx_idle(&self) -> bool {
+        U::regs().lsr.read().temt().bit_is_set()
+    }
+
+    /// Read a single byte, if one is available
+    pub fn try_read_byte(&mut self) -> nb::Result<u8, Error> {
+        let regs = U::regs();
+        let lsr = regs.lsr.read();
+        // Errors are latched until LSR is read, and reported alongside the
+        // byte at the head of the FIFO, so drop that byte too.
+        let err = if lsr.oe().bit_is_set() {
+            Some(Error::Overrun)
+        } else if lsr.bi().bit_is_set() {
+            Some(Error::Break)
+        } else if lsr.fe().bit_is_set() {
+            Some(Error::Framing)
+        } else if lsr.pe().bit_is_set() {
+            Some(Error::Parity)
+        } else {
+            None
+        };
+
+        if lsr.dr().bit_is_clear() {
+            return match err {
+                Some(err) => Err(nb::Error::Other(err)),
+                None => Err(nb::Error::WouldBlock),
+            };
+        }
+
+        let byte = regs.rbr().read().rbr().bits();
+        match err {
+            Some(err) => Err(nb::Error::Other(err)),
+            None => Ok(byte),
+        }
+    }
+
+    /// Write a single byte, if there is room in the TX FIFO
+    pub fn try_write_byte(&mut self, byte: u8) -> nb::Result<(), Error> {
+        if !self.is_tx_ready() {
+            return Err(nb::Error::WouldBlock);
+        }
+        U::regs().thr().write(|w| unsafe { w.thr().bits(byte) });
+        Ok(())
+    }
+
+    /// Write a single byte, spinning until there is room in the TX FIFO
+    #[inline]
+    pub fn write_byte(&mut self, byte: u8) {
+        while !self.is_tx_ready() {}
+        U::regs().thr().write(|w| unsafe { w.thr().bits(byte) });
+    }
+
+    /// Spin until every queued byte has left the shift register
+    #[inline]
+    pub fn flush_blocking(&mut self) {
+        while !self.is_tx_idle() {}
+    }
+
+    /// Enable or disable the "received data available" interrupt
+    #[inline]
+    pub fn set_rx_interrupt_en(&self, enabled: bool) {
+        U::regs().ier().modify(|_r, w| w.erbfi().bit(enabled));
+    }
+
+    /// Enable or disable the "transmit holding register empty" interrupt
+    #[inline]
+    pub fn set_tx_interrupt_en(&self, enabled: bool) {
+        U::regs().ier().modify(|_r, w| w.etbei().bit(enabled));
+    }
+
+    /// Service the UART interrupt, waking any task blocked on this UART
+    ///
+    /// Call this from the `MachineExternal` handler when the PLIC claims
+    /// this UART's interrupt. The RX/TX interrupt is disabled again until
+    /// the woken task re-arms it.
+    pub fn on_interrupt() {
+        let regs = U::regs();
+        let state = U::state();
+        // Reading IIR acknowledges THRE, reading USR acknowledges busy-detect.
+        let _ = regs.iir().read();
+        let _ = regs.usr.read();
+
+        let usr = regs.usr.read();
+        if usr.rfne().bit_is_set() {
+            regs.ier().modify(|_r, w| w.erbfi().clear_bit());
+            wake(&state.rx);
+        }
+        if usr.tfnf().bit_is_set() {
+            regs.ier().modify(|_r, w| w.etbei().clear_bit());
+            wake(&state.tx);
+        }
+    }
+
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let pending = &U::state().pending;
+        if let Some(e) = riscv::interrupt::free(|cs| pending.borrow(cs).borrow_mut().take()) {
+            return Err(e);
+        }
+
+        let mut n = 0;
+        while n < buf.len() {
+            match self.try_read_byte() {
+                Ok(byte) => {
+                    buf[n] = byte;
+                    n += 1;
+                }
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(e)) if n == 0 => return Err(e),
+                // Reading LSR cleared the error, so hold on to it and report
+                // it on the next call, after handing out what came before it.
+                Err(nb::Error::Other(e)) => {
+                    riscv::interrupt::free(|cs| *pending.borrow(cs).borrow_mut() = Some(e));
+                    break;
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    fn has_pending_error(&self) -> bool {
+        riscv::interrupt::free(|cs| U::state().pending.borrow(cs).borrow().is_some())
+    }
+
+    fn write_available(&mut self, buf: &[u8]) -> usize {
+        let mut n = 0;
+        for &byte in buf {
+            if self.try_write_byte(byte).is_err() {
+                break;
+            }
+            n += 1;
+        }
+        n
+    }
+}
+
+fn time_ticks() -> u64 {
+    riscv::register::time::read64()
+}
+
+/// Time the shortest run of one level in the next character on `rx_level`
+fn measure_bit_time(mut rx_level: impl FnMut() -> bool, timeout_us: u64) -> Option<u64> {
+    let start = time_ticks();
+    let timeout = timeout_us * TIME_HZ / 1_000_000;
+    let expired = || time_ticks() - start > timeout;
+
+    // Wait for the line to be idle, then for a start bit.
+    while !rx_level() {
+        if expired() {
+            return None;
+        }
+    }
+    while rx_level() {
+        if expired() {
+            return None;
+        }
+    }
+
+    let mut level = false;
+    let mut edge = time_ticks();
+    let mut shortest = u64::MAX;
+    // Start bit, 8 data bits and a stop bit give at most 10 runs; stop at
+    // the first run longer than a character, which is idle line.
+    for _ in 0..10 {
+        let limit = shortest.saturating_mul(10);
+        loop {
+            let now = time_ticks();
+            if rx_level() != level {
+                shortest = shortest.min(now - edge);
+                level = !level;
+                edge = now;
+                break;
+            }
+            if now - edge > limit || expired() {
+                return Some(shortest);
+            }
+        }
+    }
+    Some(shortest)
+}
+
+fn baudrate_from_ticks(bit_ticks: u64) -> Option<u32> {
+    if bit_ticks == 0 || bit_ticks == u64::MAX {
+        return None;
+    }
+    let measured = (TIME_HZ / bit_ticks) as u32;
+    let nearest = STANDARD_BAUDRATES
+        .iter()
+        .copied()
+        .find(|&rate| measured.abs_diff(rate) <= rate / 20);
+    Some(nearest.unwrap_or(measured))
+}
+
+impl<U: Instance> core::fmt::Write for Uart<U> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.as_bytes() {
+            self.write_byte(*byte);
+        }
+        Ok(())
+    }
+}
+
+// embedded-io
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::Overrun => embedded_io::ErrorKind::Other,
+            Error::Parity | Error::Framing | Error::Break => embedded_io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl<U: Instance> embedded_io::ErrorType for Uart<U> {
+    type Error = Error;
+}
+
+impl<U: Instance> embedded_io::Read for Uart<U> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.read_available(buf)? {
+                0 => continue,
+                n => return Ok(n),
+            }
+        }
+    }
+}
+
+impl<U: Instance> embedded_io::ReadReady for Uart<U> {
+    fn read_ready(&mut self) -> Result<bool, Error> {
+        Ok(self.has_pending_error() || self.is_rx_ready())
+    }
+}
+
+impl<U: Instance> embedded_io::Write for Uart<U> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while !self.is_tx_ready() {}
+        Ok(self.write_available(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.flush_blocking();
+        Ok(())
+    }
+}
+
+impl<U: Instance> embedded_io::WriteReady for Uart<U> {
+    fn write_ready(&mut self) -> Result<bool, Error> {
+        Ok(self.is_tx_ready())
+    }
+}
+
+// embedded-io-async
+//
+// These rely on `Uart::on_interrupt` being called from the UART's PLIC
+// interrupt; without it, a pending read or write is never woken.
+
+impl<U: Instance> embedded_io_async::Read for Uart<U> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        poll_fn(|cx| {
+            match self.read_available(buf) {
+                Ok(0) => {}
+                res => return Poll::Ready(res),
+            }
+            register(&U::state().rx, cx.waker());
+            self.set_rx_interrupt_en(true);
+            // Check again in case a byte arrived before the interrupt was enabled.
+            match self.read_available(buf) {
+                Ok(0) => Poll::Pending,
+                res => Poll::Ready(res),
+            }
+        })
+        .await
+    }
+}
+
+impl<U: Instance> embedded_io_async::Write for Uart<U> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        poll_fn(|cx| {
+            match self.write_available(buf) {
+                0 => {}
+                n => return Poll::Ready(Ok(n)),
+            }
+            register(&U::state().tx, cx.waker());
+            self.set_tx_interrupt_en(true);
+            match self.write_available(buf) {
+                0 => Poll::Pending,
+                n => Poll::Ready(Ok(n)),
+            }
+        })
+        .await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        // There is no interrupt for the shift register draining; wait for
+        // the FIFO to empty (which does interrupt), then spin out the
+        // last character.
+        poll_fn(|cx| {
+            if U::regs().usr.read().tfe().bit_is_set() {
+                return Poll::Ready(());
+            }
+            register(&U::state().tx, cx.waker());
+            self.set_tx_interrupt_en(true);
+            Poll::Pending
+        })
+        .await;
+        self.flush_blocking();
+        Ok(())
+    }
+}
+
+// embedded-hal-nb
+
+impl embedded_hal_nb::serial::Error for Error {
+    fn kind(&self) -> embedded_hal_nb::serial::ErrorKind {
+        use embedded_hal_nb::serial::ErrorKind;
+        match self {
+            Error::Overrun => ErrorKind::Overrun,
+            Error::Parity => ErrorKind::Parity,
+            Error::Framing => ErrorKind::FrameFormat,
+            Error::Break => ErrorKind::Other,
+        }
+    }
+}
+
+impl<U: Instance> embedded_hal_nb::serial::ErrorType for Uart<U> {
+    type Error = Error;
+}
+
+impl<U: Instance> embedded_hal_nb::serial::Read<u8> for Uart<U> {
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        self.try_read_byte()
+    }
+}
+
+impl<U: Instance> embedded_hal_nb::serial::Write<u8> for Uart<U> {
+    fn write(&mut self, word: u8) -> nb::Result<(), Error> {
+        self.try_write_byte(word)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Error> {
+        if self.is_tx_idle() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn achievable_from_24mhz() {
+        let apb1 = 24_000_000;
+        for rate in [1_500_000, 115_200, 57_600, 9_600] {
+            assert!(is_achievable(apb1, rate), "{}", rate);
+        }
+        for rate in [921_600, 460_800, 230_400] {
+            assert!(!is_achievable(apb1, rate), "{}", rate);
+        }
+    }
+
+    #[test]
+    fn divisor_limits() {
+        let apb1 = 24_000_000;
+        assert_eq!(divisor(apb1, 115_200), 13);
+        assert_eq!(divisor(apb1, 0), 0xFFFF);
+        assert_eq!(divisor(apb1, 1), 0xFFFF);
+        // 16 times the rate no longer fits in a u32.
+        assert_eq!(divisor(apb1, u32::MAX), 1);
+        assert!(!is_achievable(apb1, u32::MAX));
+    }
+}
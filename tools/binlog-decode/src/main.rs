@@ -0,0 +1,406 @@
+//! Decode binary logs from `d1_playground::binlog`
+//!
+//! ```text
+//! binlog-decode <firmware.elf> [input]
+//! ```
+//!
+//! Reads the byte stream from `input` (a file, or a serial device already
+//! configured with `stty`), or stdin if it is omitted or `-`, and prints
+//! each record as text. Anything that isn't a valid frame, such as plain
+//! `println!` output, is passed through unchanged.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::ExitCode,
+};
+
+use object::{Object, ObjectSection};
+
+const MAGIC: u8 = 0xB1;
+const TRUNCATED: u8 = 0x80;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let (elf, input) = match args.as_slice() {
+        [_, elf] => (elf, None),
+        [_, elf, input] if input != "-" => (elf, Some(input)),
+        [_, elf, _] => (elf, None),
+        _ => {
+            eprintln!("usage: binlog-decode <firmware.elf> [input]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let table = match load_table(elf) {
+        Ok(table) => table,
+        Err(e) => {
+            eprintln!("binlog-decode: {}: {}", elf, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let reader: Box<dyn Read> = match input {
+        Some(path) => match File::open(path) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("binlog-decode: {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(io::stdin()),
+    };
+
+    match run(&table, BufReader::new(reader), &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("binlog-decode: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Contents of the `.binlog` section
+struct Table(Vec<u8>);
+
+fn load_table(path: &str) -> Result<Table, Box<dyn std::error::Error>> {
+    let data = std::fs::read(path)?;
+    let file = object::File::parse(&*data)?;
+    let section = file
+        .section_by_name(".binlog")
+        .ok_or("no .binlog section; was it linked with binlog.x?")?;
+    Ok(Table(section.data()?.to_vec()))
+}
+
+impl Table {
+    /// Look up the module and format string for `id`
+    fn entry(&self, id: usize) -> Option<(&str, &str)> {
+        let mut parts = self.0.get(id..)?.split(|&b| b == 0);
+        let module = std::str::from_utf8(parts.next()?).ok()?;
+        let format = std::str::from_utf8(parts.next()?).ok()?;
+        Some((module, format))
+    }
+}
+
+fn run(table: &Table, mut input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+    let mut chunk = Vec::new();
+    loop {
+        chunk.clear();
+        if input.read_until(0, &mut chunk)? == 0 {
+            return Ok(());
+        }
+        let terminated = chunk.last() == Some(&0);
+        if terminated {
+            chunk.pop();
+        }
+        if chunk.is_empty() {
+            continue;
+        }
+
+        match decode_frame(table, &chunk) {
+            Some(line) => writeln!(out, "{}", line)?,
+            None => out.write_all(&chunk)?,
+        }
+        out.flush()?;
+    }
+}
+
+/// An argument value, as tagged on the wire
+#[derive(Debug)]
+enum Value {
+    Unsigned(u64),
+    /// The value, and its type's size in bytes
+    Signed(i64, u8),
+    Bool(bool),
+    Char(char),
+    F32(f32),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl Cursor<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&b, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(b)
+    }
+
+    fn take(&mut self, n: usize) -> Option<&[u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn leb128(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= ((b & 0x7F) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        Some(match self.byte()? {
+            b'u' => Value::Unsigned(self.leb128()?),
+            b'i' => {
+                let size = self.byte()?;
+                let v = self.leb128()?;
+                Value::Signed((v >> 1) as i64 ^ -((v & 1) as i64), size)
+            }
+            b'b' => Value::Bool(self.byte()? != 0),
+            b'c' => Value::Char(char::from_u32(self.leb128()? as u32)?),
+            b'f' => Value::F32(f32::from_le_bytes(self.take(4)?.try_into().ok()?)),
+            b's' => {
+                let len = self.leb128()? as usize;
+                Value::Str(String::from_utf8_lossy(self.take(len)?).into_owned())
+            }
+            b'x' => {
+                let len = self.leb128()? as usize;
+                Value::Bytes(self.take(len)?.to_vec())
+            }
+            _ => return None,
+        })
+    }
+}
+
+fn decode_frame(table: &Table, chunk: &[u8]) -> Option<String> {
+    let mut frame = vec![0; chunk.len()];
+    let len = cobs::decode(chunk, &mut frame).ok()?;
+    let mut cur = Cursor(&frame[..len]);
+
+    if cur.byte()? != MAGIC {
+        return None;
+    }
+    let level_byte = cur.byte()?;
+    let level = match level_byte & !TRUNCATED {
+        1 => "ERROR",
+        2 => "WARN",
+        3 => "INFO",
+        4 => "DEBUG",
+        5 => "TRACE",
+        _ => return None,
+    };
+    let id = cur.leb128()? as usize;
+    let timestamp = cur.leb128()?;
+    let (module, format) = table.entry(id)?;
+
+    let mut args = Vec::new();
+    while !cur.0.is_empty() {
+        match cur.value() {
+            Some(v) => args.push(v),
+            // A truncated frame may end part-way through an argument.
+            None if level_byte & TRUNCATED != 0 => break,
+            None => return None,
+        }
+    }
+
+    let mut line = format!(
+        "[{:>5}.{:06}] {:<5} {}: ",
+        timestamp / 1_000_000,
+        timestamp % 1_000_000,
+        level,
+        module
+    );
+    render(&mut line, format, &args);
+    if level_byte & TRUNCATED != 0 {
+        line.push_str(" [truncated]");
+    }
+    Some(line)
+}
+
+/// Expand `format` with `args`, supporting a subset of `core::fmt` specs
+fn render(out: &mut String, format: &str, args: &[Value]) {
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut spec = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    spec.push(c);
+                }
+                let spec = spec.split_once(':').map(|(_, s)| s).unwrap_or("");
+                match args.next() {
+                    Some(arg) => format_value(out, spec, arg),
+                    None => out.push_str("<missing>"),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+fn format_value(out: &mut String, spec: &str, value: &Value) {
+    let alternate = spec.starts_with('#');
+    let spec = spec.trim_start_matches('#');
+    let zero = spec.starts_with('0');
+    let digits_end = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let width: usize = spec[..digits_end].parse().unwrap_or(0);
+    let ty = &spec[digits_end..];
+
+    let s = match value {
+        Value::Unsigned(v) => format_int(*v, ty, alternate),
+        Value::Signed(v, _) if ty.is_empty() || ty == "?" => v.to_string(),
+        // Negative values in hex or binary show their two's complement at
+        // the argument's own width, as `core::fmt` does.
+        Value::Signed(v, size) => {
+            let bits = *size as u32 * 8;
+            let mask = if bits >= 64 {
+                u64::MAX
+            } else {
+                (1 << bits) - 1
+            };
+            format_int(*v as u64 & mask, ty, alternate)
+        }
+        Value::Bool(v) => v.to_string(),
+        Value::Char(v) if ty == "?" => format!("{:?}", v),
+        Value::Char(v) => v.to_string(),
+        Value::F32(v) => v.to_string(),
+        Value::Str(v) if ty == "?" => format!("{:?}", v),
+        Value::Str(v) => v.clone(),
+        Value::Bytes(v) => {
+            let mut s = String::from("[");
+            for (i, b) in v.iter().enumerate() {
+                if i > 0 {
+                    s.push_str(", ");
+                }
+                match ty {
+                    "x" | "X" => write!(s, "{:02x}", b).unwrap(),
+                    _ => write!(s, "{}", b).unwrap(),
+                }
+            }
+            s.push(']');
+            s
+        }
+    };
+
+    if s.len() >= width {
+        out.push_str(&s);
+    } else if zero {
+        // Zero padding goes after any prefix.
+        let prefix_len = if s.starts_with("0x") || s.starts_with("0b") || s.starts_with("0o") {
+            2
+        } else {
+            0
+        };
+        out.push_str(&s[..prefix_len]);
+        out.extend(std::iter::repeat_n('0', width - s.len()));
+        out.push_str(&s[prefix_len..]);
+    } else {
+        out.extend(std::iter::repeat_n(' ', width - s.len()));
+        out.push_str(&s);
+    }
+}
+
+fn format_int(v: u64, ty: &str, alternate: bool) -> String {
+    match (ty, alternate) {
+        ("x", false) => format!("{:x}", v),
+        ("x", true) => format!("{:#x}", v),
+        ("X", false) => format!("{:X}", v),
+        ("X", true) => format!("{:#X}", v),
+        ("b", false) => format!("{:b}", v),
+        ("b", true) => format!("{:#b}", v),
+        ("o", false) => format!("{:o}", v),
+        ("o", true) => format!("{:#o}", v),
+        _ => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leb128(out: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let b = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(b);
+                return;
+            }
+            out.push(b | 0x80);
+        }
+    }
+
+    fn signed(out: &mut Vec<u8>, v: i64, size: u8) {
+        out.extend([b'i', size]);
+        leb128(out, ((v << 1) ^ (v >> 63)) as u64);
+    }
+
+    /// A COBS frame with a zero on either side, as the firmware sends it
+    fn frame(record: &[u8]) -> Vec<u8> {
+        let mut out = vec![0];
+        out.extend(cobs::encode_vec(record));
+        out.push(0);
+        out
+    }
+
+    #[test]
+    fn decode_stream() {
+        let table =
+            Table(b"app::clk\0PLL at {} MHz\0app\0n={} hex={:#x} b={:#04x} u={:x}\0".to_vec());
+        let second = "app::clk\0PLL at {} MHz\0".len() as u64;
+
+        let mut first = vec![MAGIC, 3, 0];
+        leb128(&mut first, 1_500_000);
+        first.push(b'u');
+        leb128(&mut first, 1008);
+
+        let mut record = vec![MAGIC, 2];
+        leb128(&mut record, second);
+        leb128(&mut record, 42);
+        signed(&mut record, -300, 4);
+        signed(&mut record, -1, 4);
+        signed(&mut record, -2, 1);
+        record.push(b'u');
+        leb128(&mut record, 0xDEAD_BEEF);
+
+        let mut input = b"plain text\n".to_vec();
+        input.extend(frame(&first));
+        input.extend(frame(&record));
+
+        let mut out = Vec::new();
+        run(&table, &input[..], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "plain text\n\
+             [    1.500000] INFO  app::clk: PLL at 1008 MHz\n\
+             [    0.000042] WARN  app: n=-300 hex=0xffffffff b=0xfe u=deadbeef\n"
+        );
+    }
+
+    #[test]
+    fn signed_hex_uses_declared_width() {
+        let mut out = String::new();
+        format_value(&mut out, "x", &Value::Signed(-1, 2));
+        format_value(&mut out, "", &Value::Signed(-1, 8));
+        assert_eq!(out, "ffff-1");
+        let mut out = String::new();
+        format_value(&mut out, "X", &Value::Signed(i64::MIN, 8));
+        assert_eq!(out, "8000000000000000");
+    }
+}
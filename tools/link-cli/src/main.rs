@@ -0,0 +1,101 @@
+//! Talk to d1-playground over the framed host link
+//!
+//! ```text
+//! link-cli <device> ping
+//! link-cli <device> read <addr> <len>
+//! link-cli <device> write <addr> <hex bytes>
+//! link-cli <device> call <method> [hex args]
+//! link-cli <device> listen
+//! ```
+//!
+//! `device` is a serial port already configured with `stty`, or anything
+//! else that can be opened for reading and writing, such as a FIFO.
+
+use std::{env, fs::OpenOptions, process::ExitCode};
+
+use d1_link::host::{Client, Event};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: link-cli <device> <ping|read|write|call|listen> [args...]");
+        return ExitCode::FAILURE;
+    }
+
+    let io = match OpenOptions::new().read(true).write(true).open(&args[1]) {
+        Ok(io) => io,
+        Err(e) => {
+            eprintln!("link-cli: {}: {}", args[1], e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut client = Client::new(io);
+
+    match run(&mut client, &args[2], &args[3..]) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("link-cli: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn run(client: &mut Client<std::fs::File>, cmd: &str, args: &[String]) -> Result<()> {
+    match (cmd, args) {
+        ("ping", []) => {
+            let uptime = client.ping()?;
+            println!("pong, up {}.{:06}s", uptime / 1_000_000, uptime % 1_000_000);
+        }
+        ("read", [addr, len]) => {
+            let addr = parse_u64(addr)?;
+            let data = client.read_mem(addr, parse_u64(len)? as usize)?;
+            for (i, line) in data.chunks(16).enumerate() {
+                print!("{:08x}:", addr + i as u64 * 16);
+                for b in line {
+                    print!(" {:02x}", b);
+                }
+                println!();
+            }
+        }
+        ("write", [addr, data]) => client.write_mem(parse_u64(addr)?, &parse_hex(data)?)?,
+        ("call", [method, rest @ ..]) if rest.len() <= 1 => {
+            let args = match rest {
+                [hex] => parse_hex(hex)?,
+                _ => Vec::new(),
+            };
+            let ret = client.call(parse_u64(method)? as u16, &args)?;
+            println!("{}", to_hex(&ret));
+        }
+        ("listen", []) => loop {
+            match client.next_event()? {
+                Event::Log(log) => println!("{}", log),
+                Event::Data(data) => println!("data: {}", to_hex(&data)),
+            }
+        },
+        _ => return Err(format!("bad command or arguments: {} {:?}", cmd, args).into()),
+    }
+    Ok(())
+}
+
+fn parse_u64(s: &str) -> Result<u64> {
+    Ok(match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16)?,
+        None => s.parse()?,
+    })
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".into());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&s[i..i + 2], 16)?))
+        .collect()
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}